@@ -5,8 +5,9 @@ mod known_hosts_list;
 mod logs;
 mod pagination;
 pub mod recordings_detail;
+mod recordings_search;
 mod roles;
-mod sessions_detail;
+pub mod sessions_detail;
 pub mod sessions_list;
 mod ssh_keys;
 mod targets;
@@ -19,6 +20,7 @@ pub fn get() -> impl OpenApi {
         sessions_list::Api,
         sessions_detail::Api,
         recordings_detail::Api,
+        recordings_search::Api,
         roles::ListApi,
         roles::DetailApi,
         (targets::ListApi, targets::DetailApi, targets::RolesApi),
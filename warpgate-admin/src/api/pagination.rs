@@ -14,6 +14,37 @@ pub struct PaginationParams {
     pub limit: Option<u64>,
 }
 
+/// Loads one page of `query`'s results, along with the page's offset and the total number of items
+pub async fn fetch_page<E, M, C>(
+    query: Select<E>,
+    params: PaginationParams,
+    db: &'_ C,
+) -> poem::Result<(Vec<M>, u64, u64)>
+where
+    E: EntityTrait<Model = M>,
+    C: ConnectionTrait,
+    M: FromQueryResult + Sized + Send + Sync + 'static,
+{
+    let offset = params.offset.unwrap_or(0);
+    let limit = params.limit.unwrap_or(100);
+
+    let paginator = query.clone().paginate(db, limit);
+
+    let total = paginator
+        .num_items()
+        .await
+        .map_err(poem::error::InternalServerError)?;
+
+    let query = query.offset(offset).limit(limit);
+
+    let items = query
+        .all(db)
+        .await
+        .map_err(poem::error::InternalServerError)?;
+
+    Ok((items, offset, total))
+}
+
 impl<T: ParseFromJSON + ToJSON + Send + Sync> PaginatedResponse<T> {
     pub async fn new<E, M, C, P>(
         query: Select<E>,
@@ -27,28 +58,16 @@ impl<T: ParseFromJSON + ToJSON + Send + Sync> PaginatedResponse<T> {
         M: FromQueryResult + Sized + Send + Sync + 'static,
         P: FnMut(E::Model) -> T,
     {
-        let offset = params.offset.unwrap_or(0);
-        let limit = params.limit.unwrap_or(100);
-
-        let paginator = query.clone().paginate(db, limit);
-
-        let total = paginator
-            .num_items()
-            .await
-            .map_err(poem::error::InternalServerError)?;
-
-        let query = query.offset(offset).limit(limit);
-
-        let items = query
-            .all(db)
-            .await
-            .map_err(poem::error::InternalServerError)?;
-
+        let (items, offset, total) = fetch_page(query, params, db).await?;
         let items = items.into_iter().map(postprocess).collect::<Vec<_>>();
-        Ok(PaginatedResponse {
+        Ok(Self::from_page(items, offset, total))
+    }
+
+    pub fn from_page(items: Vec<T>, offset: u64, total: u64) -> Self {
+        PaginatedResponse {
             items,
             offset,
             total,
-        })
+        }
     }
 }
@@ -16,7 +16,9 @@ use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::Mutex;
 use tracing::*;
 use uuid::Uuid;
-use warpgate_core::recordings::{AsciiCast, SessionRecordings, TerminalRecordingItem};
+use warpgate_core::recordings::{
+    AsciiCast, FileTransferRecordingItem, SessionRecordings, TerminalRecordingItem,
+};
 use warpgate_db_entities::Recording::{self, RecordingKind};
 
 pub struct Api;
@@ -29,6 +31,14 @@ enum GetRecordingResponse {
     NotFound,
 }
 
+#[derive(ApiResponse)]
+enum GetRecordingFileTransfersResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<FileTransferRecordingItem>>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
 #[OpenApi]
 impl Api {
     #[oai(
@@ -53,6 +63,52 @@ impl Api {
             None => Ok(GetRecordingResponse::NotFound),
         }
     }
+
+    #[oai(
+        path = "/recordings/:id/file-transfers",
+        method = "get",
+        operation_id = "get_recording_file_transfers"
+    )]
+    async fn api_get_recording_file_transfers(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+        id: Path<Uuid>,
+    ) -> poem::Result<GetRecordingFileTransfersResponse> {
+        let db = db.lock().await;
+
+        let recording = Recording::Entity::find_by_id(id.0)
+            .one(&*db)
+            .await
+            .map_err(InternalServerError)?;
+
+        let Some(recording) = recording else {
+            return Ok(GetRecordingFileTransfersResponse::NotFound);
+        };
+
+        if recording.kind != RecordingKind::FileTransfer {
+            return Ok(GetRecordingFileTransfersResponse::NotFound);
+        }
+
+        let path = {
+            recordings
+                .lock()
+                .await
+                .path_for(&recording.session_id, &recording.name)
+        };
+
+        let mut items = vec![];
+        let file = File::open(&path).await.map_err(InternalServerError)?;
+        let reader = BufReader::new(file);
+        let mut lines = reader.lines();
+        while let Some(line) = lines.next_line().await.map_err(InternalServerError)? {
+            let item: FileTransferRecordingItem =
+                serde_json::from_str(&line[..]).map_err(InternalServerError)?;
+            items.push(item);
+        }
+
+        Ok(GetRecordingFileTransfersResponse::Ok(Json(items)))
+    }
 }
 
 #[handler]
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::Query;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_core::recordings::{load_transcript, SessionRecordings};
+use warpgate_db_entities::Recording::{self, RecordingKind};
+
+use super::pagination::{fetch_page, PaginatedResponse, PaginationParams};
+
+/// Every recording on a page has its transcript loaded from disk
+const MAX_RECORDINGS_PER_PAGE: u64 = 100;
+const MAX_MATCHES_PER_RECORDING: usize = 100;
+
+pub struct Api;
+
+#[derive(Object)]
+struct TranscriptMatch {
+    /// Offset into the recording in seconds
+    time: f32,
+    text: String,
+}
+
+#[derive(Object)]
+struct RecordingSearchResult {
+    recording_id: Uuid,
+    session_id: Uuid,
+    recording_started: DateTime<Utc>,
+    matches: Vec<TranscriptMatch>,
+}
+
+#[derive(ApiResponse)]
+enum SearchRecordingsResponse {
+    #[oai(status = 200)]
+    Ok(Json<PaginatedResponse<RecordingSearchResult>>),
+}
+
+#[OpenApi]
+impl Api {
+    /// Searches the transcripts of finished terminal recordings. Pages are
+    /// made of recordings, newest first - only those with matches are returned.
+    #[oai(
+        path = "/recordings/search",
+        method = "get",
+        operation_id = "search_recordings"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    async fn api_search_recordings(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+        query: Query<String>,
+        session_id: Query<Option<Uuid>>,
+        started_after: Query<Option<DateTime<Utc>>>,
+        started_before: Query<Option<DateTime<Utc>>>,
+        offset: Query<Option<u64>>,
+        limit: Query<Option<u64>>,
+    ) -> poem::Result<SearchRecordingsResponse> {
+        let needle = query.trim().to_lowercase();
+        if needle.is_empty() {
+            return Ok(SearchRecordingsResponse::Ok(Json(
+                PaginatedResponse::from_page(vec![], 0, 0),
+            )));
+        }
+
+        let mut q = Recording::Entity::find()
+            .filter(Recording::Column::Kind.eq(RecordingKind::Terminal))
+            .filter(Recording::Column::Ended.is_not_null())
+            .order_by_desc(Recording::Column::Started);
+        if let Some(session_id) = *session_id {
+            q = q.filter(Recording::Column::SessionId.eq(session_id));
+        }
+        if let Some(started_after) = *started_after {
+            q = q.filter(Recording::Column::Started.gte(started_after));
+        }
+        if let Some(started_before) = *started_before {
+            q = q.filter(Recording::Column::Started.lt(started_before));
+        }
+
+        let (page, offset, total) = {
+            let db = db.lock().await;
+            fetch_page(
+                q,
+                PaginationParams {
+                    offset: *offset,
+                    limit: Some(
+                        limit
+                            .unwrap_or(MAX_RECORDINGS_PER_PAGE)
+                            .min(MAX_RECORDINGS_PER_PAGE),
+                    ),
+                },
+                &*db,
+            )
+            .await?
+        };
+
+        let mut results = vec![];
+        for recording in page {
+            let path = {
+                recordings
+                    .lock()
+                    .await
+                    .path_for(&recording.session_id, &recording.name)
+            };
+            let transcript = match load_transcript(&path).await {
+                Ok(Some(transcript)) => transcript,
+                // Still being transcribed in the background
+                Ok(None) => continue,
+                Err(error) => {
+                    warn!(recording=%recording.id, %error, "Failed to load the transcript");
+                    continue;
+                }
+            };
+            let matches = transcript
+                .into_iter()
+                .filter(|line| line.text.to_lowercase().contains(&needle))
+                .take(MAX_MATCHES_PER_RECORDING)
+                .map(|line| TranscriptMatch {
+                    time: line.time,
+                    text: line.text,
+                })
+                .collect::<Vec<_>>();
+            if !matches.is_empty() {
+                results.push(RecordingSearchResult {
+                    recording_id: recording.id,
+                    session_id: recording.session_id,
+                    recording_started: recording.started,
+                    matches,
+                });
+            }
+        }
+
+        Ok(SearchRecordingsResponse::Ok(Json(
+            PaginatedResponse::from_page(results, offset, total),
+        )))
+    }
+}
@@ -1,14 +1,23 @@
 use std::sync::Arc;
 
-use poem::web::Data;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use poem::error::NotFoundError;
+use poem::http::{header, StatusCode};
+use poem::web::websocket::{Message, WebSocket};
+use poem::web::{Data, Query};
+use poem::{handler, IntoResponse, Request};
 use poem_openapi::param::Path;
 use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, OpenApi};
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
-use tokio::sync::Mutex;
+use serde::Deserialize;
+use tokio::sync::{mpsc, Mutex};
+use tracing::*;
 use uuid::Uuid;
-use warpgate_core::{SessionSnapshot, State};
-use warpgate_db_entities::{Recording, Session};
+use warpgate_common::WarpgateConfig;
+use warpgate_core::{AdminUsername, SessionObserver, SessionObserverMode, SessionSnapshot, State};
+use warpgate_db_entities::{Recording, Session, SessionChannel};
 
 pub struct Api;
 
@@ -27,6 +36,12 @@ enum GetSessionRecordingsResponse {
     Ok(Json<Vec<Recording::Model>>),
 }
 
+#[derive(ApiResponse)]
+enum GetSessionChannelsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<SessionChannel::Model>>),
+}
+
 #[derive(ApiResponse)]
 enum CloseSessionResponse {
     #[oai(status = 201)]
@@ -76,6 +91,26 @@ impl Api {
         Ok(GetSessionRecordingsResponse::Ok(Json(recordings)))
     }
 
+    #[oai(
+        path = "/sessions/:id/channels",
+        method = "get",
+        operation_id = "get_session_channels"
+    )]
+    async fn api_get_session_channels(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        id: Path<Uuid>,
+    ) -> poem::Result<GetSessionChannelsResponse> {
+        let db = db.lock().await;
+        let channels: Vec<SessionChannel::Model> = SessionChannel::Entity::find()
+            .order_by_asc(SessionChannel::Column::Started)
+            .filter(SessionChannel::Column::SessionId.eq(id.0))
+            .all(&*db)
+            .await
+            .map_err(poem::error::InternalServerError)?;
+        Ok(GetSessionChannelsResponse::Ok(Json(channels)))
+    }
+
     #[oai(
         path = "/sessions/:id/close",
         method = "post",
@@ -97,3 +132,95 @@ impl Api {
         }
     }
 }
+
+/// Output messages buffered for an observer before it's considered too slow
+/// and detached from the session
+const OBSERVER_OUTPUT_BUFFER: usize = 1024;
+
+#[derive(Deserialize)]
+pub struct AttachSessionParams {
+    mode: SessionObserverMode,
+}
+
+/// Attaches to a live session's terminal. Terminal output is sent as binary
+/// messages; in assist mode, messages received from the admin are typed
+/// into the session.
+#[handler]
+pub async fn api_attach_session(
+    req: &Request,
+    ws: WebSocket,
+    state: Data<&Arc<Mutex<State>>>,
+    config: Data<&Arc<Mutex<WarpgateConfig>>>,
+    admin: Data<&AdminUsername>,
+    id: poem::web::Path<Uuid>,
+    params: Query<AttachSessionParams>,
+) -> poem::Result<impl IntoResponse> {
+    // Browsers send the admin cookie along with cross-site WebSocket
+    // upgrades, so without this any page could type into a live session
+    if !is_same_origin(req, &*config.lock().await) {
+        warn!(admin=%admin.0 .0, session=%id.0, "Rejected a session attach from a foreign origin");
+        return Err(poem::Error::from_status(StatusCode::FORBIDDEN));
+    }
+
+    let (output_tx, mut output_rx) = mpsc::channel(OBSERVER_OUTPUT_BUFFER);
+    let (input_tx, input_rx) = mpsc::unbounded_channel();
+    let mode = params.mode;
+
+    {
+        let state = state.lock().await;
+        let Some(session) = state.sessions.get(&id) else {
+            return Err(NotFoundError.into());
+        };
+        let attached = session
+            .lock()
+            .await
+            .handle
+            .attach_observer(SessionObserver {
+                username: admin.0 .0.clone(),
+                mode,
+                output: output_tx,
+                input: input_rx,
+            });
+        if !attached {
+            return Err(NotFoundError.into());
+        }
+    }
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let (mut sink, mut stream) = socket.split();
+
+        tokio::spawn(async move {
+            while let Some(data) = output_rx.recv().await {
+                if sink.send(Message::Binary(data.to_vec())).await.is_err() {
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+        });
+
+        while let Some(Ok(message)) = stream.next().await {
+            let data = match message {
+                Message::Binary(data) => Bytes::from(data),
+                Message::Text(text) => Bytes::from(text),
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if mode != SessionObserverMode::Assist {
+                continue;
+            }
+            if input_tx.send(data).is_err() {
+                break;
+            }
+        }
+        debug!(session=%id.0, "Observer disconnected");
+    }))
+}
+
+fn is_same_origin(req: &Request, config: &WarpgateConfig) -> bool {
+    let Some(origin) = req.header(header::ORIGIN) else {
+        return false;
+    };
+    config
+        .construct_external_url(Some(req))
+        .is_ok_and(|url| url.origin().ascii_serialization() == origin)
+}
@@ -1,19 +1,28 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use poem::web::Data;
 use poem_openapi::payload::Json;
-use poem_openapi::{ApiResponse, Object, OpenApi};
+use poem_openapi::{ApiResponse, Enum, Object, OpenApi};
 use russh::keys::PublicKeyBase64;
 use serde::Serialize;
 use tokio::sync::Mutex;
 use warpgate_common::WarpgateConfig;
+use warpgate_protocol_ssh::HostKeyState;
 
 pub struct Api;
 
+#[derive(Serialize, Enum)]
+enum SSHKeyUsage {
+    Client,
+    CertificateAuthority,
+}
+
 #[derive(Serialize, Object)]
 struct SSHKey {
     pub kind: String,
     pub public_key_base64: String,
+    pub usage: SSHKeyUsage,
 }
 
 #[derive(ApiResponse)]
@@ -22,6 +31,55 @@ enum GetSSHOwnKeysResponse {
     Ok(Json<Vec<SSHKey>>),
 }
 
+#[derive(Serialize, Enum)]
+enum SSHHostKeyState {
+    Active,
+    Next,
+}
+
+#[derive(Serialize, Object)]
+struct SSHHostKey {
+    pub kind: String,
+    pub public_key_base64: String,
+    pub state: SSHHostKeyState,
+}
+
+#[derive(Serialize, Object)]
+struct SSHHostKeys {
+    pub keys: Vec<SSHHostKey>,
+    pub retire_at: Option<DateTime<Utc>>,
+}
+
+#[derive(ApiResponse)]
+enum GetSSHHostKeysResponse {
+    #[oai(status = 200)]
+    Ok(Json<SSHHostKeys>),
+}
+
+#[derive(ApiResponse)]
+enum RotateSSHHostKeysResponse {
+    #[oai(status = 200)]
+    Ok(Json<SSHHostKeys>),
+    #[oai(status = 409)]
+    AlreadyRotating,
+}
+
+fn get_host_keys(config: &WarpgateConfig) -> anyhow::Result<SSHHostKeys> {
+    let keys = warpgate_protocol_ssh::list_host_keys(config)?
+        .into_iter()
+        .map(|(state, k)| SSHHostKey {
+            kind: k.name().to_owned(),
+            public_key_base64: k.public_key_base64(),
+            state: match state {
+                HostKeyState::Active => SSHHostKeyState::Active,
+                HostKeyState::Next => SSHHostKeyState::Next,
+            },
+        })
+        .collect();
+    let retire_at = warpgate_protocol_ssh::host_key_retirement_time(config)?.map(Into::into);
+    Ok(SSHHostKeys { keys, retire_at })
+}
+
 #[OpenApi]
 impl Api {
     #[oai(
@@ -37,13 +95,63 @@ impl Api {
         let keys = warpgate_protocol_ssh::load_client_keys(&config)
             .map_err(poem::error::InternalServerError)?;
 
-        let keys = keys
+        let mut keys: Vec<_> = keys
             .into_iter()
             .map(|k| SSHKey {
                 kind: k.name().to_owned(),
                 public_key_base64: k.public_key_base64(),
+                usage: SSHKeyUsage::Client,
             })
             .collect();
+
+        let ca_key = warpgate_protocol_ssh::load_ca_public_key(&config)
+            .map_err(poem::error::InternalServerError)?;
+        keys.push(SSHKey {
+            kind: ca_key.name().to_owned(),
+            public_key_base64: ca_key.public_key_base64(),
+            usage: SSHKeyUsage::CertificateAuthority,
+        });
+
         Ok(GetSSHOwnKeysResponse::Ok(Json(keys)))
     }
+
+    #[oai(
+        path = "/ssh/host-keys",
+        method = "get",
+        operation_id = "get_ssh_host_keys"
+    )]
+    async fn api_ssh_get_host_keys(
+        &self,
+        config: Data<&Arc<Mutex<WarpgateConfig>>>,
+    ) -> poem::Result<GetSSHHostKeysResponse> {
+        let config = config.lock().await;
+        let keys = get_host_keys(&config)?;
+        Ok(GetSSHHostKeysResponse::Ok(Json(keys)))
+    }
+
+    #[oai(
+        path = "/ssh/host-keys/rotate",
+        method = "post",
+        operation_id = "rotate_ssh_host_keys"
+    )]
+    async fn api_ssh_rotate_host_keys(
+        &self,
+        config: Data<&Arc<Mutex<WarpgateConfig>>>,
+    ) -> poem::Result<RotateSSHHostKeysResponse> {
+        // Generating an RSA key takes a while, so don't hold up the config or the executor
+        let config = config.lock().await.clone();
+        let keys = tokio::task::spawn_blocking(move || {
+            if warpgate_protocol_ssh::host_key_retirement_time(&config)?.is_some() {
+                return Ok(None);
+            }
+            warpgate_protocol_ssh::rotate_host_keys(&config)?;
+            get_host_keys(&config).map(Some)
+        })
+        .await
+        .map_err(poem::error::InternalServerError)??;
+        Ok(match keys {
+            Some(keys) => RotateSSHHostKeysResponse::Ok(Json(keys)),
+            None => RotateSSHHostKeysResponse::AlreadyRotating,
+        })
+    }
 }
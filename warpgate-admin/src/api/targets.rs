@@ -18,6 +18,9 @@ use warpgate_db_entities::{Role, Target, TargetRoleAssignment};
 #[derive(Object)]
 struct TargetDataRequest {
     name: String,
+    motd: Option<String>,
+    #[oai(default)]
+    require_justification: bool,
     options: TargetOptions,
 }
 
@@ -26,6 +29,7 @@ enum GetTargetsResponse {
     #[oai(status = 200)]
     Ok(Json<Vec<TargetConfig>>),
 }
+#[allow(clippy::large_enum_variant)]
 #[derive(ApiResponse)]
 enum CreateTargetResponse {
     #[oai(status = 201)]
@@ -84,6 +88,8 @@ impl ListApi {
             name: Set(body.name.clone()),
             kind: Set((&body.options).into()),
             options: Set(serde_json::to_value(body.options.clone()).map_err(WarpgateError::from)?),
+            motd: Set(body.motd.clone()),
+            require_justification: Set(body.require_justification),
         };
 
         let target = values.insert(&*db).await.map_err(WarpgateError::from)?;
@@ -94,6 +100,7 @@ impl ListApi {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(ApiResponse)]
 enum GetTargetResponse {
     #[oai(status = 200)]
@@ -102,6 +109,7 @@ enum GetTargetResponse {
     NotFound,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(ApiResponse)]
 enum UpdateTargetResponse {
     #[oai(status = 200)]
@@ -176,6 +184,8 @@ impl DetailApi {
         model.name = Set(body.name.clone());
         model.options =
             Set(serde_json::to_value(body.options.clone()).map_err(WarpgateError::from)?);
+        model.motd = Set(body.motd.clone());
+        model.require_justification = Set(body.require_justification);
         let target = model
             .update(&*db)
             .await
@@ -83,3 +83,11 @@ pub(crate) fn _default_ssh_keys_path() -> String {
 pub(crate) fn _default_ssh_inactivity_timeout() -> Duration {
     Duration::SECOND * 60 * 5
 }
+
+pub(crate) fn _default_ssh_target_certificate_validity() -> Duration {
+    Duration::SECOND * 60 * 5
+}
+
+pub(crate) fn _default_ssh_host_key_rotation_overlap() -> Duration {
+    Duration::SECOND * 60 * 60 * 24 * 30
+}
@@ -121,6 +121,24 @@ pub struct SshConfig {
 
     #[serde(default = "_default_ssh_inactivity_timeout", with = "humantime_serde")]
     pub inactivity_timeout: Duration,
+
+    #[serde(
+        default = "_default_ssh_target_certificate_validity",
+        with = "humantime_serde"
+    )]
+    pub target_certificate_validity: Duration,
+
+    #[serde(default)]
+    pub algorithms: SshAlgorithms,
+
+    #[serde(
+        default = "_default_ssh_host_key_rotation_overlap",
+        with = "humantime_serde"
+    )]
+    pub host_key_rotation_overlap: Duration,
+
+    #[serde(default)]
+    pub auth_banner: Option<String>,
 }
 
 impl Default for SshConfig {
@@ -132,6 +150,10 @@ impl Default for SshConfig {
             host_key_verification: Default::default(),
             external_port: None,
             inactivity_timeout: _default_ssh_inactivity_timeout(),
+            target_certificate_validity: _default_ssh_target_certificate_validity(),
+            algorithms: Default::default(),
+            host_key_rotation_overlap: _default_ssh_host_key_rotation_overlap(),
+            auth_banner: None,
         }
     }
 }
@@ -167,6 +189,9 @@ pub struct HttpConfig {
 
     #[serde(default = "_default_cookie_max_age", with = "humantime_serde")]
     pub cookie_max_age: Duration,
+
+    #[serde(default)]
+    pub auth_banner: Option<String>,
 }
 
 impl Default for HttpConfig {
@@ -180,6 +205,7 @@ impl Default for HttpConfig {
             trust_x_forwarded_headers: false,
             session_max_age: _default_session_max_age(),
             cookie_max_age: _default_cookie_max_age(),
+            auth_banner: None,
         }
     }
 }
@@ -206,6 +232,9 @@ pub struct MySqlConfig {
 
     #[serde(default)]
     pub key: String,
+
+    #[serde(default)]
+    pub auth_banner: Option<String>,
 }
 
 impl Default for MySqlConfig {
@@ -216,6 +245,7 @@ impl Default for MySqlConfig {
             external_port: None,
             certificate: "".to_owned(),
             key: "".to_owned(),
+            auth_banner: None,
         }
     }
 }
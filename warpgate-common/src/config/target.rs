@@ -18,16 +18,139 @@ pub struct TargetSSHOptions {
     pub allow_insecure_algos: Option<bool>,
     #[serde(default)]
     pub auth: SSHTargetAuth,
+    #[serde(default)]
+    pub allow_agent_forwarding: Option<bool>,
+    #[serde(default)]
+    pub host_key_fingerprints: Option<Vec<String>>,
+    #[serde(default)]
+    pub via: Option<Vec<String>>,
+    #[serde(default)]
+    pub command_policies: Option<Vec<SshCommandPolicy>>,
+    #[serde(default)]
+    pub forwarding_policies: Option<Vec<SshForwardingPolicy>>,
+    #[serde(default)]
+    pub session_limits: Option<Vec<SessionLimitPolicy>>,
+    #[serde(default)]
+    pub reuse_connections: Option<bool>,
+    #[serde(default)]
+    pub algorithms: Option<SshAlgorithms>,
+    #[serde(default)]
+    pub input_recording: Option<SshInputRecordingMode>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Enum, Default)]
+pub enum SshInputRecordingMode {
+    #[serde(rename = "full")]
+    #[default]
+    Full,
+    /// Input typed while the terminal isn't echoing it is left out
+    #[serde(rename = "redacted")]
+    Redacted,
+    #[serde(rename = "none")]
+    None,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Enum, Default)]
+pub enum SshCommandPatternKind {
+    #[serde(rename = "glob")]
+    #[default]
+    Glob,
+    #[serde(rename = "regex")]
+    Regex,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct SshCommandPolicy {
+    #[serde(default)]
+    pub roles: Option<Vec<String>>,
+    #[serde(default)]
+    pub pattern_kind: SshCommandPatternKind,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct SshForwardingPolicy {
+    #[serde(default)]
+    pub roles: Option<Vec<String>>,
+    #[serde(default)]
+    pub allow_local: Option<bool>,
+    #[serde(default)]
+    pub allow_remote: Option<bool>,
+    #[serde(default)]
+    pub allow_x11: Option<bool>,
+    #[serde(default)]
+    pub local_destinations: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object, Default)]
+pub struct SshAlgorithms {
+    #[serde(default)]
+    pub kex: Option<Vec<String>>,
+    #[serde(default)]
+    pub ciphers: Option<Vec<String>>,
+    #[serde(default)]
+    pub macs: Option<Vec<String>>,
+    #[serde(default)]
+    pub host_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct SessionLimitPolicy {
+    #[serde(default)]
+    pub roles: Option<Vec<String>>,
+    #[serde(default)]
+    pub idle_timeout_seconds: Option<u64>,
+    #[serde(default)]
+    pub max_duration_seconds: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Union)]
-#[serde(untagged)]
+#[serde(tag = "kind", from = "SSHTargetAuthRepr")]
 #[oai(discriminator_name = "kind", one_of)]
 pub enum SSHTargetAuth {
     #[serde(rename = "password")]
     Password(SshTargetPasswordAuth),
     #[serde(rename = "publickey")]
     PublicKey(SshTargetPublicKeyAuth),
+    #[serde(rename = "keyboard-interactive")]
+    KeyboardInteractive(SshTargetKeyboardInteractiveAuth),
+}
+
+/// Older configs store the auth settings without a `kind` tag
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SSHTargetAuthRepr {
+    Tagged(TaggedSSHTargetAuth),
+    Password(SshTargetPasswordAuth),
+    PublicKey(SshTargetPublicKeyAuth),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind")]
+enum TaggedSSHTargetAuth {
+    #[serde(rename = "password")]
+    Password(SshTargetPasswordAuth),
+    #[serde(rename = "publickey")]
+    PublicKey(SshTargetPublicKeyAuth),
+    #[serde(rename = "keyboard-interactive")]
+    KeyboardInteractive(SshTargetKeyboardInteractiveAuth),
+}
+
+impl From<SSHTargetAuthRepr> for SSHTargetAuth {
+    fn from(repr: SSHTargetAuthRepr) -> Self {
+        match repr {
+            SSHTargetAuthRepr::Tagged(TaggedSSHTargetAuth::Password(auth))
+            | SSHTargetAuthRepr::Password(auth) => Self::Password(auth),
+            SSHTargetAuthRepr::Tagged(TaggedSSHTargetAuth::PublicKey(auth))
+            | SSHTargetAuthRepr::PublicKey(auth) => Self::PublicKey(auth),
+            SSHTargetAuthRepr::Tagged(TaggedSSHTargetAuth::KeyboardInteractive(auth)) => {
+                Self::KeyboardInteractive(auth)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
@@ -38,6 +161,9 @@ pub struct SshTargetPasswordAuth {
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object, Default)]
 pub struct SshTargetPublicKeyAuth {}
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object, Default)]
+pub struct SshTargetKeyboardInteractiveAuth {}
+
 impl Default for SSHTargetAuth {
     fn default() -> Self {
         SSHTargetAuth::PublicKey(SshTargetPublicKeyAuth::default())
@@ -105,6 +231,9 @@ pub struct TargetMySqlOptions {
 
     #[serde(default)]
     pub tls: Tls,
+
+    #[serde(default)]
+    pub session_limits: Option<Vec<SessionLimitPolicy>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Object, Default)]
@@ -117,6 +246,10 @@ pub struct Target {
     pub name: String,
     #[serde(default = "_default_empty_vec")]
     pub allow_roles: Vec<String>,
+    #[serde(default)]
+    pub motd: Option<String>,
+    #[serde(default)]
+    pub require_justification: bool,
     #[serde(flatten)]
     pub options: TargetOptions,
 }
@@ -133,3 +266,23 @@ pub enum TargetOptions {
     #[serde(rename = "web_admin")]
     WebAdmin(TargetWebAdminOptions),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_untagged_ssh_auth() {
+        let parse = |json| serde_json::from_str::<SSHTargetAuth>(json).ok();
+        assert!(matches!(
+            parse(r#"{"password": "x"}"#),
+            Some(SSHTargetAuth::Password(_))
+        ));
+        assert!(matches!(parse("{}"), Some(SSHTargetAuth::PublicKey(_))));
+
+        let auth = SSHTargetAuth::KeyboardInteractive(SshTargetKeyboardInteractiveAuth {});
+        let json = serde_json::to_string(&auth).ok();
+        assert_eq!(json.as_deref(), Some(r#"{"kind":"keyboard-interactive"}"#));
+        assert_eq!(json.as_deref().and_then(parse), Some(auth));
+    }
+}
@@ -298,6 +298,26 @@ impl ConfigProvider for DatabaseConfigProvider {
         Ok(intersect)
     }
 
+    async fn list_user_roles(&mut self, username: &str) -> Result<Vec<String>, WarpgateError> {
+        let db = self.db.lock().await;
+
+        let Some(user_model) = User::Entity::find()
+            .filter(User::Column::Username.eq(username))
+            .one(&*db)
+            .await?
+        else {
+            return Ok(vec![]);
+        };
+
+        Ok(user_model
+            .find_related(Role::Entity)
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|role| role.name)
+            .collect())
+    }
+
     async fn apply_sso_role_mappings(
         &mut self,
         username: &str,
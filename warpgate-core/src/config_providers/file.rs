@@ -288,6 +288,19 @@ impl ConfigProvider for FileConfigProvider {
         Ok(intersect)
     }
 
+    async fn list_user_roles(&mut self, username: &str) -> Result<Vec<String>, WarpgateError> {
+        let config = self.config.lock().await;
+        let Some(user) = config.store.users.iter().find(|x| x.username == username) else {
+            return Ok(vec![]);
+        };
+        Ok(user
+            .roles
+            .iter()
+            .filter(|role| config.store.roles.iter().any(|y| &y.name == *role))
+            .cloned()
+            .collect())
+    }
+
     async fn apply_sso_role_mappings(
         &mut self,
         _username: &str,
@@ -49,6 +49,8 @@ pub trait ConfigProvider {
         username: &str,
         target: &str,
     ) -> Result<bool, WarpgateError>;
+
+    async fn list_user_roles(&mut self, username: &str) -> Result<Vec<String>, WarpgateError>;
 }
 
 //TODO: move this somewhere
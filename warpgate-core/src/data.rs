@@ -14,6 +14,8 @@ pub struct SessionSnapshot {
     pub ended: Option<DateTime<Utc>>,
     pub ticket_id: Option<Uuid>,
     pub protocol: String,
+    pub end_reason: Option<String>,
+    pub justification: Option<String>,
 }
 
 impl From<Session::Model> for SessionSnapshot {
@@ -28,6 +30,12 @@ impl From<Session::Model> for SessionSnapshot {
             ended: model.ended,
             ticket_id: model.ticket_id,
             protocol: model.protocol,
+            end_reason: model.end_reason,
+            justification: model.justification,
         }
     }
 }
+
+/// Username of the admin making an admin API request
+#[derive(Clone)]
+pub struct AdminUsername(pub String);
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::time::Duration;
 
 use anyhow::Result;
-use sea_orm::sea_query::Expr;
+use sea_orm::sea_query::{Expr, Query};
 use sea_orm::{
     ActiveModelTrait, ColumnTrait, ConnectOptions, Database, DatabaseConnection, EntityTrait,
     ModelTrait, QueryFilter, TransactionTrait,
@@ -67,7 +67,7 @@ pub async fn populate_db(
     config: &mut WarpgateConfig,
 ) -> Result<(), WarpgateError> {
     use sea_orm::ActiveValue::Set;
-    use warpgate_db_entities::{Recording, Session};
+    use warpgate_db_entities::{Recording, Session, SessionChannel};
 
     Recording::Entity::update_many()
         .set(Recording::ActiveModel {
@@ -79,6 +79,16 @@ pub async fn populate_db(
         .await
         .map_err(WarpgateError::from)?;
 
+    SessionChannel::Entity::update_many()
+        .set(SessionChannel::ActiveModel {
+            ended: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        })
+        .filter(Expr::col(SessionChannel::Column::Ended).is_null())
+        .exec(db)
+        .await
+        .map_err(WarpgateError::from)?;
+
     Session::Entity::update_many()
         .set(Session::ActiveModel {
             ended: Set(Some(chrono::Utc::now())),
@@ -123,6 +133,8 @@ pub async fn populate_db(
                     TargetWebAdminOptions {},
                 ))
                 .map_err(WarpgateError::from)?),
+                motd: Set(None),
+                require_justification: Set(false),
             };
 
             values.insert(&*db).await.map_err(WarpgateError::from)?
@@ -204,6 +216,8 @@ async fn migrate_config_into_db(
                     kind: Set((&target_config.options).into()),
                     options: Set(serde_json::to_value(target_config.options.clone())
                         .map_err(WarpgateError::from)?),
+                    motd: Set(target_config.motd.clone()),
+                    require_justification: Set(target_config.require_justification),
                 };
 
                 info!("Migrating target {}", target_config.name);
@@ -286,7 +300,7 @@ pub async fn cleanup_db(
     recordings: &mut SessionRecordings,
     retention: &Duration,
 ) -> Result<()> {
-    use warpgate_db_entities::{Recording, Session};
+    use warpgate_db_entities::{Recording, Session, SessionChannel};
     let cutoff = chrono::Utc::now() - chrono::Duration::from_std(*retention)?;
 
     LogEntry::Entity::delete_many()
@@ -310,6 +324,20 @@ pub async fn cleanup_db(
         recording.delete(db).await?;
     }
 
+    SessionChannel::Entity::delete_many()
+        .filter(
+            SessionChannel::Column::SessionId.in_subquery(
+                Query::select()
+                    .column(Session::Column::Id)
+                    .from(Session::Entity)
+                    .and_where(Expr::col(Session::Column::Ended).is_not_null())
+                    .and_where(Expr::col(Session::Column::Ended).lt(cutoff))
+                    .to_owned(),
+            ),
+        )
+        .exec(db)
+        .await?;
+
     Session::Entity::delete_many()
         .filter(Expr::col(Session::Column::Ended).is_not_null())
         .filter(Expr::col(Session::Column::Ended).lt(cutoff))
@@ -10,6 +10,8 @@ pub mod db;
 mod protocols;
 pub use protocols::*;
 pub mod recordings;
+mod session_limits;
+pub use session_limits::*;
 mod services;
 pub use services::*;
 mod auth_state_store;
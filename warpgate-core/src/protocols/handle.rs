@@ -1,14 +1,55 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
-use tokio::sync::Mutex;
+use bytes::Bytes;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::Deserialize;
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
 use warpgate_common::{SessionId, Target, WarpgateError};
-use warpgate_db_entities::Session;
+use warpgate_db_entities::{Session, SessionChannel};
 
 use crate::{SessionState, State};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionObserverMode {
+    /// Read-only
+    Shadow,
+    /// Can type into the session
+    Assist,
+}
+
+/// An admin attached to a live terminal session.
+/// The session writes terminal output into `output` and, in assist mode,
+/// forwards `input` to the target. Dropping `input`'s sender detaches, and
+/// an observer that lets `output` fill up is detached by the session.
+#[derive(Debug)]
+pub struct SessionObserver {
+    pub username: String,
+    pub mode: SessionObserverMode,
+    pub output: mpsc::Sender<Bytes>,
+    pub input: mpsc::UnboundedReceiver<Bytes>,
+}
+
+/// Metadata recorded for a channel of a session
+#[derive(Debug)]
+pub enum ChannelUpdate {
+    Command(String),
+    Subsystem(String),
+    Env(HashMap<String, String>),
+    ExitStatus(u32),
+    ExitSignal(String),
+    Closed,
+}
+
 pub trait SessionHandle {
     fn close(&mut self);
+
+    /// Returns false if the protocol doesn't support live observers
+    fn attach_observer(&mut self, _observer: SessionObserver) -> bool {
+        false
+    }
 }
 
 pub struct WarpgateServerHandle {
@@ -87,6 +128,109 @@ impl WarpgateServerHandle {
 
         Ok(())
     }
+
+    pub async fn set_justification(&self, justification: String) -> Result<(), WarpgateError> {
+        use sea_orm::ActiveValue::Set;
+        {
+            let mut state = self.session_state.lock().await;
+            state.justification = Some(justification.clone());
+            state.emit_change()
+        }
+
+        let db = self.db.lock().await;
+
+        Session::Entity::update_many()
+            .set(Session::ActiveModel {
+                justification: Set(Some(justification)),
+                ..Default::default()
+            })
+            .filter(Session::Column::Id.eq(self.id))
+            .exec(&*db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn add_channel(&self, channel_id: Uuid) -> Result<(), WarpgateError> {
+        use sea_orm::ActiveValue::Set;
+
+        let db = self.db.lock().await;
+
+        SessionChannel::ActiveModel {
+            id: Set(channel_id),
+            session_id: Set(self.id),
+            started: Set(chrono::Utc::now()),
+            env: Set(serde_json::json!({})),
+            ..Default::default()
+        }
+        .insert(&*db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_channel(
+        &self,
+        channel_id: Uuid,
+        update: ChannelUpdate,
+    ) -> Result<(), WarpgateError> {
+        use sea_orm::ActiveValue::Set;
+
+        let values = match update {
+            ChannelUpdate::Command(command) => SessionChannel::ActiveModel {
+                command: Set(Some(command)),
+                ..Default::default()
+            },
+            ChannelUpdate::Subsystem(name) => SessionChannel::ActiveModel {
+                subsystem: Set(Some(name)),
+                ..Default::default()
+            },
+            ChannelUpdate::Env(env) => SessionChannel::ActiveModel {
+                env: Set(serde_json::to_value(env).map_err(WarpgateError::other)?),
+                ..Default::default()
+            },
+            ChannelUpdate::ExitStatus(code) => SessionChannel::ActiveModel {
+                exit_status: Set(Some(code.into())),
+                ..Default::default()
+            },
+            ChannelUpdate::ExitSignal(signal) => SessionChannel::ActiveModel {
+                exit_signal: Set(Some(signal)),
+                ..Default::default()
+            },
+            ChannelUpdate::Closed => SessionChannel::ActiveModel {
+                ended: Set(Some(chrono::Utc::now())),
+                ..Default::default()
+            },
+        };
+
+        let db = self.db.lock().await;
+
+        SessionChannel::Entity::update_many()
+            .set(values)
+            .filter(SessionChannel::Column::Id.eq(channel_id))
+            .filter(SessionChannel::Column::SessionId.eq(self.id))
+            .exec(&*db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_end_reason(&self, reason: &str) -> Result<(), WarpgateError> {
+        use sea_orm::ActiveValue::Set;
+
+        let db = self.db.lock().await;
+
+        Session::Entity::update_many()
+            .set(Session::ActiveModel {
+                end_reason: Set(Some(reason.to_owned())),
+                ..Default::default()
+            })
+            .filter(Session::Column::Id.eq(self.id))
+            .exec(&*db)
+            .await?;
+
+        Ok(())
+    }
 }
 
 impl Drop for WarpgateServerHandle {
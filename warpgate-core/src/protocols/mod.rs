@@ -3,7 +3,10 @@ use std::net::SocketAddr;
 
 use anyhow::Result;
 use async_trait::async_trait;
-pub use handle::{SessionHandle, WarpgateServerHandle};
+pub use handle::{
+    ChannelUpdate, SessionHandle, SessionObserver, SessionObserverMode, WarpgateServerHandle,
+};
+use tokio::io::DuplexStream;
 use warpgate_common::Target;
 
 #[derive(Debug, thiserror::Error)]
@@ -25,3 +28,22 @@ pub trait ProtocolServer {
     async fn run(self, address: SocketAddr) -> Result<()>;
     async fn test_target(&self, target: Target) -> Result<(), TargetTestError>;
 }
+
+/// A connection that one protocol server accepted on behalf of an already
+/// authenticated user and hands over to the server of the target's protocol,
+/// e.g. an SSH port forward to an HTTP or MySQL target
+pub struct ForwardedConnection {
+    pub username: String,
+    pub target: Target,
+    pub remote_address: SocketAddr,
+    pub justification: Option<String>,
+}
+
+#[async_trait]
+pub trait ForwardedConnectionHandler: Send + Sync {
+    async fn handle_forwarded_connection(
+        &self,
+        stream: DuplexStream,
+        connection: ForwardedConnection,
+    ) -> Result<()>;
+}
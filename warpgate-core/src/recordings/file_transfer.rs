@@ -0,0 +1,121 @@
+use std::fmt::{Display, Formatter};
+
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use warpgate_db_entities::Recording::RecordingKind;
+
+use super::writer::RecordingWriter;
+use super::{Error, Recorder, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum FileTransferOperation {
+    #[serde(rename = "open")]
+    Open,
+    #[serde(rename = "read")]
+    Read,
+    #[serde(rename = "write")]
+    Write,
+    #[serde(rename = "close")]
+    Close,
+    #[serde(rename = "remove")]
+    Remove,
+    #[serde(rename = "rename")]
+    Rename,
+    #[serde(rename = "mkdir")]
+    Mkdir,
+    #[serde(rename = "rmdir")]
+    Rmdir,
+    #[serde(rename = "symlink")]
+    Symlink,
+}
+
+impl FileTransferOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Close => "close",
+            Self::Remove => "remove",
+            Self::Rename => "rename",
+            Self::Mkdir => "mkdir",
+            Self::Rmdir => "rmdir",
+            Self::Symlink => "symlink",
+        }
+    }
+}
+
+/// A single file operation performed over a file transfer protocol
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Object)]
+pub struct FileTransferEvent {
+    pub operation: FileTransferOperation,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    pub success: bool,
+}
+
+impl Display for FileTransferEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.operation.as_str(), self.path)?;
+        if let Some(ref target_path) = self.target_path {
+            write!(f, " -> {target_path}")?;
+        }
+        if let Some(bytes) = self.bytes {
+            write!(f, " ({bytes} bytes)")?;
+        }
+        if let Some(mode) = self.mode {
+            write!(f, " (mode {mode:04o})")?;
+        }
+        if !self.success {
+            write!(f, " (failed)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Object)]
+pub struct FileTransferRecordingItem {
+    pub time: f32,
+    pub event: FileTransferEvent,
+}
+
+pub struct FileTransferRecorder {
+    writer: RecordingWriter,
+    started_at: Instant,
+}
+
+impl FileTransferRecorder {
+    fn get_time(&self) -> f32 {
+        self.started_at.elapsed().as_secs_f32()
+    }
+
+    pub async fn write(&mut self, event: FileTransferEvent) -> Result<()> {
+        let item = FileTransferRecordingItem {
+            time: self.get_time(),
+            event,
+        };
+        let mut serialized_item = serde_json::to_vec(&item).map_err(Error::Serialization)?;
+        serialized_item.push(b'\n');
+        self.writer.write(&serialized_item).await?;
+        Ok(())
+    }
+}
+
+impl Recorder for FileTransferRecorder {
+    fn kind() -> RecordingKind {
+        RecordingKind::FileTransfer
+    }
+
+    fn new(writer: RecordingWriter) -> Self {
+        FileTransferRecorder {
+            writer,
+            started_at: Instant::now(),
+        }
+    }
+}
@@ -10,11 +10,15 @@ use uuid::Uuid;
 use warpgate_common::helpers::fs::secure_directory;
 use warpgate_common::{RecordingsConfig, SessionId, WarpgateConfig};
 use warpgate_db_entities::Recording::{self, RecordingKind};
+mod file_transfer;
 mod terminal;
 mod traffic;
+mod transcript;
 mod writer;
+pub use file_transfer::*;
 pub use terminal::*;
 pub use traffic::*;
+pub use transcript::*;
 use writer::RecordingWriter;
 
 #[derive(thiserror::Error, Debug)]
@@ -107,6 +111,10 @@ impl SessionRecordings {
     pub async fn remove<P: AsRef<Path>>(&self, session_id: &SessionId, name: P) -> Result<()> {
         let path = self.path_for(session_id, name);
         tokio::fs::remove_file(&path).await?;
+        let transcript_path = transcript_path_for(&path);
+        if tokio::fs::try_exists(&transcript_path).await? {
+            tokio::fs::remove_file(&transcript_path).await?;
+        }
         if let Some(parent) = path.parent() {
             if tokio::fs::read_dir(parent)
                 .await?
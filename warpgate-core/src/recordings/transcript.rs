@@ -0,0 +1,483 @@
+//! Plain-text transcripts of terminal recordings, produced by replaying the
+//! output through a minimal VT100/xterm screen model. A line makes it into the
+//! transcript when it scrolls off the screen, when the screen is cleared or
+//! switched, or when the recording ends - each with the time it was first drawn.
+
+use std::path::{Path, PathBuf};
+
+use anstyle_parse::{DefaultCharAccumulator, Params, Parser, Perform};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::Mutex;
+use tracing::*;
+use warpgate_common::helpers::fs::secure_file;
+use warpgate_db_entities::Recording::{self, RecordingKind};
+
+use super::{Result, SessionRecordings, TerminalRecordingItem, TerminalRecordingStreamId};
+
+const TRANSCRIPT_EXTENSION: &str = "transcript";
+const DEFAULT_COLS: usize = 80;
+const DEFAULT_ROWS: usize = 24;
+/// Guards against absurd PTY sizes in recordings
+const MAX_DIMENSION: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TranscriptLine {
+    pub time: f32,
+    pub text: String,
+}
+
+#[derive(Clone)]
+struct Row {
+    cells: Vec<char>,
+    time: Option<f32>,
+}
+
+impl Row {
+    fn new(cols: usize) -> Self {
+        Self {
+            cells: vec![' '; cols],
+            time: None,
+        }
+    }
+}
+
+struct Screen {
+    cols: usize,
+    rows: Vec<Row>,
+    cursor_row: usize,
+    cursor_col: usize,
+    pending_wrap: bool,
+    alternate: bool,
+    time: f32,
+    lines: Vec<TranscriptLine>,
+}
+
+impl Screen {
+    fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows: vec![Row::new(cols); rows],
+            cursor_row: 0,
+            cursor_col: 0,
+            pending_wrap: false,
+            alternate: false,
+            time: 0.0,
+            lines: vec![],
+        }
+    }
+
+    fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn resize(&mut self, cols: usize, rows: usize) {
+        let cols = cols.clamp(1, MAX_DIMENSION);
+        let rows = rows.clamp(1, MAX_DIMENSION);
+        while self.rows.len() > rows {
+            // Like a real terminal, push the top lines into the scrollback
+            self.scroll_up();
+            self.rows.pop();
+            self.cursor_row = self.cursor_row.saturating_sub(1);
+        }
+        while self.rows.len() < rows {
+            self.rows.push(Row::new(cols));
+        }
+        for row in self.rows.iter_mut() {
+            row.cells.resize(cols, ' ');
+        }
+        self.cols = cols;
+        self.cursor_row = self.cursor_row.min(rows - 1);
+        self.cursor_col = self.cursor_col.min(cols - 1);
+    }
+
+    fn commit_row(&mut self, index: usize) {
+        let cols = self.cols;
+        let Some(row) = self.rows.get_mut(index) else {
+            return;
+        };
+        let row = std::mem::replace(row, Row::new(cols));
+        let text = row.cells.into_iter().collect::<String>();
+        let text = text.trim_end();
+        if !text.is_empty() {
+            self.lines.push(TranscriptLine {
+                time: row.time.unwrap_or(self.time),
+                text: text.to_owned(),
+            });
+        }
+    }
+
+    fn commit_all(&mut self) {
+        for index in 0..self.height() {
+            self.commit_row(index);
+        }
+    }
+
+    fn scroll_up(&mut self) {
+        self.commit_row(0);
+        self.rows.remove(0);
+        self.rows.push(Row::new(self.cols));
+    }
+
+    fn scroll_down(&mut self) {
+        self.rows.pop();
+        self.rows.insert(0, Row::new(self.cols));
+    }
+
+    fn line_feed(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor_row + 1 >= self.height() {
+            self.scroll_up();
+        } else {
+            self.cursor_row += 1;
+        }
+    }
+
+    fn current_row(&mut self) -> Option<&mut Row> {
+        self.rows.get_mut(self.cursor_row)
+    }
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize) {
+        if let Some(row) = self.rows.get_mut(row) {
+            let to = to.min(row.cells.len());
+            for cell in row.cells.iter_mut().take(to).skip(from) {
+                *cell = ' ';
+            }
+        }
+    }
+
+    fn set_alternate_screen(&mut self, alternate: bool) {
+        if self.alternate != alternate {
+            self.commit_all();
+            self.alternate = alternate;
+            self.cursor_row = 0;
+            self.cursor_col = 0;
+            self.pending_wrap = false;
+        }
+    }
+
+    fn move_cursor(&mut self, row: usize, col: usize) {
+        self.cursor_row = row.min(self.height() - 1);
+        self.cursor_col = col.min(self.cols - 1);
+        self.pending_wrap = false;
+    }
+}
+
+fn param(params: &Params, index: usize, default: usize) -> usize {
+    match params.iter().nth(index).and_then(|p| p.first()) {
+        Some(0) | None => default,
+        Some(value) => *value as usize,
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, c: char) {
+        if self.pending_wrap {
+            self.line_feed();
+            self.cursor_col = 0;
+        }
+        let (col, time) = (self.cursor_col, self.time);
+        if let Some(row) = self.current_row() {
+            if let Some(cell) = row.cells.get_mut(col) {
+                *cell = c;
+            }
+            row.time.get_or_insert(time);
+        }
+        if self.cursor_col + 1 >= self.cols {
+            self.pending_wrap = true;
+        } else {
+            self.cursor_col += 1;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            b'\r' => {
+                self.cursor_col = 0;
+                self.pending_wrap = false;
+            }
+            0x08 => {
+                self.cursor_col = self.cursor_col.saturating_sub(1);
+                self.pending_wrap = false;
+            }
+            b'\t' => self.cursor_col = ((self.cursor_col / 8 + 1) * 8).min(self.cols - 1),
+            _ => (),
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: u8) {
+        let (row, col) = (self.cursor_row, self.cursor_col);
+        let n = param(params, 0, 1);
+        match (intermediates, action) {
+            ([], b'A') => self.move_cursor(row.saturating_sub(n), col),
+            ([], b'B') => self.move_cursor(row + n, col),
+            ([], b'C') => self.move_cursor(row, col + n),
+            ([], b'D') => self.move_cursor(row, col.saturating_sub(n)),
+            ([], b'E') => self.move_cursor(row + n, 0),
+            ([], b'F') => self.move_cursor(row.saturating_sub(n), 0),
+            ([], b'G') => self.move_cursor(row, n - 1),
+            ([], b'd') => self.move_cursor(n - 1, col),
+            ([], b'H' | b'f') => self.move_cursor(n - 1, param(params, 1, 1) - 1),
+            ([], b'J') => match param(params, 0, 0) {
+                0 => {
+                    self.erase_cells(row, col, self.cols);
+                    for index in row + 1..self.height() {
+                        self.erase_cells(index, 0, self.cols);
+                    }
+                }
+                1 => {
+                    for index in 0..row {
+                        self.erase_cells(index, 0, self.cols);
+                    }
+                    self.erase_cells(row, 0, col + 1);
+                }
+                _ => self.commit_all(),
+            },
+            ([], b'K') => match param(params, 0, 0) {
+                0 => self.erase_cells(row, col, self.cols),
+                1 => self.erase_cells(row, 0, col + 1),
+                _ => self.erase_cells(row, 0, self.cols),
+            },
+            ([], b'X') => self.erase_cells(row, col, col + n),
+            ([], b'P') => {
+                let cols = self.cols;
+                if let Some(current) = self.current_row() {
+                    let n = n.min(cols - col);
+                    current.cells.drain(col..col + n);
+                    current.cells.resize(cols, ' ');
+                }
+            }
+            ([], b'@') => {
+                let cols = self.cols;
+                if let Some(current) = self.current_row() {
+                    for _ in 0..n.min(cols - col) {
+                        current.cells.insert(col, ' ');
+                    }
+                    current.cells.truncate(cols);
+                }
+            }
+            ([], b'L') => {
+                for _ in 0..n.min(self.height() - row) {
+                    self.rows.pop();
+                    self.rows.insert(row, Row::new(self.cols));
+                }
+            }
+            ([], b'M') => {
+                for _ in 0..n.min(self.height() - row) {
+                    self.commit_row(row);
+                    self.rows.remove(row);
+                    self.rows.push(Row::new(self.cols));
+                }
+            }
+            ([], b'S') => {
+                for _ in 0..n.min(self.height()) {
+                    self.scroll_up();
+                }
+            }
+            ([], b'T') => {
+                for _ in 0..n.min(self.height()) {
+                    self.scroll_down();
+                }
+            }
+            ([b'?'], b'h' | b'l') => {
+                if params
+                    .iter()
+                    .any(|p| matches!(p.first(), Some(47 | 1047 | 1049)))
+                {
+                    self.set_alternate_screen(action == b'h');
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        match (intermediates, byte) {
+            ([], b'D') => self.line_feed(),
+            ([], b'E') => {
+                self.line_feed();
+                self.cursor_col = 0;
+            }
+            ([], b'M') => {
+                if self.cursor_row == 0 {
+                    self.scroll_down();
+                } else {
+                    self.cursor_row -= 1;
+                }
+            }
+            ([], b'c') => {
+                self.commit_all();
+                self.move_cursor(0, 0);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Incrementally builds a transcript from terminal output
+pub struct Transcriber {
+    parser: Parser<DefaultCharAccumulator>,
+    screen: Screen,
+}
+
+impl Default for Transcriber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transcriber {
+    pub fn new() -> Self {
+        Self {
+            parser: Parser::default(),
+            screen: Screen::new(DEFAULT_COLS, DEFAULT_ROWS),
+        }
+    }
+
+    pub fn resize(&mut self, cols: u32, rows: u32) {
+        self.screen.resize(cols as usize, rows as usize);
+    }
+
+    pub fn feed(&mut self, time: f32, data: &[u8]) {
+        self.screen.time = time;
+        for byte in data {
+            self.parser.advance(&mut self.screen, *byte);
+        }
+    }
+
+    pub fn add_item(&mut self, item: &TerminalRecordingItem) {
+        match item {
+            TerminalRecordingItem::Data {
+                time,
+                stream: TerminalRecordingStreamId::Output | TerminalRecordingStreamId::Error,
+                data,
+            } => self.feed(*time, data),
+            TerminalRecordingItem::Data { .. } => (),
+            TerminalRecordingItem::PtyResize { cols, rows, .. } => self.resize(*cols, *rows),
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<TranscriptLine> {
+        self.screen.commit_all();
+        self.screen.lines
+    }
+}
+
+pub fn transcript_path_for(recording_path: &Path) -> PathBuf {
+    let mut path = recording_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(TRANSCRIPT_EXTENSION);
+    path.into()
+}
+
+/// Builds the transcript of a terminal recording file
+pub async fn transcribe(recording_path: &Path) -> Result<Vec<TranscriptLine>> {
+    let mut transcriber = Transcriber::new();
+    let mut lines = BufReader::new(File::open(recording_path).await?).lines();
+    while let Some(line) = lines.next_line().await? {
+        // The last line may still be incomplete in a live recording
+        if let Ok(item) = serde_json::from_str::<TerminalRecordingItem>(&line) {
+            transcriber.add_item(&item);
+        }
+    }
+    Ok(transcriber.finish())
+}
+
+/// Builds the transcript of a finished terminal recording and stores it next to it
+pub async fn write_transcript(recording_path: &Path) -> Result<Vec<TranscriptLine>> {
+    let transcript = transcribe(recording_path).await?;
+    let path = transcript_path_for(recording_path);
+    let mut writer = BufWriter::new(File::create(&path).await?);
+    secure_file(&path)?;
+    for line in transcript.iter() {
+        let mut serialized = serde_json::to_vec(line)?;
+        serialized.push(b'\n');
+        writer.write_all(&serialized).await?;
+    }
+    writer.flush().await?;
+    Ok(transcript)
+}
+
+/// Loads the stored transcript of a finished terminal recording.
+/// Returns `None` if it hasn't been transcribed yet.
+pub async fn load_transcript(recording_path: &Path) -> Result<Option<Vec<TranscriptLine>>> {
+    let path = transcript_path_for(recording_path);
+    if !tokio::fs::try_exists(&path).await? {
+        return Ok(None);
+    }
+    let mut transcript = vec![];
+    let mut lines = BufReader::new(File::open(&path).await?).lines();
+    while let Some(line) = lines.next_line().await? {
+        transcript.push(serde_json::from_str(&line)?);
+    }
+    Ok(Some(transcript))
+}
+
+/// Transcribes finished terminal recordings made before transcripts existed
+pub async fn write_missing_transcripts(recordings: &Mutex<SessionRecordings>) -> Result<()> {
+    let db = recordings.lock().await.db.clone();
+    let finished = {
+        let db = db.lock().await;
+        Recording::Entity::find()
+            .filter(Recording::Column::Kind.eq(RecordingKind::Terminal))
+            .filter(Recording::Column::Ended.is_not_null())
+            .all(&*db)
+            .await?
+    };
+
+    let mut written = 0;
+    for recording in finished {
+        let path = recordings
+            .lock()
+            .await
+            .path_for(&recording.session_id, &recording.name);
+        if !tokio::fs::try_exists(&path).await?
+            || tokio::fs::try_exists(transcript_path_for(&path)).await?
+        {
+            continue;
+        }
+        match write_transcript(&path).await {
+            Ok(_) => written += 1,
+            Err(error) => {
+                warn!(recording=%recording.id, %error, "Failed to transcribe the recording")
+            }
+        }
+    }
+    if written > 0 {
+        info!("Transcribed {written} older terminal recordings");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: &[TranscriptLine]) -> Vec<&str> {
+        lines.iter().map(|l| l.text.as_str()).collect()
+    }
+
+    #[test]
+    fn transcribes_terminal_output() {
+        let mut transcriber = Transcriber::new();
+        transcriber.resize(20, 3);
+        transcriber.feed(1.0, b"$ ls\r\n\x1b[1;34mdir\x1b[0m  file\r\n$ ");
+        // line editing: type a typo, erase it and retype
+        transcriber.feed(2.0, b"cta\x08\x08\x1b[Kat file\r\nhello\r\n");
+        // a full-screen app's content is kept when it exits
+        transcriber.feed(3.0, b"\x1b[?1049h\x1b[H\x1b[2Jeditor\x1b[?1049l$ ");
+        let lines = transcriber.finish();
+
+        assert_eq!(
+            texts(&lines),
+            vec!["$ ls", "dir  file", "$ cat file", "hello", "editor", "$"]
+        );
+        let time = |index: usize| lines.get(index).map(|l| l.time);
+        assert_eq!(time(0), Some(1.0));
+        assert_eq!(time(2), Some(1.0));
+        assert_eq!(time(3), Some(2.0));
+    }
+}
@@ -12,9 +12,9 @@ use tracing::*;
 use uuid::Uuid;
 use warpgate_common::helpers::fs::secure_file;
 use warpgate_common::try_block;
-use warpgate_db_entities::Recording;
+use warpgate_db_entities::Recording::{self, RecordingKind};
 
-use super::{Error, Result};
+use super::{write_transcript, Error, Result};
 
 #[derive(Clone)]
 pub struct RecordingWriter {
@@ -80,6 +80,7 @@ impl RecordingWriter {
 
                 use sea_orm::ActiveValue::Set;
                 let id = model.id;
+                let kind = model.kind.clone();
                 let db = db.lock().await;
                 let recording = Recording::Entity::find_by_id(id)
                     .one(&*db)
@@ -88,6 +89,11 @@ impl RecordingWriter {
                 let mut model: Recording::ActiveModel = recording.into();
                 model.ended = Set(Some(chrono::Utc::now()));
                 model.update(&*db).await?;
+                drop(db);
+
+                if kind == RecordingKind::Terminal {
+                    write_transcript(&path).await?;
+                }
                 Ok::<(), anyhow::Error>(())
             } catch (error: anyhow::Error) {
                 error!(%error, ?path, "Failed to write recording");
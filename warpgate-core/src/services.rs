@@ -1,16 +1,25 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Result;
 use sea_orm::DatabaseConnection;
 use tokio::sync::Mutex;
+use tracing::*;
 use warpgate_common::{ConfigProviderKind, WarpgateConfig};
+use warpgate_db_entities::Target::TargetKind;
 
 use crate::db::{connect_to_db, populate_db};
-use crate::recordings::SessionRecordings;
-use crate::{AuthStateStore, ConfigProvider, DatabaseConfigProvider, FileConfigProvider, State};
+use crate::recordings::{write_missing_transcripts, SessionRecordings};
+use crate::{
+    AuthStateStore, ConfigProvider, DatabaseConfigProvider, FileConfigProvider,
+    ForwardedConnectionHandler, State,
+};
 
 type ConfigProviderArc = Arc<Mutex<dyn ConfigProvider + Send + 'static>>;
+type ForwardedConnectionHandlers = HashMap<TargetKind, Arc<dyn ForwardedConnectionHandler>>;
+type ProtocolStates = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
 
 #[derive(Clone)]
 pub struct Services {
@@ -20,6 +29,8 @@ pub struct Services {
     pub state: Arc<Mutex<State>>,
     pub config_provider: ConfigProviderArc,
     pub auth_state_store: Arc<Mutex<AuthStateStore>>,
+    pub forwarded_connection_handlers: Arc<Mutex<ForwardedConnectionHandlers>>,
+    pub protocol_states: Arc<Mutex<ProtocolStates>>,
 }
 
 impl Services {
@@ -31,6 +42,15 @@ impl Services {
         let recordings = SessionRecordings::new(db.clone(), &config)?;
         let recordings = Arc::new(Mutex::new(recordings));
 
+        tokio::spawn({
+            let recordings = recordings.clone();
+            async move {
+                if let Err(error) = write_missing_transcripts(&recordings).await {
+                    error!(%error, "Failed to transcribe older recordings");
+                }
+            }
+        });
+
         let provider = config.store.config_provider.clone();
         let config = Arc::new(Mutex::new(config));
 
@@ -62,6 +82,49 @@ impl Services {
             state: State::new(&db),
             config_provider,
             auth_state_store,
+            forwarded_connection_handlers: Arc::new(Mutex::new(HashMap::new())),
+            protocol_states: Arc::new(Mutex::new(HashMap::new())),
         })
     }
+
+    pub async fn register_forwarded_connection_handler(
+        &self,
+        kind: TargetKind,
+        handler: Arc<dyn ForwardedConnectionHandler>,
+    ) {
+        self.forwarded_connection_handlers
+            .lock()
+            .await
+            .insert(kind, handler);
+    }
+
+    pub async fn forwarded_connection_handler(
+        &self,
+        kind: &TargetKind,
+    ) -> Option<Arc<dyn ForwardedConnectionHandler>> {
+        self.forwarded_connection_handlers
+            .lock()
+            .await
+            .get(kind)
+            .cloned()
+    }
+
+    /// State that a protocol shares between all of its sessions, e.g. a
+    /// connection pool. Created on first use.
+    pub async fn protocol_state<T: Default + Send + Sync + 'static>(&self) -> Arc<T> {
+        let mut states = self.protocol_states.lock().await;
+        let state = states
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Arc::new(T::default()))
+            .clone();
+        match state.downcast::<T>() {
+            Ok(state) => state,
+            Err(_) => {
+                // Unreachable since states are keyed by their type
+                let state = Arc::new(T::default());
+                states.insert(TypeId::of::<T>(), state.clone());
+                state
+            }
+        }
+    }
 }
@@ -0,0 +1,261 @@
+use std::time::{Duration, Instant};
+
+use warpgate_common::SessionLimitPolicy;
+
+/// Warnings are sent this long before a limit is hit,
+/// or earlier into the limit if it's short
+const WARNING_LEAD: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionLimits {
+    pub idle_timeout: Option<Duration>,
+    pub max_duration: Option<Duration>,
+}
+
+impl SessionLimits {
+    /// Combines the policies that apply to any of the user's roles,
+    /// picking the strictest value for each limit
+    pub fn for_roles(policies: &[SessionLimitPolicy], roles: &[String]) -> Self {
+        let applicable = policies.iter().filter(|p| match &p.roles {
+            Some(policy_roles) => policy_roles.iter().any(|r| roles.contains(r)),
+            None => true,
+        });
+
+        let mut limits = Self::default();
+        for policy in applicable {
+            if let Some(seconds) = policy.idle_timeout_seconds {
+                limits.idle_timeout = strictest(limits.idle_timeout, seconds);
+            }
+            if let Some(seconds) = policy.max_duration_seconds {
+                limits.max_duration = strictest(limits.max_duration, seconds);
+            }
+        }
+        limits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idle_timeout.is_none() && self.max_duration.is_none()
+    }
+}
+
+fn strictest(current: Option<Duration>, seconds: u64) -> Option<Duration> {
+    let value = Duration::from_secs(seconds);
+    Some(current.map_or(value, |c| c.min(value)))
+}
+
+fn warning_lead(limit: Duration) -> Duration {
+    WARNING_LEAD.min(limit / 5)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionLimitEvent {
+    IdleWarning(Duration),
+    MaxDurationWarning(Duration),
+    IdleTimeout,
+    MaxDurationReached,
+}
+
+impl SessionLimitEvent {
+    /// Reason recorded for the session when it's cut off
+    pub fn end_reason(&self) -> Option<&'static str> {
+        match self {
+            Self::IdleTimeout => Some("Idle timeout"),
+            Self::MaxDurationReached => Some("Maximum session duration reached"),
+            _ => None,
+        }
+    }
+}
+
+pub struct SessionLimitTracker {
+    limits: SessionLimits,
+    started: Instant,
+    last_activity: Instant,
+    idle_warned: bool,
+    duration_warned: bool,
+}
+
+impl SessionLimitTracker {
+    pub fn new(limits: SessionLimits, now: Instant) -> Self {
+        Self {
+            limits,
+            started: now,
+            last_activity: now,
+            idle_warned: false,
+            duration_warned: false,
+        }
+    }
+
+    pub fn limits(&self) -> &SessionLimits {
+        &self.limits
+    }
+
+    /// Input from the user or output from the target - a job that keeps
+    /// printing is not idle even if nobody types
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = now;
+        self.idle_warned = false;
+    }
+
+    /// Limits too large to be represented as an [Instant] never run out
+    fn idle_deadline(&self) -> Option<Instant> {
+        self.limits
+            .idle_timeout
+            .and_then(|t| self.last_activity.checked_add(t))
+    }
+
+    fn duration_deadline(&self) -> Option<Instant> {
+        self.limits
+            .max_duration
+            .and_then(|t| self.started.checked_add(t))
+    }
+
+    /// When the session has to be cut off if nothing else happens
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match (self.idle_deadline(), self.duration_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// When [SessionLimitTracker::check] should be called next
+    pub fn next_check(&self) -> Option<Instant> {
+        let idle =
+            self.limits.idle_timeout.zip(self.idle_deadline()).map(
+                |(timeout, deadline)| match self.idle_warned {
+                    true => deadline,
+                    false => deadline - warning_lead(timeout),
+                },
+            );
+        let duration = self.limits.max_duration.zip(self.duration_deadline()).map(
+            |(max, deadline)| match self.duration_warned {
+                true => deadline,
+                false => deadline - warning_lead(max),
+            },
+        );
+        match (idle, duration) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn check(&mut self, now: Instant) -> Option<SessionLimitEvent> {
+        if let Some(deadline) = self.duration_deadline() {
+            if now >= deadline {
+                return Some(SessionLimitEvent::MaxDurationReached);
+            }
+        }
+        if let Some(deadline) = self.idle_deadline() {
+            if now >= deadline {
+                return Some(SessionLimitEvent::IdleTimeout);
+            }
+        }
+        if let (Some(max), Some(deadline)) = (self.limits.max_duration, self.duration_deadline()) {
+            if !self.duration_warned && now >= deadline - warning_lead(max) {
+                self.duration_warned = true;
+                return Some(SessionLimitEvent::MaxDurationWarning(deadline - now));
+            }
+        }
+        if let (Some(timeout), Some(deadline)) = (self.limits.idle_timeout, self.idle_deadline()) {
+            if !self.idle_warned && now >= deadline - warning_lead(timeout) {
+                self.idle_warned = true;
+                return Some(SessionLimitEvent::IdleWarning(deadline - now));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_session_limits() {
+        let policies = vec![
+            SessionLimitPolicy {
+                roles: None,
+                idle_timeout_seconds: Some(600),
+                max_duration_seconds: None,
+            },
+            SessionLimitPolicy {
+                roles: Some(vec!["contractors".to_owned()]),
+                idle_timeout_seconds: Some(300),
+                max_duration_seconds: Some(3600),
+            },
+        ];
+        assert_eq!(
+            SessionLimits::for_roles(&policies, &["admins".to_owned()]),
+            SessionLimits {
+                idle_timeout: Some(Duration::from_secs(600)),
+                max_duration: None,
+            }
+        );
+        let limits = SessionLimits::for_roles(&policies, &["contractors".to_owned()]);
+        assert_eq!(limits.idle_timeout, Some(Duration::from_secs(300)));
+
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut tracker = SessionLimitTracker::new(limits, start);
+
+        assert_eq!(tracker.next_check(), Some(at(240)));
+        assert_eq!(tracker.check(at(100)), None);
+        assert_eq!(
+            tracker.check(at(240)),
+            Some(SessionLimitEvent::IdleWarning(Duration::from_secs(60)))
+        );
+        assert_eq!(tracker.next_check(), Some(at(300)));
+
+        tracker.activity(at(250));
+        assert_eq!(tracker.next_deadline(), Some(at(550)));
+        assert_eq!(tracker.check(at(550)), Some(SessionLimitEvent::IdleTimeout));
+
+        tracker.activity(at(3299));
+        assert_eq!(
+            tracker.check(at(3300)),
+            Some(SessionLimitEvent::MaxDurationWarning(Duration::from_secs(
+                300
+            )))
+        );
+        assert_eq!(
+            tracker.check(at(3600)),
+            Some(SessionLimitEvent::MaxDurationReached)
+        );
+    }
+
+    #[test]
+    fn target_output_keeps_the_session_alive() {
+        let limits = SessionLimits {
+            idle_timeout: Some(Duration::from_secs(300)),
+            max_duration: None,
+        };
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut tracker = SessionLimitTracker::new(limits, start);
+
+        // A long-running exec job printing progress once a minute, with no input
+        for minute in 1..=60 {
+            tracker.activity(at(minute * 60));
+            assert_eq!(tracker.check(at(minute * 60 + 30)), None);
+        }
+        assert_eq!(
+            tracker.check(at(3600 + 300)),
+            Some(SessionLimitEvent::IdleTimeout)
+        );
+    }
+
+    #[test]
+    fn huge_limits_never_run_out() {
+        let limits = SessionLimits {
+            idle_timeout: Some(Duration::from_secs(u64::MAX)),
+            max_duration: Some(Duration::from_secs(u64::MAX)),
+        };
+        let start = Instant::now();
+        let mut tracker = SessionLimitTracker::new(limits, start);
+        assert_eq!(tracker.next_deadline(), None);
+        assert_eq!(tracker.next_check(), None);
+        assert_eq!(
+            tracker.check(start + Duration::from_secs(86400 * 365)),
+            None
+        );
+    }
+}
@@ -114,6 +114,7 @@ pub struct SessionState {
     pub remote_address: Option<SocketAddr>,
     pub username: Option<String>,
     pub target: Option<Target>,
+    pub justification: Option<String>,
     pub handle: Box<dyn SessionHandle + Send>,
     change_sender: broadcast::Sender<()>,
 }
@@ -129,6 +130,7 @@ impl SessionState {
             remote_address: init.remote_address,
             username: None,
             target: None,
+            justification: None,
             handle: init.handle,
             change_sender,
         }
@@ -1,7 +1,9 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use bytes::{Buf, Bytes};
 
+use crate::err_protocol;
 use crate::error::Error;
 use crate::io::{BufExt, BufMutExt, Decode, Encode};
 use crate::mysql::io::{MySqlBufExt, MySqlBufMutExt};
@@ -30,6 +32,9 @@ pub struct HandshakeResponse {
 
     /// Opaque authentication response
     pub auth_response: Option<Bytes>,
+
+    /// Connection attributes sent by the client
+    pub attributes: HashMap<String, String>,
 }
 
 impl Encode<'_, Capabilities> for HandshakeResponse {
@@ -80,6 +85,15 @@ impl Encode<'_, Capabilities> for HandshakeResponse {
                 buf.push(0);
             }
         }
+
+        if capabilities.contains(Capabilities::CONNECT_ATTRS) {
+            let mut attributes = Vec::new();
+            for (key, value) in &self.attributes {
+                attributes.put_str_lenenc(key);
+                attributes.put_str_lenenc(value);
+            }
+            buf.put_bytes_lenenc(&attributes);
+        }
     }
 }
 
@@ -108,6 +122,7 @@ impl Decode<'_, &mut Capabilities> for HandshakeResponse {
                 auth_response: None,
                 auth_plugin: None,
                 database: None,
+                attributes: HashMap::new(),
             });
         }
         let username = buf.get_str_nul()?;
@@ -133,6 +148,13 @@ impl Decode<'_, &mut Capabilities> for HandshakeResponse {
             None
         };
 
+        let attributes = if partial_cap.contains(Capabilities::CONNECT_ATTRS) && buf.has_remaining()
+        {
+            decode_attributes(&mut buf)?
+        } else {
+            HashMap::new()
+        };
+
         *server_capabilities &= Capabilities::from_bits_truncate(capabilities);
 
         Ok(HandshakeResponse {
@@ -142,6 +164,88 @@ impl Decode<'_, &mut Capabilities> for HandshakeResponse {
             auth_response,
             auth_plugin,
             database,
+            attributes,
         })
     }
 }
+
+fn decode_attributes(buf: &mut Bytes) -> Result<HashMap<String, String>, Error> {
+    let len = get_attribute_len(buf)?;
+    let mut attrs = buf.split_to(len);
+    let mut attributes = HashMap::new();
+    while attrs.has_remaining() {
+        let key = get_attribute_str(&mut attrs)?;
+        let value = get_attribute_str(&mut attrs)?;
+        attributes.insert(key, value);
+    }
+    Ok(attributes)
+}
+
+fn get_attribute_str(buf: &mut Bytes) -> Result<String, Error> {
+    let len = get_attribute_len(buf)?;
+    buf.get_str(len)
+}
+
+/// Reads a length-encoded integer and checks that the data it announces is
+/// there - the client sends the attributes before authenticating, so they
+/// may be cut short anywhere
+fn get_attribute_len(buf: &mut Bytes) -> Result<usize, Error> {
+    let prefix_len = match buf.first() {
+        None => return Err(err_protocol!("connection attributes are truncated")),
+        Some(0xfc) => 3,
+        Some(0xfd) => 4,
+        Some(0xfe) => 9,
+        Some(_) => 1,
+    };
+    if buf.remaining() < prefix_len {
+        return Err(err_protocol!("connection attributes are truncated"));
+    }
+    let len = usize::try_from(buf.get_uint_lenenc())
+        .map_err(|_| err_protocol!("connection attributes are too long"))?;
+    if len > buf.remaining() {
+        return Err(err_protocol!("connection attributes are truncated"));
+    }
+    Ok(len)
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_handshake_response_attributes_roundtrip() {
+    let mut capabilities = Capabilities::PROTOCOL_41
+        | Capabilities::SECURE_CONNECTION
+        | Capabilities::PLUGIN_AUTH_LENENC_DATA
+        | Capabilities::CONNECT_ATTRS;
+
+    let response = HandshakeResponse {
+        database: None,
+        max_packet_size: 1024,
+        collation: 45,
+        username: "user".to_owned(),
+        auth_plugin: None,
+        auth_response: None,
+        attributes: HashMap::from([
+            ("_client_name".to_owned(), "libmysql".to_owned()),
+            ("justification".to_owned(), "CHG-1234".to_owned()),
+        ]),
+    };
+    let mut buf = Vec::new();
+    response.encode_with(&mut buf, capabilities);
+
+    let decoded = HandshakeResponse::decode_with(Bytes::from(buf), &mut capabilities).unwrap();
+
+    assert_eq!(decoded.username, "user");
+    assert_eq!(decoded.attributes, response.attributes);
+}
+
+#[test]
+fn test_handshake_response_truncated_attributes() {
+    // 0xfe announces an 8-byte length, but only two bytes follow
+    assert!(decode_attributes(&mut Bytes::from_static(&[0xfe, 0x01, 0x02])).is_err());
+    assert!(decode_attributes(&mut Bytes::from_static(&[0xfc, 0x01])).is_err());
+    assert!(decode_attributes(&mut Bytes::from_static(&[0x05, b'a'])).is_err());
+    assert!(decode_attributes(&mut Bytes::new()).is_err());
+
+    // The attribute block itself is complete, but the key inside it is not
+    assert!(decode_attributes(&mut Bytes::from_static(&[0x02, 0xfe, 0x01])).is_err());
+    assert!(decode_attributes(&mut Bytes::from_static(&[0x02, 0x01, b'k'])).is_err());
+}
@@ -12,6 +12,8 @@ pub enum RecordingKind {
     Terminal,
     #[sea_orm(string_value = "traffic")]
     Traffic,
+    #[sea_orm(string_value = "file_transfer")]
+    FileTransfer,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
@@ -14,6 +14,9 @@ pub struct Model {
     pub ended: Option<DateTime<Utc>>,
     pub ticket_id: Option<Uuid>,
     pub protocol: String,
+    pub end_reason: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub justification: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::ForeignKeyAction;
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "session_channels")]
+#[oai(rename = "SessionChannel")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+    pub command: Option<String>,
+    pub subsystem: Option<String>,
+    pub env: serde_json::Value,
+    pub exit_status: Option<i64>,
+    pub exit_signal: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    Session,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::Session => Entity::belongs_to(super::Session::Entity)
+                .from(Column::SessionId)
+                .to(super::Session::Column::Id)
+                .on_delete(ForeignKeyAction::Cascade)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::Session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -4,7 +4,7 @@ use serde::Serialize;
 use uuid::Uuid;
 use warpgate_common::{Target, TargetOptions};
 
-#[derive(Debug, PartialEq, Eq, Serialize, Clone, Enum, EnumIter, DeriveActiveEnum)]
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Clone, Enum, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
 pub enum TargetKind {
     #[sea_orm(string_value = "http")]
@@ -46,6 +46,9 @@ pub struct Model {
     pub name: String,
     pub kind: TargetKind,
     pub options: serde_json::Value,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub motd: Option<String>,
+    pub require_justification: bool,
 }
 
 impl Related<super::Role::Entity> for Entity {
@@ -72,6 +75,8 @@ impl TryFrom<Model> for Target {
             id: model.id,
             name: model.name,
             allow_roles: vec![],
+            motd: model.motd,
+            require_justification: model.require_justification,
             options,
         })
     }
@@ -5,6 +5,7 @@ pub mod LogEntry;
 pub mod Recording;
 pub mod Role;
 pub mod Session;
+pub mod SessionChannel;
 pub mod Target;
 pub mod TargetRoleAssignment;
 pub mod Ticket;
@@ -10,6 +10,10 @@ mod m00005_create_log_entry;
 mod m00006_add_session_protocol;
 mod m00007_targets_and_roles;
 mod m00008_users;
+mod m00009_add_session_end_reason;
+mod m00010_create_session_channel;
+mod m00011_add_target_motd;
+mod m00012_add_justification;
 
 pub struct Migrator;
 
@@ -25,6 +29,10 @@ impl MigratorTrait for Migrator {
             Box::new(m00006_add_session_protocol::Migration),
             Box::new(m00007_targets_and_roles::Migration),
             Box::new(m00008_users::Migration),
+            Box::new(m00009_add_session_end_reason::Migration),
+            Box::new(m00010_create_session_channel::Migration),
+            Box::new(m00011_add_target_motd::Migration),
+            Box::new(m00012_add_justification::Migration),
         ]
     }
 }
@@ -0,0 +1,74 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod session_channel {
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    use crate::m00002_create_session::session;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "session_channels")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        pub session_id: Uuid,
+        pub started: DateTimeUtc,
+        pub ended: Option<DateTimeUtc>,
+        pub command: Option<String>,
+        pub subsystem: Option<String>,
+        pub env: serde_json::Value,
+        pub exit_status: Option<i64>,
+        pub exit_signal: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter)]
+    pub enum Relation {
+        Session,
+    }
+
+    impl RelationTrait for Relation {
+        fn def(&self) -> RelationDef {
+            match self {
+                Self::Session => Entity::belongs_to(session::Entity)
+                    .from(Column::SessionId)
+                    .to(session::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .into(),
+            }
+        }
+    }
+
+    impl Related<session::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::Session.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00010_create_session_channel"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(session_channel::Entity))
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(session_channel::Entity).to_owned())
+            .await
+    }
+}
@@ -25,6 +25,7 @@ pub struct Info {
     external_host: Option<String>,
     ports: PortsInfo,
     authorized_via_ticket: bool,
+    auth_banner: Option<String>,
 }
 
 #[derive(ApiResponse)]
@@ -64,6 +65,7 @@ impl Api {
                 session.get_auth(),
                 Some(SessionAuthorization::Ticket { .. })
             ),
+            auth_banner: config.store.http.auth_banner.clone(),
             ports: if session.is_authenticated() {
                 PortsInfo {
                     ssh: if config.store.ssh.enable {
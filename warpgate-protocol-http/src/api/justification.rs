@@ -0,0 +1,91 @@
+use poem::session::Session;
+use poem::web::Data;
+use poem::{FromRequest, Request};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use tracing::*;
+use warpgate_common::TargetOptions;
+use warpgate_core::Services;
+
+use crate::common::{endpoint_auth, SessionAuthorization, SessionExt};
+use crate::session_handle::WarpgateServerHandleFromRequest;
+
+pub struct Api;
+
+#[derive(Object)]
+struct JustificationRequest {
+    target_name: String,
+    justification: String,
+}
+
+#[derive(ApiResponse)]
+enum JustificationResponse {
+    #[oai(status = 201)]
+    Accepted,
+
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/justification",
+        method = "post",
+        operation_id = "provide_justification",
+        transform = "endpoint_auth"
+    )]
+    async fn api_provide_justification(
+        &self,
+        req: &Request,
+        session: &Session,
+        services: Data<&Services>,
+        auth: Data<&SessionAuthorization>,
+        body: Json<JustificationRequest>,
+    ) -> poem::Result<JustificationResponse> {
+        let justification = body.justification.trim();
+        if justification.is_empty() {
+            return Ok(JustificationResponse::BadRequest(Json(
+                "justification".into(),
+            )));
+        }
+
+        let authorized = match *auth {
+            SessionAuthorization::Ticket { target_name, .. } => *target_name == body.target_name,
+            SessionAuthorization::User(username) => {
+                services
+                    .config_provider
+                    .lock()
+                    .await
+                    .authorize_target(username, &body.target_name)
+                    .await?
+            }
+        };
+        let is_http_target = services
+            .config_provider
+            .lock()
+            .await
+            .list_targets()
+            .await?
+            .iter()
+            .any(|t| t.name == body.target_name && matches!(t.options, TargetOptions::Http(_)));
+        if !authorized || !is_http_target {
+            return Ok(JustificationResponse::NotFound);
+        }
+
+        info!(target=%body.target_name, %justification, "Access justification provided");
+        session.set_justification(body.target_name.clone(), justification.to_owned());
+        if let Ok(handle) = WarpgateServerHandleFromRequest::from_request_without_body(req).await {
+            handle
+                .lock()
+                .await
+                .set_justification(justification.to_owned())
+                .await?;
+        }
+
+        Ok(JustificationResponse::Accepted)
+    }
+}
@@ -2,6 +2,7 @@ use poem_openapi::OpenApi;
 
 pub mod auth;
 pub mod info;
+pub mod justification;
 pub mod sso_provider_detail;
 pub mod sso_provider_list;
 pub mod targets_list;
@@ -10,6 +11,7 @@ pub fn get() -> impl OpenApi {
     (
         auth::Api,
         info::Api,
+        justification::Api,
         targets_list::Api,
         sso_provider_list::Api,
         sso_provider_detail::Api,
@@ -10,7 +10,7 @@ use tracing::*;
 use warpgate_common::{Target, TargetHTTPOptions, TargetOptions};
 use warpgate_core::{Services, WarpgateServerHandle};
 
-use crate::common::{SessionAuthorization, SessionExt};
+use crate::common::{justification_redirect, SessionAuthorization, SessionExt};
 use crate::proxy::{proxy_normal_request, proxy_websocket_request};
 
 #[derive(Deserialize)]
@@ -37,10 +37,19 @@ pub async fn catchall_endpoint(
         return Ok(target_select_redirect());
     };
 
+    let justification = session.get_justification(&target.name);
+    if target.require_justification && justification.is_none() {
+        return Ok(justification_redirect(req, &target.name));
+    }
+
     session.set_target_name(target.name.clone());
 
     if let Some(server_handle) = server_handle {
-        server_handle.lock().await.set_target(&target).await?;
+        let server_handle = server_handle.lock().await;
+        server_handle.set_target(&target).await?;
+        if let Some(justification) = justification {
+            server_handle.set_justification(justification).await?;
+        }
     }
 
     let span = info_span!("", target=%target.name);
@@ -50,7 +59,7 @@ pub async fn catchall_endpoint(
             .instrument(span)
             .await?
             .into_response(),
-        None => proxy_normal_request(req, body, &options)
+        None => proxy_normal_request(req, body, &options, true)
             .instrument(span)
             .await?
             .into_response(),
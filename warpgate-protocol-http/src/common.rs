@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use http::StatusCode;
@@ -10,7 +11,7 @@ use tokio::sync::Mutex;
 use uuid::Uuid;
 use warpgate_common::auth::{AuthState, CredentialKind};
 use warpgate_common::{ProtocolName, TargetOptions, WarpgateError};
-use warpgate_core::{AuthStateStore, Services};
+use warpgate_core::{AdminUsername, AuthStateStore, Services};
 
 use crate::session::SessionStore;
 
@@ -18,6 +19,7 @@ pub const PROTOCOL_NAME: ProtocolName = "HTTP";
 static TARGET_SESSION_KEY: &str = "target_name";
 static AUTH_SESSION_KEY: &str = "auth";
 static AUTH_STATE_ID_SESSION_KEY: &str = "auth_state_id";
+static JUSTIFICATIONS_SESSION_KEY: &str = "justifications";
 pub static SESSION_COOKIE_NAME: &str = "warpgate-http-session";
 
 pub trait SessionExt {
@@ -29,6 +31,8 @@ pub trait SessionExt {
     fn set_auth(&self, auth: SessionAuthorization);
     fn get_auth_state_id(&self) -> Option<AuthStateId>;
     fn clear_auth_state(&self);
+    fn get_justification(&self, target_name: &str) -> Option<String>;
+    fn set_justification(&self, target_name: String, justification: String);
 }
 
 impl SessionExt for Session {
@@ -63,6 +67,18 @@ impl SessionExt for Session {
     fn clear_auth_state(&self) {
         self.remove(AUTH_STATE_ID_SESSION_KEY)
     }
+
+    fn get_justification(&self, target_name: &str) -> Option<String> {
+        self.get::<HashMap<String, String>>(JUSTIFICATIONS_SESSION_KEY)
+            .and_then(|mut j| j.remove(target_name))
+    }
+
+    fn set_justification(&self, target_name: String, justification: String) {
+        let mut justifications: HashMap<String, String> =
+            self.get(JUSTIFICATIONS_SESSION_KEY).unwrap_or_default();
+        justifications.insert(target_name, justification);
+        self.set(JUSTIFICATIONS_SESSION_KEY, justifications);
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -112,7 +128,8 @@ pub fn endpoint_admin_auth<E: Endpoint + 'static>(e: E) -> impl Endpoint {
     e.around(|ep, req| async move {
         let auth: Data<&SessionAuthorization> = <_>::from_request_without_body(&req).await?;
         if is_user_admin(&req, &auth).await? {
-            return Ok(ep.call(req).await?.into_response());
+            let admin = AdminUsername(auth.username().clone());
+            return Ok(ep.data(admin).call(req).await?.into_response());
         }
         Err(poem::Error::from_status(StatusCode::UNAUTHORIZED))
     })
@@ -175,6 +192,22 @@ pub fn gateway_redirect(req: &Request) -> Response {
     Redirect::temporary(path).into_response()
 }
 
+pub fn justification_redirect(req: &Request, target_name: &str) -> Response {
+    let path = req
+        .original_uri()
+        .path_and_query()
+        .map(|p| p.to_string())
+        .unwrap_or_else(|| "".into());
+
+    let path = format!(
+        "/@warpgate#/justification?target={}&next={}",
+        utf8_percent_encode(target_name, NON_ALPHANUMERIC),
+        utf8_percent_encode(&path, NON_ALPHANUMERIC),
+    );
+
+    Redirect::temporary(path).into_response()
+}
+
 pub async fn get_auth_state_for_request(
     username: &str,
     session: &Session,
@@ -0,0 +1,130 @@
+//! HTTP targets reached through connections forwarded by other protocol
+//! servers, e.g. `ssh -L 8080:<target name>:0`. The user and the target are
+//! already known, so there is no cookie session or target selection - every
+//! request on the connection is proxied to the forwarded target.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use poem::web::websocket::WebSocket;
+use poem::web::{Data, LocalAddr, RemoteAddr};
+use poem::{handler, Addr, Body, Endpoint, EndpointExt, IntoResponse, Request, Response};
+use tokio::io::DuplexStream;
+use tracing::*;
+use warpgate_common::{TargetHTTPOptions, TargetOptions};
+use warpgate_core::{ForwardedConnection, ForwardedConnectionHandler, Services, SessionStateInit};
+
+use crate::common::PROTOCOL_NAME;
+use crate::proxy::{proxy_normal_request, proxy_websocket_request};
+use crate::session_handle::HttpSessionHandle;
+
+pub struct HTTPForwardedConnectionHandler {
+    services: Services,
+}
+
+impl HTTPForwardedConnectionHandler {
+    pub fn new(services: &Services) -> Self {
+        HTTPForwardedConnectionHandler {
+            services: services.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl ForwardedConnectionHandler for HTTPForwardedConnectionHandler {
+    async fn handle_forwarded_connection(
+        &self,
+        stream: DuplexStream,
+        connection: ForwardedConnection,
+    ) -> Result<()> {
+        let ForwardedConnection {
+            username,
+            target,
+            remote_address,
+            justification,
+        } = connection;
+        let TargetOptions::Http(ref options) = target.options else {
+            anyhow::bail!("Not an HTTP target");
+        };
+        if target.require_justification && justification.is_none() {
+            warn!(target=%target.name, "Forwarded connection refused: the target requires a justification");
+            return Ok(());
+        }
+
+        let (session_handle, mut session_handle_rx) = HttpSessionHandle::new();
+        let server_handle = self
+            .services
+            .state
+            .lock()
+            .await
+            .register_session(
+                &PROTOCOL_NAME,
+                SessionStateInit {
+                    remote_address: Some(remote_address),
+                    handle: Box::new(session_handle),
+                },
+            )
+            .await?;
+
+        let span = {
+            let handle = server_handle.lock().await;
+            handle.set_username(username.clone()).await?;
+            handle.set_target(&target).await?;
+            if let Some(justification) = justification {
+                handle.set_justification(justification).await?;
+            }
+            let client_ip = remote_address.ip().to_string();
+            info_span!("HTTP", session=%handle.id(), session_username=%username, %client_ip, target=%target.name)
+        };
+
+        let endpoint = Arc::new(
+            forwarded_endpoint
+                .data(options.clone())
+                .data(self.services.clone())
+                .map_to_response(),
+        );
+        let service = hyper::service::service_fn(move |req: hyper::Request<hyper::Body>| {
+            let endpoint = endpoint.clone();
+            async move {
+                let req: Request = (
+                    req,
+                    LocalAddr(Addr::Custom("forwarded", "".into())),
+                    RemoteAddr(Addr::SocketAddr(remote_address)),
+                    http::uri::Scheme::HTTP,
+                )
+                    .into();
+                Ok::<_, Infallible>(hyper::Response::from(endpoint.get_response(req).await))
+            }
+        });
+        let http_connection = hyper::server::conn::Http::new()
+            .serve_connection(stream, service)
+            .with_upgrades();
+
+        tokio::select! {
+            result = http_connection.instrument(span.clone()) => result?,
+            _ = session_handle_rx.recv() => {
+                info!(parent: &span, "Session closed by admin");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[handler]
+async fn forwarded_endpoint(
+    req: &Request,
+    ws: Option<WebSocket>,
+    body: Body,
+    options: Data<&TargetHTTPOptions>,
+) -> poem::Result<Response> {
+    Ok(match ws {
+        Some(ws) => proxy_websocket_request(req, ws, &options)
+            .await?
+            .into_response(),
+        None => proxy_normal_request(req, body, &options, false)
+            .await?
+            .into_response(),
+    })
+}
@@ -3,6 +3,7 @@ pub mod api;
 mod catchall;
 mod common;
 mod error;
+mod forwarded;
 mod logging;
 mod middleware;
 mod proxy;
@@ -18,6 +19,7 @@ use anyhow::{Context, Result};
 use async_trait::async_trait;
 use common::page_admin_auth;
 pub use common::PROTOCOL_NAME;
+pub use forwarded::HTTPForwardedConnectionHandler;
 use http::HeaderValue;
 use logging::{get_client_ip, log_request_result, span_for_request};
 use poem::endpoint::{EmbeddedFileEndpoint, EmbeddedFilesEndpoint};
@@ -214,7 +216,7 @@ impl ProtocolServer for HTTPProtocolServer {
             ));
         };
         let request = poem::Request::builder().uri_str("http://host/").finish();
-        crate::proxy::proxy_normal_request(&request, poem::Body::empty(), &options)
+        crate::proxy::proxy_normal_request(&request, poem::Body::empty(), &options, true)
             .await
             .map_err(|e| TargetTestError::ConnectionError(format!("{e}")))?;
         Ok(())
@@ -231,6 +231,7 @@ pub async fn proxy_normal_request(
     req: &Request,
     body: Body,
     options: &TargetHTTPOptions,
+    embed_ui: bool,
 ) -> poem::Result<Response> {
     let uri = construct_uri(req, options, false)?;
 
@@ -289,7 +290,7 @@ pub async fn proxy_normal_request(
     let mut response: Response = "".into();
 
     copy_client_response(&client_response, &mut response);
-    copy_client_body(client_response, &mut response).await?;
+    copy_client_body(client_response, &mut response, embed_ui).await?;
 
     log_request_result(
         req.method(),
@@ -305,8 +306,10 @@ pub async fn proxy_normal_request(
 async fn copy_client_body(
     client_response: reqwest::Response,
     response: &mut Response,
+    embed_ui: bool,
 ) -> Result<()> {
-    if response.content_type().map(|c| c.starts_with("text/html")) == Some(true)
+    if embed_ui
+        && response.content_type().map(|c| c.starts_with("text/html")) == Some(true)
         && response.status() == 200
     {
         copy_client_body_and_embed(client_response, response).await?;
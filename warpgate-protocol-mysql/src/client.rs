@@ -113,6 +113,7 @@ impl MySqlClient {
             database: options.database,
             max_packet_size: options.max_packet_size,
             username: target.username.clone(),
+            attributes: Default::default(),
         };
 
         if handshake.auth_plugin == Some(AuthPlugin::MySqlNativePassword) {
@@ -3,6 +3,9 @@ use warpgate_common::ProtocolName;
 
 pub const PROTOCOL_NAME: ProtocolName = "MySQL";
 
+/// Connection attribute that carries the access justification
+pub const JUSTIFICATION_ATTRIBUTE: &str = "justification";
+
 pub fn compute_auth_challenge_response(
     challenge: [u8; 20],
     password: &str,
@@ -15,12 +15,17 @@ use async_trait::async_trait;
 use client::{ConnectionOptions, MySqlClient};
 use rustls::server::NoClientAuth;
 use rustls::ServerConfig;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
 use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Mutex};
 use tracing::*;
 use warpgate_common::{
     Target, TargetOptions, TlsCertificateAndPrivateKey, TlsCertificateBundle, TlsPrivateKey,
 };
-use warpgate_core::{ProtocolServer, Services, SessionStateInit, TargetTestError};
+use warpgate_core::{
+    ForwardedConnection, ForwardedConnectionHandler, ProtocolServer, Services, SessionStateInit,
+    TargetTestError, WarpgateServerHandle,
+};
 
 use crate::session::MySqlSession;
 use crate::session_handle::MySqlSessionHandle;
@@ -77,35 +82,11 @@ impl ProtocolServer for MySQLProtocolServer {
             let tls_config = tls_config.clone();
             let services = self.services.clone();
             tokio::spawn(async move {
-                let (session_handle, mut abort_rx) = MySqlSessionHandle::new();
-
-                let server_handle = services
-                    .state
-                    .lock()
-                    .await
-                    .register_session(
-                        &crate::common::PROTOCOL_NAME,
-                        SessionStateInit {
-                            remote_address: Some(remote_address),
-                            handle: Box::new(session_handle),
-                        },
-                    )
-                    .await?;
-
+                let (server_handle, abort_rx) = register_session(&services, remote_address).await?;
                 let session =
                     MySqlSession::new(server_handle, services, stream, tls_config, remote_address)
                         .await;
-                let span = session.make_logging_span();
-                tokio::select! {
-                    result = session.run().instrument(span) => match result {
-                        Ok(_) => info!("Session ended"),
-                        Err(e) => error!(error=%e, "Session failed"),
-                    },
-                    _ = abort_rx.recv() => {
-                        warn!("Session aborted by admin");
-                    },
-                }
-
+                run_session(session, abort_rx).await;
                 Ok::<(), anyhow::Error>(())
             });
         }
@@ -124,6 +105,76 @@ impl ProtocolServer for MySQLProtocolServer {
     }
 }
 
+async fn register_session(
+    services: &Services,
+    remote_address: SocketAddr,
+) -> Result<(
+    Arc<Mutex<WarpgateServerHandle>>,
+    mpsc::UnboundedReceiver<()>,
+)> {
+    let (session_handle, abort_rx) = MySqlSessionHandle::new();
+    let server_handle = services
+        .state
+        .lock()
+        .await
+        .register_session(
+            &crate::common::PROTOCOL_NAME,
+            SessionStateInit {
+                remote_address: Some(remote_address),
+                handle: Box::new(session_handle),
+            },
+        )
+        .await?;
+    Ok((server_handle, abort_rx))
+}
+
+async fn run_session<S>(session: MySqlSession<S>, mut abort_rx: mpsc::UnboundedReceiver<()>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let span = session.make_logging_span();
+    tokio::select! {
+        result = session.run().instrument(span) => match result {
+            Ok(_) => info!("Session ended"),
+            Err(e) => error!(error=%e, "Session failed"),
+        },
+        _ = abort_rx.recv() => {
+            warn!("Session aborted by admin");
+        },
+    }
+}
+
+/// Serves MySQL targets over connections forwarded by other protocol
+/// servers, e.g. `ssh -L 3306:<target name>:0`
+pub struct MySQLForwardedConnectionHandler {
+    services: Services,
+}
+
+impl MySQLForwardedConnectionHandler {
+    pub fn new(services: &Services) -> Self {
+        MySQLForwardedConnectionHandler {
+            services: services.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl ForwardedConnectionHandler for MySQLForwardedConnectionHandler {
+    async fn handle_forwarded_connection(
+        &self,
+        stream: DuplexStream,
+        connection: ForwardedConnection,
+    ) -> Result<()> {
+        let (server_handle, abort_rx) =
+            register_session(&self.services, connection.remote_address).await?;
+        let session =
+            MySqlSession::new_forwarded(server_handle, self.services.clone(), stream, connection)
+                .await;
+        run_session(session, abort_rx).await;
+        Ok(())
+    }
+}
+
 impl Debug for MySQLProtocolServer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "MySQLProtocolServer")
@@ -1,9 +1,11 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use bytes::{Buf, Bytes, BytesMut};
 use rand::Rng;
 use rustls::ServerConfig;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 use tracing::*;
@@ -11,7 +13,10 @@ use uuid::Uuid;
 use warpgate_common::auth::{AuthCredential, AuthResult, AuthSelector, CredentialKind};
 use warpgate_common::helpers::rng::get_crypto_rng;
 use warpgate_common::{Secret, TargetMySqlOptions, TargetOptions};
-use warpgate_core::{authorize_ticket, consume_ticket, Services, WarpgateServerHandle};
+use warpgate_core::{
+    authorize_ticket, consume_ticket, ForwardedConnection, Services, SessionLimitTracker,
+    SessionLimits, WarpgateServerHandle,
+};
 use warpgate_database_protocols::io::{BufExt, Decode};
 use warpgate_database_protocols::mysql::protocol::auth::AuthPlugin;
 use warpgate_database_protocols::mysql::protocol::connect::{
@@ -22,55 +27,110 @@ use warpgate_database_protocols::mysql::protocol::text::Query;
 use warpgate_database_protocols::mysql::protocol::Capabilities;
 
 use crate::client::{ConnectionOptions, MySqlClient};
+use crate::common::JUSTIFICATION_ATTRIBUTE;
 use crate::error::MySqlError;
 use crate::stream::MySqlStream;
 
-pub struct MySqlSession {
-    stream: MySqlStream<tokio_rustls::server::TlsStream<TcpStream>>,
+pub struct MySqlSession<S = TcpStream>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    stream: MySqlStream<tokio_rustls::server::TlsStream<S>, S>,
     capabilities: Capabilities,
     challenge: [u8; 20],
     username: Option<String>,
     database: Option<String>,
-    tls_config: Arc<ServerConfig>,
+    tls_config: Option<Arc<ServerConfig>>,
     server_handle: Arc<Mutex<WarpgateServerHandle>>,
     id: Uuid,
     services: Services,
     remote_address: SocketAddr,
+    forwarded: Option<ForwardedConnection>,
+    justification: Option<String>,
 }
 
-impl MySqlSession {
+impl<S> MySqlSession<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
     pub async fn new(
         server_handle: Arc<Mutex<WarpgateServerHandle>>,
         services: Services,
-        stream: TcpStream,
+        stream: S,
         tls_config: ServerConfig,
         remote_address: SocketAddr,
+    ) -> Self {
+        Self::create(
+            server_handle,
+            services,
+            stream,
+            Some(Arc::new(tls_config)),
+            remote_address,
+            None,
+        )
+        .await
+    }
+
+    /// Forwarded connections arrive already encrypted and authenticated by
+    /// the protocol that carries them, so TLS and the password are skipped
+    pub async fn new_forwarded(
+        server_handle: Arc<Mutex<WarpgateServerHandle>>,
+        services: Services,
+        stream: S,
+        connection: ForwardedConnection,
+    ) -> Self {
+        let remote_address = connection.remote_address;
+        Self::create(
+            server_handle,
+            services,
+            stream,
+            None,
+            remote_address,
+            Some(connection),
+        )
+        .await
+    }
+
+    async fn create(
+        server_handle: Arc<Mutex<WarpgateServerHandle>>,
+        services: Services,
+        stream: S,
+        tls_config: Option<Arc<ServerConfig>>,
+        remote_address: SocketAddr,
+        forwarded: Option<ForwardedConnection>,
     ) -> Self {
         let id = server_handle.lock().await.id();
+        let mut capabilities = Capabilities::PROTOCOL_41
+            | Capabilities::PLUGIN_AUTH
+            | Capabilities::FOUND_ROWS
+            | Capabilities::LONG_FLAG
+            | Capabilities::NO_SCHEMA
+            | Capabilities::PLUGIN_AUTH_LENENC_DATA
+            | Capabilities::CONNECT_WITH_DB
+            | Capabilities::SESSION_TRACK
+            | Capabilities::IGNORE_SPACE
+            | Capabilities::INTERACTIVE
+            | Capabilities::TRANSACTIONS
+            | Capabilities::DEPRECATE_EOF
+            | Capabilities::SECURE_CONNECTION
+            | Capabilities::CONNECT_ATTRS
+            | Capabilities::SSL;
+        if tls_config.is_none() {
+            capabilities.remove(Capabilities::SSL);
+        }
         Self {
             services,
             stream: MySqlStream::new(stream),
-            capabilities: Capabilities::PROTOCOL_41
-                | Capabilities::PLUGIN_AUTH
-                | Capabilities::FOUND_ROWS
-                | Capabilities::LONG_FLAG
-                | Capabilities::NO_SCHEMA
-                | Capabilities::PLUGIN_AUTH_LENENC_DATA
-                | Capabilities::CONNECT_WITH_DB
-                | Capabilities::SESSION_TRACK
-                | Capabilities::IGNORE_SPACE
-                | Capabilities::INTERACTIVE
-                | Capabilities::TRANSACTIONS
-                | Capabilities::DEPRECATE_EOF
-                | Capabilities::SECURE_CONNECTION
-                | Capabilities::SSL,
+            capabilities,
             challenge: get_crypto_rng().gen(),
-            tls_config: Arc::new(tls_config),
+            tls_config,
             username: None,
             database: None,
             server_handle,
             id,
             remote_address,
+            forwarded,
+            justification: None,
         }
     }
 
@@ -112,11 +172,14 @@ impl MySqlSession {
             trace!(?resp, "Handshake response");
             info!(capabilities=?self.capabilities, username=%resp.username, "User handshake");
 
+            let Some(tls_config) = self.tls_config.clone() else {
+                break resp;
+            };
             if self.capabilities.contains(Capabilities::SSL) {
                 if self.stream.is_tls() {
                     break resp;
                 }
-                self.stream = self.stream.upgrade(self.tls_config.clone()).await?;
+                self.stream = self.stream.upgrade(tls_config).await?;
                 continue;
             } else {
                 self.send_error(1002, "Warpgate requires TLS - please enable it in your client: add `--ssl` on the CLI or add `?sslMode=PREFERRED` to your database URI").await?;
@@ -124,6 +187,14 @@ impl MySqlSession {
             }
         };
 
+        if let Some(connection) = self.forwarded.take() {
+            info!(username=%connection.username, "Forwarded connection");
+            self.justification = connection.justification;
+            return self
+                .run_authorized(resp, connection.username, connection.target.name)
+                .await;
+        }
+
         if resp.auth_plugin == Some(AuthPlugin::MySqlClearPassword) {
             if let Some(mut response) = resp.auth_response.clone() {
                 let password = Secret::new(response.get_str_nul()?);
@@ -147,6 +218,16 @@ impl MySqlSession {
         self.run_authorization(resp, password).await
     }
 
+    /// MySQL has no pre-auth message, so the configured notice is shown
+    /// along with the access denied error instead
+    async fn access_denied_message(&self) -> String {
+        let message = "Warpgate access denied".to_owned();
+        match self.services.config.lock().await.store.mysql.auth_banner {
+            Some(ref banner) => format!("{message}\n{}", banner.trim_end()),
+            None => message,
+        }
+    }
+
     async fn send_error(&mut self, code: u16, message: &str) -> Result<(), MySqlError> {
         self.stream.push(
             &ErrPacket {
@@ -167,11 +248,14 @@ impl MySqlSession {
     ) -> Result<(), MySqlError> {
         let selector: AuthSelector = (&handshake.username).into();
 
-        async fn fail(this: &mut MySqlSession) -> Result<(), MySqlError> {
+        async fn fail<S: AsyncRead + AsyncWrite + Unpin + Send>(
+            this: &mut MySqlSession<S>,
+        ) -> Result<(), MySqlError> {
+            let error_message = this.access_denied_message().await;
             this.stream.push(
                 &ErrPacket {
                     error_code: 1,
-                    error_message: "Warpgate access denied".to_owned(),
+                    error_message,
                     sql_state: None,
                 },
                 (),
@@ -295,10 +379,11 @@ impl MySqlSession {
 
         let Some((target, mysql_options)) = target else {
             warn!("Selected target not found");
+            let error_message = self.access_denied_message().await;
             self.stream.push(
                 &ErrPacket {
                     error_code: 1,
-                    error_message: "Warpgate access denied".to_owned(),
+                    error_message,
                     sql_state: None,
                 },
                 (),
@@ -307,19 +392,54 @@ impl MySqlSession {
             return Ok(());
         };
 
+        let justification = self.justification.take().or_else(|| {
+            handshake
+                .attributes
+                .get(JUSTIFICATION_ATTRIBUTE)
+                .map(|j| j.trim().to_owned())
+                .filter(|j| !j.is_empty())
+        });
+        if target.require_justification && justification.is_none() {
+            warn!("No justification provided");
+            self.send_error(
+                1045,
+                &format!("Warpgate: this target requires an access justification - pass it in the `{JUSTIFICATION_ATTRIBUTE}` connection attribute"),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let roles = self
+            .services
+            .config_provider
+            .lock()
+            .await
+            .list_user_roles(&username)
+            .await?;
+        let limits = SessionLimits::for_roles(
+            mysql_options.session_limits.as_deref().unwrap_or_default(),
+            &roles,
+        );
+
         {
             let handle = self.server_handle.lock().await;
             handle.set_username(username).await?;
             handle.set_target(&target).await?;
+            if let Some(justification) = justification {
+                info!(%justification, "Access justification provided");
+                handle.set_justification(justification).await?;
+            }
         }
 
-        self.run_authorized_inner(handshake, mysql_options).await
+        self.run_authorized_inner(handshake, mysql_options, limits)
+            .await
     }
 
     async fn run_authorized_inner(
         mut self,
         handshake: HandshakeResponse,
         options: TargetMySqlOptions,
+        limits: SessionLimits,
     ) -> Result<(), MySqlError> {
         self.database = handshake.database.clone();
         self.username = Some(handshake.username);
@@ -346,12 +466,49 @@ impl MySqlSession {
             x => x,
         }?;
 
+        let mut limit_tracker = if limits.is_empty() {
+            None
+        } else {
+            info!(idle_timeout=?limits.idle_timeout, max_duration=?limits.max_duration, "Session limits apply");
+            Some(SessionLimitTracker::new(limits, Instant::now()))
+        };
+
         loop {
             self.stream.reset_sequence_id();
             client.stream.reset_sequence_id();
-            let Some(payload) = self.stream.recv().await? else {
+            let deadline = limit_tracker.as_ref().and_then(|t| t.next_deadline());
+            let payload = match deadline {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(deadline.into(), self.stream.recv()).await {
+                        Ok(payload) => payload?,
+                        Err(_) => {
+                            let reason = limit_tracker
+                                .as_mut()
+                                .and_then(|t| t.check(Instant::now()))
+                                .and_then(|e| e.end_reason());
+                            let Some(reason) = reason else {
+                                continue;
+                            };
+                            info!(%reason, "Session closed by a session limit");
+                            self.server_handle
+                                .lock()
+                                .await
+                                .set_end_reason(reason)
+                                .await?;
+                            // ER_CLIENT_INTERACTION_TIMEOUT
+                            self.send_error(4031, reason).await?;
+                            break;
+                        }
+                    }
+                }
+                None => self.stream.recv().await?,
+            };
+            let Some(payload) = payload else {
                 break;
             };
+            if let Some(tracker) = limit_tracker.as_mut() {
+                tracker.activity(Instant::now());
+            }
             trace!(?payload, "server got packet");
 
             let com = payload.first();
@@ -16,23 +16,23 @@ pub enum MySqlStreamError {
     Io(#[from] std::io::Error),
 }
 
-pub struct MySqlStream<TS>
+pub struct MySqlStream<TS, S = TcpStream>
 where
-    TcpStream: UpgradableStream<TS>,
+    S: AsyncRead + AsyncWrite + Unpin + UpgradableStream<TS>,
     TS: AsyncRead + AsyncWrite + Unpin,
 {
-    stream: MaybeTlsStream<TcpStream, TS>,
+    stream: MaybeTlsStream<S, TS>,
     codec: PacketCodec,
     inbound_buffer: BytesMut,
     outbound_buffer: BytesMut,
 }
 
-impl<TS> MySqlStream<TS>
+impl<TS, S> MySqlStream<TS, S>
 where
-    TcpStream: UpgradableStream<TS>,
+    S: AsyncRead + AsyncWrite + Unpin + UpgradableStream<TS>,
     TS: AsyncRead + AsyncWrite + Unpin,
 {
-    pub fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: S) -> Self {
         Self {
             stream: MaybeTlsStream::new(stream),
             codec: PacketCodec::default(),
@@ -84,7 +84,7 @@ where
 
     pub async fn upgrade(
         mut self,
-        config: <TcpStream as UpgradableStream<TS>>::UpgradeConfig,
+        config: <S as UpgradableStream<TS>>::UpgradeConfig,
     ) -> Result<Self, MaybeTlsStreamError> {
         self.stream = self.stream.upgrade(config).await?;
         Ok(self)
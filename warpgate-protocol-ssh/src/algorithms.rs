@@ -0,0 +1,352 @@
+//! Configurable algorithm lists and reporting of the negotiated algorithms.
+//! russh doesn't expose the negotiation result, so the peer's KEXINIT
+//! is read off the wire and matched against our own lists instead.
+
+use std::borrow::Cow;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use russh::keys::key::{self, KeyPair};
+use russh::{cipher, kex, Preferred};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::oneshot;
+use tracing::*;
+use warpgate_common::SshAlgorithms;
+
+/// Pseudo-algorithms that only signal protocol extensions
+const KEX_EXTENSIONS: &[kex::Name] = &[
+    kex::EXTENSION_SUPPORT_AS_CLIENT,
+    kex::EXTENSION_SUPPORT_AS_SERVER,
+    kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT,
+    kex::EXTENSION_OPENSSH_STRICT_KEX_AS_SERVER,
+];
+
+/// Ciphers that authenticate the data themselves and don't use a MAC
+const AEAD_CIPHERS: &[cipher::Name] = &[cipher::CHACHA20_POLY1305, cipher::AES_256_GCM];
+
+/// Stop looking for the KEXINIT if it hasn't shown up in this many bytes
+const MAX_SNIFF_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unsupported SSH {kind} algorithm: {name}")]
+pub struct UnsupportedAlgorithmError {
+    kind: &'static str,
+    name: String,
+}
+
+fn parse_names<N: Copy + for<'a> TryFrom<&'a str>>(
+    kind: &'static str,
+    names: &[String],
+) -> Result<Vec<N>, UnsupportedAlgorithmError> {
+    names
+        .iter()
+        .map(|name| {
+            let parsed = match name.as_str() {
+                "none" | "clear" => None,
+                name => N::try_from(name).ok(),
+            };
+            parsed.ok_or_else(|| UnsupportedAlgorithmError {
+                kind,
+                name: name.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Replaces the lists in `base` with the ones set in `algorithms`
+pub fn apply_algorithms(
+    base: Preferred,
+    algorithms: &SshAlgorithms,
+) -> Result<Preferred, UnsupportedAlgorithmError> {
+    let mut preferred = base;
+    if let Some(ref names) = algorithms.kex {
+        let mut kex = parse_names::<kex::Name>("key exchange", names)?;
+        kex.retain(|name| !KEX_EXTENSIONS.contains(name));
+        kex.extend_from_slice(KEX_EXTENSIONS);
+        preferred.kex = Cow::Owned(kex);
+    }
+    if let Some(ref names) = algorithms.ciphers {
+        preferred.cipher = Cow::Owned(parse_names("cipher", names)?);
+    }
+    if let Some(ref names) = algorithms.macs {
+        preferred.mac = Cow::Owned(parse_names("MAC", names)?);
+    }
+    if let Some(ref names) = algorithms.host_keys {
+        preferred.key = Cow::Owned(parse_names::<key::Name>("host key", names)?);
+    }
+    Ok(preferred)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedAlgorithms {
+    pub kex: Option<String>,
+    pub host_key: Option<String>,
+    pub cipher: Option<String>,
+    /// Not set for ciphers that don't use a separate MAC
+    pub mac: Option<String>,
+}
+
+impl NegotiatedAlgorithms {
+    pub fn log(&self, peer: &str) {
+        info!(
+            kex = self.kex.as_deref().unwrap_or("-"),
+            host_key = self.host_key.as_deref().unwrap_or("-"),
+            cipher = self.cipher.as_deref().unwrap_or("-"),
+            mac = self.mac.as_deref().unwrap_or("implicit"),
+            "Negotiated SSH algorithms with the {peer}"
+        );
+    }
+}
+
+/// Algorithm name lists from a KEXINIT message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KexInit {
+    kex: Vec<String>,
+    host_key: Vec<String>,
+    cipher: Vec<String>,
+    mac: Vec<String>,
+}
+
+impl KexInit {
+    pub fn from_preferred(preferred: &Preferred) -> Self {
+        fn names<N: AsRef<str>>(names: &[N]) -> Vec<String> {
+            names.iter().map(|n| n.as_ref().to_owned()).collect()
+        }
+        Self {
+            kex: names(&preferred.kex),
+            host_key: preferred.key.iter().map(|n| n.0.to_owned()).collect(),
+            cipher: names(&preferred.cipher),
+            mac: names(&preferred.mac),
+        }
+    }
+
+    /// What the server actually offers: only host key algorithms it has keys for
+    pub fn for_server(preferred: &Preferred, keys: &[KeyPair]) -> Self {
+        let mut this = Self::from_preferred(preferred);
+        this.host_key
+            .retain(|name| keys.iter().any(|k| k.name() == name));
+        this
+    }
+
+    /// Picks the first client algorithm that the server supports as well
+    pub fn negotiate(client: &KexInit, server: &KexInit) -> NegotiatedAlgorithms {
+        fn pick(client: &[String], server: &[String]) -> Option<String> {
+            client.iter().find(|name| server.contains(name)).cloned()
+        }
+        let cipher = pick(&client.cipher, &server.cipher);
+        let aead = AEAD_CIPHERS
+            .iter()
+            .any(|name| Some(name.as_ref()) == cipher.as_deref());
+        NegotiatedAlgorithms {
+            kex: pick(&client.kex, &server.kex),
+            host_key: pick(&client.host_key, &server.host_key),
+            mac: match aead {
+                true => None,
+                false => pick(&client.mac, &server.mac),
+            },
+            cipher,
+        }
+    }
+
+    /// Parses the peer's identification line and the KEXINIT packet following it.
+    /// Returns `None` if more data is needed.
+    fn parse(data: &[u8]) -> Option<Result<Self, ()>> {
+        let mut offset = 0;
+        loop {
+            let end = offset + data.get(offset..)?.iter().position(|b| *b == b'\n')? + 1;
+            let is_version = data
+                .get(offset..)
+                .is_some_and(|line| line.starts_with(b"SSH-"));
+            offset = end;
+            if is_version {
+                break;
+            }
+        }
+
+        let packet = data.get(offset..)?;
+        let length = u32::from_be_bytes(packet.get(..4)?.try_into().ok()?) as usize;
+        let packet = packet.get(4..4 + length)?;
+
+        let mut reader = NameListReader {
+            // padding length, message type, cookie
+            data: packet.get(1 + 1 + 16..).unwrap_or_default(),
+        };
+        if packet.get(1) != Some(&20) {
+            return Some(Err(()));
+        }
+        let kex = reader.next();
+        let host_key = reader.next();
+        let cipher = reader.next();
+        let _cipher_server_to_client = reader.next();
+        let mac = reader.next();
+        Some(match (kex, host_key, cipher, mac) {
+            (Some(kex), Some(host_key), Some(cipher), Some(mac)) => Ok(Self {
+                kex,
+                host_key,
+                cipher,
+                mac,
+            }),
+            _ => Err(()),
+        })
+    }
+}
+
+struct NameListReader<'a> {
+    data: &'a [u8],
+}
+
+impl NameListReader<'_> {
+    fn next(&mut self) -> Option<Vec<String>> {
+        let length = u32::from_be_bytes(self.data.get(..4)?.try_into().ok()?) as usize;
+        let list = self.data.get(4..4 + length)?;
+        self.data = self.data.get(4 + length..)?;
+        Some(
+            String::from_utf8_lossy(list)
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_owned())
+                .collect(),
+        )
+    }
+}
+
+/// Passes a stream through while looking for the peer's KEXINIT
+pub struct KexInitSniffer<S> {
+    inner: S,
+    buffer: Option<Vec<u8>>,
+    tx: Option<oneshot::Sender<KexInit>>,
+}
+
+impl<S> KexInitSniffer<S> {
+    pub fn new(inner: S) -> (Self, oneshot::Receiver<KexInit>) {
+        let (tx, rx) = oneshot::channel();
+        (
+            Self {
+                inner,
+                buffer: Some(vec![]),
+                tx: Some(tx),
+            },
+            rx,
+        )
+    }
+
+    fn sniff(&mut self, data: &[u8]) {
+        let Some(ref mut buffer) = self.buffer else {
+            return;
+        };
+        buffer.extend_from_slice(data);
+        let result = match KexInit::parse(buffer) {
+            None if buffer.len() < MAX_SNIFF_LENGTH => return,
+            None => Err(()),
+            Some(result) => result,
+        };
+        self.buffer = None;
+        if let (Ok(kex_init), Some(tx)) = (result, self.tx.take()) {
+            let _ = tx.send(kex_init);
+        }
+        self.tx = None;
+    }
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for KexInitSniffer<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for KexInitSniffer<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if self.buffer.is_some() {
+            if let Some(data) = buf.filled().get(before..) {
+                let data = data.to_vec();
+                self.sniff(&data);
+            }
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for KexInitSniffer<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_list(names: &str) -> Vec<u8> {
+        let mut data = (names.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(names.as_bytes());
+        data
+    }
+
+    #[test]
+    fn negotiates_with_the_peer_kex_init() {
+        let algorithms = SshAlgorithms {
+            kex: Some(vec!["curve25519-sha256".to_owned()]),
+            ciphers: Some(vec!["aes256-ctr".to_owned()]),
+            macs: None,
+            host_keys: None,
+        };
+        let preferred = apply_algorithms(Preferred::default(), &algorithms).ok();
+        assert!(preferred
+            .as_ref()
+            .is_some_and(|p| p.kex.contains(&kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT)));
+        assert!(apply_algorithms(
+            Preferred::default(),
+            &SshAlgorithms {
+                ciphers: Some(vec!["none".to_owned()]),
+                ..algorithms.clone()
+            }
+        )
+        .is_err());
+
+        let mut payload = vec![20];
+        payload.extend_from_slice(&[0; 16]);
+        payload.extend(name_list("diffie-hellman-group14-sha256,curve25519-sha256"));
+        payload.extend(name_list("ssh-ed25519"));
+        payload.extend(name_list("aes128-ctr,aes256-ctr"));
+        payload.extend(name_list("aes128-ctr,aes256-ctr"));
+        payload.extend(name_list("hmac-sha2-256"));
+        payload.extend(name_list("hmac-sha2-256"));
+        let mut data = b"banner\r\nSSH-2.0-OpenSSH_9.6\r\n".to_vec();
+        data.extend(((payload.len() + 1) as u32).to_be_bytes());
+        data.push(0);
+        data.extend(payload);
+
+        assert_eq!(KexInit::parse(data.get(..40).unwrap_or_default()), None);
+        let server = KexInit::parse(&data).and_then(|x| x.ok());
+        let client = preferred.as_ref().map(KexInit::from_preferred);
+        let negotiated = client.zip(server).map(|(c, s)| KexInit::negotiate(&c, &s));
+        assert_eq!(
+            negotiated,
+            Some(NegotiatedAlgorithms {
+                kex: Some("curve25519-sha256".to_owned()),
+                host_key: Some("ssh-ed25519".to_owned()),
+                cipher: Some("aes256-ctr".to_owned()),
+                mac: Some("hmac-sha2-256".to_owned()),
+            })
+        );
+    }
+}
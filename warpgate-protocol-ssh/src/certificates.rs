@@ -0,0 +1,116 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use russh::keys::key::KeyPair;
+use russh::keys::PublicKeyBase64;
+use ssh_key::certificate::{Builder, CertType};
+use ssh_key::rand_core::{OsRng, RngCore};
+use ssh_key::{Certificate, PrivateKey, PublicKey};
+
+/// Allowance for clock skew between Warpgate and the targets
+const CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Extensions that `ssh-keygen` grants to user certificates by default
+const DEFAULT_USER_EXTENSIONS: &[&str] = &[
+    "permit-X11-forwarding",
+    "permit-agent-forwarding",
+    "permit-port-forwarding",
+    "permit-pty",
+    "permit-user-rc",
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum CertificateError {
+    #[error(transparent)]
+    SshKey(#[from] ssh_key::Error),
+
+    #[error(transparent)]
+    Key(#[from] russh::keys::Error),
+
+    #[error("system clock is before the Unix epoch")]
+    Clock,
+}
+
+pub(crate) fn unix_time_now() -> Result<u64, CertificateError> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|_| CertificateError::Clock)
+}
+
+pub(crate) fn from_ssh_key_public(
+    key: &PublicKey,
+) -> Result<russh::keys::key::PublicKey, CertificateError> {
+    Ok(russh::keys::key::parse_public_key(&key.to_bytes()?, None)?)
+}
+
+/// Converts a russh key into its ssh-key counterpart
+pub(crate) fn to_ssh_key_public(
+    key: &russh::keys::key::PublicKey,
+) -> Result<PublicKey, CertificateError> {
+    Ok(PublicKey::from_bytes(&key.public_key_bytes())?)
+}
+
+/// Issues a short-lived user certificate for one of Warpgate's own client keys,
+/// valid only for the given target username
+pub fn issue_target_certificate(
+    ca_key: &PrivateKey,
+    client_key: &KeyPair,
+    principal: &str,
+    key_id: &str,
+    validity: Duration,
+) -> Result<Certificate, CertificateError> {
+    let public_key = to_ssh_key_public(&client_key.clone_public_key()?)?;
+    let now = unix_time_now()?;
+
+    let mut builder = Builder::new_with_random_nonce(
+        &mut OsRng,
+        public_key.key_data().clone(),
+        now.saturating_sub(CLOCK_SKEW.as_secs()),
+        now + validity.as_secs(),
+    )?;
+    builder
+        .serial(OsRng.next_u64())?
+        .cert_type(CertType::User)?
+        .key_id(key_id)?
+        .valid_principal(principal)?;
+    for extension in DEFAULT_USER_EXTENSIONS {
+        builder.extension(*extension, "")?;
+    }
+    Ok(builder.sign(ca_key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_key::{Algorithm, HashAlg};
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn issues_valid_target_certificate() {
+        let ca_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let client_key = KeyPair::generate_ed25519().unwrap();
+
+        let certificate = issue_target_certificate(
+            &ca_key,
+            &client_key,
+            "root",
+            "warpgate:admin:session",
+            Duration::from_secs(300),
+        )
+        .unwrap();
+
+        let now = unix_time_now().unwrap();
+        certificate
+            .validate_at(now, [&ca_key.public_key().fingerprint(HashAlg::Sha256)])
+            .unwrap();
+        assert_eq!(certificate.valid_principals(), ["root".to_owned()]);
+        assert_eq!(certificate.cert_type(), CertType::User);
+        assert!(certificate
+            .validate_at(
+                now + 3600,
+                [&ca_key.public_key().fingerprint(HashAlg::Sha256)]
+            )
+            .is_err());
+    }
+}
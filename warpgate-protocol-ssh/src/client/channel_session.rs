@@ -79,6 +79,9 @@ impl SessionChannel {
                         Some(ChannelOperation::RequestSubsystem(name)) => {
                             self.client_channel.request_subsystem(false, &name).await?;
                         },
+                        Some(ChannelOperation::RequestAgentForward) => {
+                            self.client_channel.agent_forward(false).await?;
+                        },
                         Some(ChannelOperation::Eof) => {
                             self.client_channel.eof().await?;
                         },
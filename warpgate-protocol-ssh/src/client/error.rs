@@ -10,6 +10,8 @@ pub enum SshClientError {
     Russh(#[from] russh::Error),
     #[error(transparent)]
     Warpgate(#[from] WarpgateError),
+    #[error("in use by another session on the shared connection")]
+    SharedConnectionInUse,
     #[error(transparent)]
     Other(Box<dyn Error + Send + Sync>),
 }
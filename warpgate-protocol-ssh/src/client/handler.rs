@@ -1,15 +1,18 @@
+use std::collections::HashSet;
+
 use async_trait::async_trait;
+use bytes::Bytes;
 use russh::client::{Msg, Session};
 use russh::keys::key::PublicKey;
 use russh::keys::PublicKeyBase64;
-use russh::Channel;
+use russh::{Channel, ChannelId};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot;
 use tracing::*;
 use warpgate_common::{SessionId, TargetSSHOptions};
 use warpgate_core::Services;
 
-use crate::known_hosts::{KnownHostValidationResult, KnownHosts};
+use crate::known_hosts::{matches_pinned_fingerprint, KnownHostValidationResult, KnownHosts};
 use crate::{ConnectionError, ForwardedTcpIpParams};
 
 #[derive(Debug)]
@@ -18,6 +21,9 @@ pub enum ClientHandlerEvent {
     HostKeyUnknown(PublicKey, oneshot::Sender<bool>),
     ForwardedTcpIp(Channel<Msg>, ForwardedTcpIpParams),
     X11(Channel<Msg>, String, u32),
+    AgentForward(ChannelId),
+    AgentData(ChannelId, Bytes),
+    AgentClose(ChannelId),
     Disconnect,
 }
 
@@ -26,6 +32,7 @@ pub struct ClientHandler {
     pub event_tx: UnboundedSender<ClientHandlerEvent>,
     pub services: Services,
     pub session_id: SessionId,
+    pub agent_channels: HashSet<ChannelId>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -40,6 +47,22 @@ pub enum ClientHandlerError {
     Internal,
 }
 
+impl ClientHandler {
+    /// Logged separately from the usual unknown key prompts since this
+    /// could be a man-in-the-middle attack
+    fn report_changed_host_key(&self, key: &PublicKey) {
+        error!(
+            session=%self.session_id,
+            security_alert = true,
+            host=%self.ssh_options.host,
+            port=self.ssh_options.port,
+            "Security alert: the host key has changed! Received {} key SHA256:{}",
+            key.name(),
+            key.fingerprint(),
+        );
+    }
+}
+
 #[async_trait]
 impl russh::client::Handler for ClientHandler {
     type Error = ClientHandlerError;
@@ -54,6 +77,22 @@ impl russh::client::Handler for ClientHandler {
                 server_public_key.clone(),
             ))
             .map_err(|_| ClientHandlerError::ConnectionError(ConnectionError::Internal))?;
+
+        if let Some(ref fingerprints) = self.ssh_options.host_key_fingerprints {
+            if !fingerprints.is_empty() {
+                if matches_pinned_fingerprint(server_public_key, fingerprints) {
+                    return Ok(true);
+                }
+                self.report_changed_host_key(server_public_key);
+                return Err(ClientHandlerError::ConnectionError(
+                    ConnectionError::HostKeyNotPinned {
+                        received_key_type: server_public_key.name().to_owned(),
+                        received_fingerprint: format!("SHA256:{}", server_public_key.fingerprint()),
+                    },
+                ));
+            }
+        }
+
         match known_hosts
             .validate(
                 &self.ssh_options.host,
@@ -67,7 +106,7 @@ impl russh::client::Handler for ClientHandler {
                 key_type,
                 key_base64,
             }) => {
-                warn!(session=%self.session_id, "Host key is invalid!");
+                self.report_changed_host_key(server_public_key);
                 return Err(ClientHandlerError::ConnectionError(
                     ConnectionError::HostKeyMismatch {
                         received_key_type: server_public_key.name().to_owned(),
@@ -149,6 +188,49 @@ impl russh::client::Handler for ClientHandler {
         ));
         Ok(())
     }
+
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if !self.ssh_options.allow_agent_forwarding.unwrap_or(false) {
+            warn!(session=%self.session_id, "Target tried to open an agent channel, but agent forwarding is not enabled");
+            session.close(channel);
+            return Ok(());
+        }
+        self.agent_channels.insert(channel);
+        let _ = self
+            .event_tx
+            .send(ClientHandlerEvent::AgentForward(channel));
+        Ok(())
+    }
+
+    async fn data(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if self.agent_channels.contains(&channel) {
+            let _ = self.event_tx.send(ClientHandlerEvent::AgentData(
+                channel,
+                Bytes::from(data.to_vec()),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if self.agent_channels.remove(&channel) {
+            let _ = self.event_tx.send(ClientHandlerEvent::AgentClose(channel));
+        }
+        Ok(())
+    }
 }
 
 impl Drop for ClientHandler {
@@ -2,35 +2,43 @@ mod channel_direct_tcpip;
 mod channel_session;
 mod error;
 mod handler;
+mod pool;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::net::ToSocketAddrs;
 use std::sync::Arc;
 
 use anyhow::Result;
+use bimap::BiMap;
 use bytes::Bytes;
 use channel_direct_tcpip::DirectTCPIPChannel;
 use channel_session::SessionChannel;
 pub use error::SshClientError;
 use futures::pin_mut;
 use handler::ClientHandler;
-use russh::client::Handle;
-use russh::keys::key::PublicKey;
-use russh::{kex, Preferred, Sig};
+use pool::{ConnectionPool, PoolKey, SharedConnection};
+use russh::client::{Handle, KeyboardInteractiveAuthResponse, Msg, Prompt};
+use russh::keys::key::{KeyPair, PublicKey};
+use russh::{kex, ChannelId, ChannelStream, CryptoVec, Preferred, Sig};
+use ssh_key::Certificate;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::{oneshot, Mutex};
 use tokio::task::JoinHandle;
 use tracing::*;
 use uuid::Uuid;
-use warpgate_common::{SSHTargetAuth, SessionId, TargetSSHOptions};
+use warpgate_common::{SSHTargetAuth, SessionId, TargetOptions, TargetSSHOptions};
 use warpgate_core::Services;
 
 use self::handler::ClientHandlerEvent;
 use super::{ChannelOperation, DirectTCPIPParams};
+use crate::algorithms::{apply_algorithms, KexInit, KexInitSniffer, UnsupportedAlgorithmError};
+use crate::certificates::{issue_target_certificate, CertificateError};
 use crate::client::handler::ClientHandlerError;
 use crate::helpers::PublicKeyAsOpenSSH;
-use crate::keys::load_client_keys;
+use crate::keys::{load_ca_key, load_client_keys};
 use crate::ForwardedTcpIpParams;
 
 #[derive(Debug, thiserror::Error)]
@@ -43,6 +51,12 @@ pub enum ConnectionError {
         known_key_base64: String,
     },
 
+    #[error("Host key doesn't match the pinned fingerprints")]
+    HostKeyNotPinned {
+        received_key_type: String,
+        received_fingerprint: String,
+    },
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
@@ -63,6 +77,15 @@ pub enum ConnectionError {
 
     #[error("Authentication failed")]
     Authentication,
+
+    #[error("Jump host target {0} not found")]
+    JumpHostNotFound(String),
+
+    #[error("Jump host target {0} is part of a loop")]
+    JumpHostLoop(String),
+
+    #[error(transparent)]
+    UnsupportedAlgorithm(#[from] UnsupportedAlgorithmError),
 }
 
 #[derive(Debug)]
@@ -92,15 +115,29 @@ pub enum RCEvent {
     Done,
     HostKeyReceived(PublicKey),
     HostKeyUnknown(PublicKey, oneshot::Sender<bool>),
+    KeyboardInteractive(
+        KeyboardInteractivePrompt,
+        oneshot::Sender<Option<Vec<String>>>,
+    ),
     ForwardedTcpIp(Uuid, ForwardedTcpIpParams),
     X11(Uuid, String, u32),
+    AgentForward(Uuid),
+}
+
+/// Prompts sent by the target during keyboard-interactive authentication
+#[derive(Debug)]
+pub struct KeyboardInteractivePrompt {
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<Prompt>,
 }
 
 pub type RCCommandReply = oneshot::Sender<Result<(), SshClientError>>;
 
 #[derive(Clone, Debug)]
 pub enum RCCommand {
-    Connect(TargetSSHOptions),
+    /// The target name and its SSH options
+    Connect(String, Box<TargetSSHOptions>),
     Channel(Uuid, ChannelOperation),
     ForwardTCPIP(String, u32),
     CancelTCPIPForward(String, u32),
@@ -125,7 +162,10 @@ pub struct RemoteClient {
     id: SessionId,
     tx: UnboundedSender<RCEvent>,
     session: Option<Arc<Mutex<Handle<ClientHandler>>>>,
+    jump_host_sessions: Vec<Handle<ClientHandler>>,
+    shared_connection: Option<Arc<SharedConnection>>,
     channel_pipes: Arc<Mutex<HashMap<Uuid, UnboundedSender<ChannelOperation>>>>,
+    agent_channels: BiMap<Uuid, ChannelId>,
     pending_ops: Vec<(Uuid, ChannelOperation)>,
     pending_forwards: Vec<(String, u32)>,
     state: RCState,
@@ -154,7 +194,10 @@ impl RemoteClient {
             id,
             tx: event_tx,
             session: None,
+            jump_host_sessions: vec![],
+            shared_connection: None,
             channel_pipes: Arc::new(Mutex::new(HashMap::new())),
+            agent_channels: BiMap::new(),
             pending_ops: vec![],
             pending_forwards: vec![],
             state: RCState::NotInitialized,
@@ -188,6 +231,10 @@ impl RemoteClient {
 
     fn set_disconnected(&mut self) {
         self.session = None;
+        self.jump_host_sessions.clear();
+        if let Some(shared) = self.shared_connection.take() {
+            shared.release(self.id);
+        }
         for (id, op) in self.pending_ops.drain(..) {
             if let ChannelOperation::OpenShell = op {
                 let _ = self.tx.send(RCEvent::Close(id));
@@ -232,6 +279,17 @@ impl RemoteClient {
             return Ok(());
         }
 
+        if let Some(shared) = &self.shared_connection {
+            let claimed = match op {
+                ChannelOperation::RequestX11(_) => shared.claim_x11(self.id),
+                ChannelOperation::RequestAgentForward => shared.claim_agent(self.id),
+                _ => true,
+            };
+            if !claimed {
+                return Err(SshClientError::SharedConnectionInUse);
+            }
+        }
+
         match op {
             ChannelOperation::OpenShell => {
                 self.open_shell(channel_id).await?;
@@ -239,6 +297,9 @@ impl RemoteClient {
             ChannelOperation::OpenDirectTCPIP(params) => {
                 self.open_direct_tcpip(channel_id, params).await?;
             }
+            op if self.agent_channels.contains_left(&channel_id) => {
+                self.apply_agent_channel_op(channel_id, op).await?;
+            }
             op => {
                 let mut channel_pipes = self.channel_pipes.lock().await;
                 match channel_pipes.get(&channel_id) {
@@ -318,6 +379,22 @@ impl RemoteClient {
                             .tx
                             .send(RCEvent::X11(id, originator_address, originator_port));
                     }
+                    ClientHandlerEvent::AgentForward(channel) => {
+                        let id = Uuid::new_v4();
+                        info!(channel=%id, "Target opened an agent connection");
+                        self.agent_channels.insert(id, channel);
+                        let _ = self.tx.send(RCEvent::AgentForward(id));
+                    }
+                    ClientHandlerEvent::AgentData(channel, data) => {
+                        if let Some(id) = self.agent_channels.get_by_right(&channel) {
+                            let _ = self.tx.send(RCEvent::Output(*id, data));
+                        }
+                    }
+                    ClientHandlerEvent::AgentClose(channel) => {
+                        if let Some((id, _)) = self.agent_channels.remove_by_right(&channel) {
+                            let _ = self.tx.send(RCEvent::Close(id));
+                        }
+                    }
                     event => {
                         error!(?event, "Unhandled client handler event");
                     }
@@ -349,26 +426,28 @@ impl RemoteClient {
 
     async fn handle_command(&mut self, cmd: RCCommand) -> Result<bool, SshClientError> {
         match cmd {
-            RCCommand::Connect(options) => match self.connect(options).await {
-                Ok(_) => {
-                    self.set_state(RCState::Connected)
-                        .map_err(SshClientError::other)?;
-                    let ops = self.pending_ops.drain(..).collect::<Vec<_>>();
-                    for (id, op) in ops {
-                        self.apply_channel_op(id, op).await?;
+            RCCommand::Connect(target_name, options) => {
+                match self.connect(target_name, *options).await {
+                    Ok(_) => {
+                        self.set_state(RCState::Connected)
+                            .map_err(SshClientError::other)?;
+                        let ops = self.pending_ops.drain(..).collect::<Vec<_>>();
+                        for (id, op) in ops {
+                            self.apply_channel_op(id, op).await?;
+                        }
+                        let forwards = self.pending_forwards.drain(..).collect::<Vec<_>>();
+                        for (address, port) in forwards {
+                            self.tcpip_forward(address, port).await?;
+                        }
                     }
-                    let forwards = self.pending_forwards.drain(..).collect::<Vec<_>>();
-                    for (address, port) in forwards {
-                        self.tcpip_forward(address, port).await?;
+                    Err(e) => {
+                        debug!("Connect error: {}", e);
+                        let _ = self.tx.send(RCEvent::ConnectionError(e));
+                        self.set_disconnected();
+                        return Ok(true);
                     }
                 }
-                Err(e) => {
-                    debug!("Connect error: {}", e);
-                    let _ = self.tx.send(RCEvent::ConnectionError(e));
-                    self.set_disconnected();
-                    return Ok(true);
-                }
-            },
+            }
             RCCommand::Channel(ch, op) => {
                 self.apply_channel_op(ch, op).await?;
             }
@@ -386,7 +465,191 @@ impl RemoteClient {
         Ok(false)
     }
 
-    async fn connect(&mut self, ssh_options: TargetSSHOptions) -> Result<(), ConnectionError> {
+    async fn connect(
+        &mut self,
+        target_name: String,
+        ssh_options: TargetSSHOptions,
+    ) -> Result<(), ConnectionError> {
+        let pool_key = match ssh_options.reuse_connections {
+            Some(true) => self.pool_key(target_name, &ssh_options).await,
+            _ => None,
+        };
+        let pool = self.services.protocol_state::<ConnectionPool>().await;
+        if let Some(ref key) = pool_key {
+            if let Some(shared) = pool
+                .acquire(key, self.id, self.inner_event_tx.clone())
+                .await
+            {
+                info!(
+                    address=%format!("{}:{}", ssh_options.host, ssh_options.port),
+                    username=&ssh_options.username[..],
+                    "Reusing a shared upstream connection"
+                );
+                self.session = Some(shared.session.clone());
+                self.shared_connection = Some(shared);
+                return Ok(());
+            }
+        }
+
+        let mut chain = self.resolve_jump_hosts(&ssh_options).await?;
+        chain.push(ssh_options);
+
+        let mut stream: Option<ChannelStream<Msg>> = None;
+        let mut session = None;
+        for (index, hop_options) in chain.iter().enumerate() {
+            let (hop_session, event_rx) = match stream.take() {
+                Some(stream) => self.establish(hop_options, stream).await?,
+                None => {
+                    let socket = self.dial(hop_options).await?;
+                    self.establish(hop_options, socket).await?
+                }
+            };
+
+            let Some(next) = chain.get(index + 1) else {
+                session = Some((hop_session, event_rx));
+                break;
+            };
+
+            info!(
+                jump_host=%format!("{}:{}", hop_options.host, hop_options.port),
+                "Connecting to {}:{} through the jump host", next.host, next.port
+            );
+            let channel = hop_session
+                .channel_open_direct_tcpip(next.host.clone(), next.port.into(), "127.0.0.1", 0)
+                .await?;
+            stream = Some(channel.into_stream());
+            self.jump_host_sessions.push(hop_session);
+
+            // Jump hosts are only used for tunneling, so their events are not interesting
+            tokio::spawn(
+                async move {
+                    let mut event_rx = event_rx;
+                    while let Some(event) = event_rx.recv().await {
+                        debug!(?event, "Jump host event");
+                    }
+                }
+                .instrument(Span::current()),
+            );
+        }
+
+        let Some((session, mut event_rx)) = session else {
+            return Err(ConnectionError::Internal);
+        };
+
+        info!("Connected");
+
+        if let Some(key) = pool_key {
+            let shared = pool.insert(
+                key,
+                self.id,
+                session,
+                std::mem::take(&mut self.jump_host_sessions),
+                event_rx,
+                self.inner_event_tx.clone(),
+            );
+            self.session = Some(shared.session.clone());
+            self.shared_connection = Some(shared);
+            return Ok(());
+        }
+
+        self.session = Some(Arc::new(Mutex::new(session)));
+
+        tokio::spawn(
+            {
+                let inner_event_tx = self.inner_event_tx.clone();
+                async move {
+                    while let Some(e) = event_rx.recv().await {
+                        info!("{:?}", e);
+                        inner_event_tx.send(InnerEvent::ClientHandlerEvent(e))?
+                    }
+                    Ok::<(), anyhow::Error>(())
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(())
+    }
+
+    async fn warpgate_username(&self) -> Option<String> {
+        match self.services.state.lock().await.sessions.get(&self.id) {
+            Some(state) => state.lock().await.username.clone(),
+            None => None,
+        }
+    }
+
+    async fn pool_key(
+        &self,
+        target_name: String,
+        ssh_options: &TargetSSHOptions,
+    ) -> Option<PoolKey> {
+        // A shared connection would carry the first session's certificate
+        if let SSHTargetAuth::PublicKey(_) = ssh_options.auth {
+            if load_ca_key(&*self.services.config.lock().await).is_ok() {
+                debug!("Not sharing the connection since it authenticates with a per-session certificate");
+                return None;
+            }
+        }
+        let warpgate_username = self.warpgate_username().await?;
+        match PoolKey::new(warpgate_username, target_name, ssh_options) {
+            Ok(key) => Some(key),
+            Err(error) => {
+                warn!(%error, "Not sharing the connection");
+                None
+            }
+        }
+    }
+
+    /// Looks up the SSH targets listed in `via`, including the jump hosts of those
+    /// targets themselves, in the order they have to be connected through
+    async fn resolve_jump_hosts(
+        &self,
+        ssh_options: &TargetSSHOptions,
+    ) -> Result<Vec<TargetSSHOptions>, ConnectionError> {
+        let Some(ref via) = ssh_options.via else {
+            return Ok(vec![]);
+        };
+        if via.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let targets = self
+            .services
+            .config_provider
+            .lock()
+            .await
+            .list_targets()
+            .await
+            .map_err(|error| {
+                error!(?error, "Failed to load the targets");
+                ConnectionError::Internal
+            })?;
+
+        let find = |name: &str| {
+            targets.iter().find_map(|target| match target.options {
+                TargetOptions::Ssh(ref options) if target.name == name => Some(options),
+                _ => None,
+            })
+        };
+
+        let mut order = vec![];
+        order_jump_hosts(
+            via,
+            &|name| find(name).map(|options| options.via.clone().unwrap_or_default()),
+            &mut vec![],
+            &mut order,
+        )?;
+        order
+            .iter()
+            .map(|name| {
+                find(name)
+                    .cloned()
+                    .ok_or_else(|| ConnectionError::JumpHostNotFound(name.clone()))
+            })
+            .collect()
+    }
+
+    async fn dial(&mut self, ssh_options: &TargetSSHOptions) -> Result<TcpStream, ConnectionError> {
         let address_str = format!("{}:{}", ssh_options.host, ssh_options.port);
         let address = match address_str
             .to_socket_addrs()
@@ -402,6 +665,18 @@ impl RemoteClient {
         };
 
         info!(?address, username = &ssh_options.username[..], "Connecting");
+        Ok(TcpStream::connect(address).await?)
+    }
+
+    /// Runs the SSH handshake and authentication over an already connected stream
+    async fn establish<R>(
+        &mut self,
+        ssh_options: &TargetSSHOptions,
+        stream: R,
+    ) -> Result<(Handle<ClientHandler>, UnboundedReceiver<ClientHandlerEvent>), ConnectionError>
+    where
+        R: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         let algos = if ssh_options.allow_insecure_algos.unwrap_or(false) {
             Preferred {
                 kex: Cow::Borrowed(&[
@@ -424,12 +699,18 @@ impl RemoteClient {
         } else {
             Preferred::default()
         };
+        let algos = match ssh_options.algorithms {
+            Some(ref algorithms) => apply_algorithms(algos, algorithms)?,
+            None => algos,
+        };
+        let client_kex_init = KexInit::from_preferred(&algos);
 
         let config = russh::client::Config {
             preferred: algos,
             ..Default::default()
         };
         let config = Arc::new(config);
+        let (stream, mut server_kex_init) = KexInitSniffer::new(stream);
 
         let (event_tx, mut event_rx) = unbounded_channel();
         let handler = ClientHandler {
@@ -437,9 +718,10 @@ impl RemoteClient {
             event_tx,
             services: self.services.clone(),
             session_id: self.id,
+            agent_channels: HashSet::new(),
         };
 
-        let fut_connect = russh::client::connect(config, address, handler);
+        let fut_connect = russh::client::connect_stream(config, stream, handler);
         pin_mut!(fut_connect);
 
         loop {
@@ -474,8 +756,12 @@ impl RemoteClient {
                         }
                     };
 
+                    if let Ok(server_kex_init) = server_kex_init.try_recv() {
+                        KexInit::negotiate(&client_kex_init, &server_kex_init).log("target");
+                    }
+
                     let mut auth_result = false;
-                    match ssh_options.auth {
+                    match ssh_options.auth.clone() {
                         SSHTargetAuth::Password(auth) => {
                             auth_result = session
                                 .authenticate_password(ssh_options.username.clone(), auth.password.expose_secret())
@@ -484,17 +770,38 @@ impl RemoteClient {
                                 debug!(username=&ssh_options.username[..], "Authenticated with password");
                             }
                         }
+                        SSHTargetAuth::KeyboardInteractive(_) => {
+                            auth_result = self
+                                .authenticate_keyboard_interactive(&mut session, &ssh_options.username)
+                                .await?;
+                            if auth_result {
+                                debug!(username=&ssh_options.username[..], "Authenticated with keyboard-interactive");
+                            }
+                        }
                         SSHTargetAuth::PublicKey(_) => {
                             #[allow(clippy::explicit_auto_deref)]
                             let keys = load_client_keys(&*self.services.config.lock().await)?;
-                            for key in keys.into_iter() {
-                                let key_str = key.as_openssh();
+
+                            if let Some((key, certificate)) = self.issue_certificate(ssh_options, &keys).await {
+                                let key_id = certificate.key_id().to_owned();
                                 auth_result = session
-                                    .authenticate_publickey(ssh_options.username.clone(), Arc::new(key))
+                                    .authenticate_openssh_cert(ssh_options.username.clone(), Arc::new(key), certificate)
                                     .await?;
                                 if auth_result {
-                                    debug!(username=&ssh_options.username[..], key=%key_str, "Authenticated with key");
-                                    break;
+                                    debug!(username=&ssh_options.username[..], %key_id, "Authenticated with certificate");
+                                }
+                            }
+
+                            if !auth_result {
+                                for key in keys.into_iter() {
+                                    let key_str = key.as_openssh();
+                                    auth_result = session
+                                        .authenticate_publickey(ssh_options.username.clone(), Arc::new(key))
+                                        .await?;
+                                    if auth_result {
+                                        debug!(username=&ssh_options.username[..], key=%key_str, "Authenticated with key");
+                                        break;
+                                    }
                                 }
                             }
                         }
@@ -508,23 +815,99 @@ impl RemoteClient {
                         return Err(ConnectionError::Authentication);
                     }
 
-                    self.session = Some(Arc::new(Mutex::new(session)));
-
-                    info!(?address, "Connected");
+                    return Ok((session, event_rx))
+                }
+            }
+        }
+    }
 
-                    tokio::spawn({
-                        let inner_event_tx = self.inner_event_tx.clone();
-                        async move {
-                            while let Some(e) = event_rx.recv().await {
-                                info!("{:?}", e);
-                                inner_event_tx.send(InnerEvent::ClientHandlerEvent(e))?
-                            }
-                            Ok::<(), anyhow::Error>(())
-                        }
-                    }.instrument(Span::current()));
+    /// Relays the target's keyboard-interactive prompts to the user
+    /// and sends their answers back
+    async fn authenticate_keyboard_interactive(
+        &mut self,
+        session: &mut Handle<ClientHandler>,
+        username: &str,
+    ) -> Result<bool, ConnectionError> {
+        let mut response = session
+            .authenticate_keyboard_interactive_start(username, None)
+            .await?;
+        loop {
+            let (name, instructions, prompts) = match response {
+                KeyboardInteractiveAuthResponse::Success => return Ok(true),
+                KeyboardInteractiveAuthResponse::Failure => return Ok(false),
+                KeyboardInteractiveAuthResponse::InfoRequest {
+                    name,
+                    instructions,
+                    prompts,
+                } => (name, instructions, prompts),
+            };
 
-                    return Ok(())
+            let answers = if prompts.is_empty() {
+                vec![]
+            } else {
+                let (reply, answers) = oneshot::channel();
+                let prompt = KeyboardInteractivePrompt {
+                    name,
+                    instructions,
+                    prompts,
+                };
+                self.tx
+                    .send(RCEvent::KeyboardInteractive(prompt, reply))
+                    .map_err(|_| ConnectionError::Internal)?;
+                tokio::select! {
+                    answers = answers => match answers {
+                        Ok(Some(answers)) => answers,
+                        _ => return Ok(false),
+                    },
+                    Some(_) = self.abort_rx.recv() => {
+                        info!("Abort requested");
+                        self.set_disconnected();
+                        return Err(ConnectionError::Aborted)
+                    }
                 }
+            };
+
+            response = session
+                .authenticate_keyboard_interactive_respond(answers)
+                .await?;
+        }
+    }
+
+    /// Signs Warpgate's Ed25519 client key for this connection only
+    async fn issue_certificate(
+        &self,
+        ssh_options: &TargetSSHOptions,
+        keys: &[KeyPair],
+    ) -> Option<(KeyPair, Certificate)> {
+        let key = keys
+            .iter()
+            .find(|k| matches!(k, KeyPair::Ed25519(_)))?
+            .clone();
+
+        let username = self.warpgate_username().await;
+        let key_id = format!(
+            "warpgate:{}:{}",
+            username.as_deref().unwrap_or("-"),
+            self.id
+        );
+
+        let config = self.services.config.lock().await;
+        let result = load_ca_key(&config)
+            .map_err(CertificateError::from)
+            .and_then(|ca_key| {
+                issue_target_certificate(
+                    &ca_key,
+                    &key,
+                    &ssh_options.username,
+                    &key_id,
+                    config.store.ssh.target_certificate_validity,
+                )
+            });
+        match result {
+            Ok(certificate) => Some((key, certificate)),
+            Err(error) => {
+                warn!(?error, "Failed to issue a client certificate");
+                None
             }
         }
     }
@@ -579,10 +962,38 @@ impl RemoteClient {
         Ok(())
     }
 
+    async fn apply_agent_channel_op(
+        &mut self,
+        channel_id: Uuid,
+        op: ChannelOperation,
+    ) -> Result<(), SshClientError> {
+        let (Some(session), Some(agent_channel)) =
+            (&self.session, self.agent_channels.get_by_left(&channel_id))
+        else {
+            return Ok(());
+        };
+        match op {
+            ChannelOperation::Data(data) => {
+                let _ = session
+                    .lock()
+                    .await
+                    .data(*agent_channel, CryptoVec::from_slice(&data))
+                    .await;
+            }
+            operation => {
+                debug!(channel=%channel_id, ?operation, "unexpected agent channel operation");
+            }
+        }
+        Ok(())
+    }
+
     async fn tcpip_forward(&mut self, address: String, port: u32) -> Result<(), SshClientError> {
         if let Some(session) = &self.session {
             let mut session = session.lock().await;
-            session.tcpip_forward(address, port).await?;
+            session.tcpip_forward(address.clone(), port).await?;
+            if let Some(shared) = &self.shared_connection {
+                shared.claim_remote_forward(self.id, address, port);
+            }
         } else {
             self.pending_forwards.push((address, port));
         }
@@ -596,7 +1007,10 @@ impl RemoteClient {
     ) -> Result<(), SshClientError> {
         if let Some(session) = &self.session {
             let session = session.lock().await;
-            session.cancel_tcpip_forward(address, port).await?;
+            session.cancel_tcpip_forward(address.clone(), port).await?;
+            if let Some(shared) = &self.shared_connection {
+                shared.release_remote_forward(address, port);
+            }
         } else {
             self.pending_forwards
                 .retain(|x| x.0 != address || x.1 != port);
@@ -606,11 +1020,14 @@ impl RemoteClient {
 
     async fn disconnect(&mut self) {
         if let Some(session) = &mut self.session {
-            let _ = session
-                .lock()
-                .await
-                .disconnect(russh::Disconnect::ByApplication, "", "")
-                .await;
+            // Shared connections are closed by the pool once nobody uses them
+            if self.shared_connection.is_none() {
+                let _ = session
+                    .lock()
+                    .await
+                    .disconnect(russh::Disconnect::ByApplication, "", "")
+                    .await;
+            }
             self.set_disconnected();
         }
     }
@@ -626,7 +1043,90 @@ impl Drop for RemoteClient {
         for task in self.child_tasks.drain(..) {
             task.abort();
         }
+        if let Some(shared) = self.shared_connection.take() {
+            shared.release(self.id);
+        }
         info!("Closed connection");
         debug!("Dropped");
     }
 }
+
+/// Expands `via` into the full list of hops: a jump host that has jump hosts
+/// of its own is preceded by them. `get_via` returns a target's own `via`.
+fn order_jump_hosts(
+    via: &[String],
+    get_via: &impl Fn(&str) -> Option<Vec<String>>,
+    visiting: &mut Vec<String>,
+    order: &mut Vec<String>,
+) -> Result<(), ConnectionError> {
+    for name in via {
+        if visiting.contains(name) || order.contains(name) {
+            return Err(ConnectionError::JumpHostLoop(name.clone()));
+        }
+        let hop_via =
+            get_via(name).ok_or_else(|| ConnectionError::JumpHostNotFound(name.clone()))?;
+        visiting.push(name.clone());
+        order_jump_hosts(&hop_via, get_via, visiting, order)?;
+        visiting.pop();
+        order.push(name.clone());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn order(targets: &[(&str, &[&str])], via: &[&str]) -> Result<Vec<String>, ConnectionError> {
+        let targets: HashMap<_, _> = targets
+            .iter()
+            .map(|(name, via)| (*name, via.iter().map(|v| v.to_string()).collect::<Vec<_>>()))
+            .collect();
+        let via: Vec<_> = via.iter().map(|v| v.to_string()).collect();
+        let mut order = vec![];
+        order_jump_hosts(
+            &via,
+            &|name| targets.get(name).cloned(),
+            &mut vec![],
+            &mut order,
+        )?;
+        Ok(order)
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn orders_nested_jump_hosts() {
+        let targets: &[(&str, &[&str])] = &[
+            ("bastion", &[]),
+            ("dmz", &["bastion"]),
+            ("internal", &["dmz"]),
+            ("a", &["b"]),
+            ("b", &["a"]),
+            ("self", &["self"]),
+        ];
+
+        assert_eq!(order(targets, &["bastion"]).unwrap(), ["bastion"]);
+        assert_eq!(
+            order(targets, &["internal"]).unwrap(),
+            ["bastion", "dmz", "internal"]
+        );
+        assert!(matches!(
+            order(targets, &["a"]),
+            Err(ConnectionError::JumpHostLoop(_))
+        ));
+        assert!(matches!(
+            order(targets, &["self"]),
+            Err(ConnectionError::JumpHostLoop(_))
+        ));
+        assert!(matches!(
+            order(targets, &["dmz", "bastion"]),
+            Err(ConnectionError::JumpHostLoop(_))
+        ));
+        assert!(matches!(
+            order(targets, &["missing"]),
+            Err(ConnectionError::JumpHostNotFound(_))
+        ));
+    }
+}
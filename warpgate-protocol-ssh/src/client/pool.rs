@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use russh::client::Handle;
+use russh::ChannelId;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+use tracing::*;
+use warpgate_common::{SessionId, TargetSSHOptions};
+
+use super::handler::{ClientHandler, ClientHandlerEvent};
+use super::InnerEvent;
+
+/// How long an upstream connection is kept open after its last session has left
+const IDLE_LINGER: Duration = Duration::from_secs(60);
+
+/// Connections are only shared between sessions of the same Warpgate user
+/// on the same target, and only while the target's settings stay the same
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct PoolKey {
+    pub warpgate_username: String,
+    pub target_name: String,
+    pub host: String,
+    pub username: String,
+    /// The target's SSH options, including its auth settings and jump hosts
+    options: String,
+}
+
+impl PoolKey {
+    pub fn new(
+        warpgate_username: String,
+        target_name: String,
+        options: &TargetSSHOptions,
+    ) -> serde_json::Result<Self> {
+        Ok(Self {
+            warpgate_username,
+            target_name,
+            host: format!("{}:{}", options.host, options.port),
+            username: options.username.clone(),
+            options: serde_json::to_string(options)?,
+        })
+    }
+}
+
+/// Upstream connections that can be shared, kept in [warpgate_core::Services]
+#[derive(Default)]
+pub(super) struct ConnectionPool {
+    connections: std::sync::Mutex<HashMap<PoolKey, Arc<SharedConnection>>>,
+}
+
+#[derive(Default)]
+struct Routes {
+    subscribers: HashMap<SessionId, UnboundedSender<InnerEvent>>,
+    remote_forwards: HashMap<(String, u32), SessionId>,
+    x11: Option<SessionId>,
+    agent: Option<SessionId>,
+    agent_channels: HashMap<ChannelId, SessionId>,
+}
+
+/// X11 and agent channels opened by the target can't be told apart by
+/// session, so only one session at a time may have them delivered
+fn claim(owner: &mut Option<SessionId>, id: SessionId) -> bool {
+    match owner {
+        Some(current) if *current != id => false,
+        _ => {
+            *owner = Some(id);
+            true
+        }
+    }
+}
+
+impl Routes {
+    /// Detaches a session, returning its remote forwards and whether the connection is now unused
+    fn remove(&mut self, id: SessionId) -> (Vec<(String, u32)>, bool) {
+        self.subscribers.remove(&id);
+        let forwards = self
+            .remote_forwards
+            .iter()
+            .filter(|(_, owner)| **owner == id)
+            .map(|(forward, _)| forward.clone())
+            .collect::<Vec<_>>();
+        for forward in forwards.iter() {
+            self.remote_forwards.remove(forward);
+        }
+        if self.x11 == Some(id) {
+            self.x11 = None;
+        }
+        if self.agent == Some(id) {
+            self.agent = None;
+        }
+        self.agent_channels.retain(|_, owner| *owner != id);
+        (forwards, self.subscribers.is_empty())
+    }
+}
+
+/// An upstream connection used by several Warpgate sessions at once.
+/// Channels opened by the target are only delivered to the session
+/// that asked for them.
+pub(super) struct SharedConnection {
+    key: PoolKey,
+    pool: Weak<ConnectionPool>,
+    pub session: Arc<Mutex<Handle<ClientHandler>>>,
+    _jump_host_sessions: Vec<Handle<ClientHandler>>,
+    routes: std::sync::Mutex<Routes>,
+    releases: AtomicU64,
+}
+
+impl SharedConnection {
+    fn routes(&self) -> std::sync::MutexGuard<'_, Routes> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn claim_remote_forward(&self, id: SessionId, address: String, port: u32) {
+        self.routes().remote_forwards.insert((address, port), id);
+    }
+
+    pub fn release_remote_forward(&self, address: String, port: u32) {
+        self.routes().remote_forwards.remove(&(address, port));
+    }
+
+    /// Returns false if another session already receives the X11 channels
+    pub fn claim_x11(&self, id: SessionId) -> bool {
+        claim(&mut self.routes().x11, id)
+    }
+
+    /// Returns false if another session already receives the agent channels
+    pub fn claim_agent(&self, id: SessionId) -> bool {
+        claim(&mut self.routes().agent, id)
+    }
+
+    fn route(&self, event: ClientHandlerEvent) {
+        let mut routes = self.routes();
+        let target = match &event {
+            ClientHandlerEvent::Disconnect => {
+                for tx in routes.subscribers.values() {
+                    let _ = tx.send(InnerEvent::ClientHandlerEvent(
+                        ClientHandlerEvent::Disconnect,
+                    ));
+                }
+                return;
+            }
+            ClientHandlerEvent::ForwardedTcpIp(_, params) => routes
+                .remote_forwards
+                .get(&(params.connected_address.clone(), params.connected_port))
+                .cloned(),
+            ClientHandlerEvent::X11(..) => routes.x11,
+            ClientHandlerEvent::AgentForward(channel) => {
+                let target = routes.agent;
+                if let Some(id) = target {
+                    routes.agent_channels.insert(*channel, id);
+                }
+                target
+            }
+            ClientHandlerEvent::AgentData(channel, _) => {
+                routes.agent_channels.get(channel).cloned()
+            }
+            ClientHandlerEvent::AgentClose(channel) => routes.agent_channels.remove(channel),
+            _ => None,
+        };
+
+        match target.and_then(|id| routes.subscribers.get(&id)) {
+            Some(tx) => {
+                let _ = tx.send(InnerEvent::ClientHandlerEvent(event));
+            }
+            None => debug!(
+                ?event,
+                "No session to deliver the shared connection event to"
+            ),
+        }
+    }
+
+    /// Detaches a session from the connection, cancels its remote forwards,
+    /// and closes the connection once it has been unused for a while
+    pub fn release(self: &Arc<Self>, id: SessionId) {
+        let (forwards, idle) = self.routes().remove(id);
+
+        let generation = self.releases.fetch_add(1, Ordering::SeqCst) + 1;
+        let this = self.clone();
+        tokio::spawn(
+            async move {
+                for (address, port) in forwards {
+                    let _ = this
+                        .session
+                        .lock()
+                        .await
+                        .cancel_tcpip_forward(address, port)
+                        .await;
+                }
+                if !idle {
+                    return;
+                }
+
+                tokio::time::sleep(IDLE_LINGER).await;
+                if this.releases.load(Ordering::SeqCst) != generation
+                    || !this.routes().subscribers.is_empty()
+                {
+                    return;
+                }
+                this.remove_from_pool();
+                info!(
+                    host=%this.key.host,
+                    username=%this.key.username,
+                    "Closing the idle shared upstream connection"
+                );
+                let _ = this
+                    .session
+                    .lock()
+                    .await
+                    .disconnect(russh::Disconnect::ByApplication, "", "")
+                    .await;
+            }
+            .instrument(Span::current()),
+        );
+    }
+
+    fn remove_from_pool(self: &Arc<Self>) {
+        if let Some(pool) = self.pool.upgrade() {
+            pool.remove(self);
+        }
+    }
+}
+
+impl ConnectionPool {
+    fn connections(&self) -> std::sync::MutexGuard<'_, HashMap<PoolKey, Arc<SharedConnection>>> {
+        self.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn remove(&self, connection: &Arc<SharedConnection>) {
+        let mut connections = self.connections();
+        if connections
+            .get(&connection.key)
+            .map(|c| Arc::ptr_eq(c, connection))
+            .unwrap_or(false)
+        {
+            connections.remove(&connection.key);
+        }
+    }
+
+    /// Attaches a session to a live pooled connection, if there is one
+    pub async fn acquire(
+        &self,
+        key: &PoolKey,
+        id: SessionId,
+        inner_event_tx: UnboundedSender<InnerEvent>,
+    ) -> Option<Arc<SharedConnection>> {
+        let shared = self.connections().get(key).cloned()?;
+        if shared.session.lock().await.is_closed() {
+            self.remove(&shared);
+            return None;
+        }
+        shared.routes().subscribers.insert(id, inner_event_tx);
+        shared.releases.fetch_add(1, Ordering::SeqCst);
+        Some(shared)
+    }
+
+    /// Puts a freshly established connection into the pool and
+    /// starts delivering its events to the sessions using it
+    pub fn insert(
+        self: &Arc<Self>,
+        key: PoolKey,
+        id: SessionId,
+        session: Handle<ClientHandler>,
+        jump_host_sessions: Vec<Handle<ClientHandler>>,
+        event_rx: UnboundedReceiver<ClientHandlerEvent>,
+        inner_event_tx: UnboundedSender<InnerEvent>,
+    ) -> Arc<SharedConnection> {
+        let mut routes = Routes::default();
+        routes.subscribers.insert(id, inner_event_tx);
+
+        let shared = Arc::new(SharedConnection {
+            key: key.clone(),
+            pool: Arc::downgrade(self),
+            session: Arc::new(Mutex::new(session)),
+            _jump_host_sessions: jump_host_sessions,
+            routes: std::sync::Mutex::new(routes),
+            releases: AtomicU64::new(0),
+        });
+        self.connections().insert(key, shared.clone());
+        route_events(&shared, event_rx);
+        shared
+    }
+}
+
+fn route_events(
+    shared: &Arc<SharedConnection>,
+    mut event_rx: UnboundedReceiver<ClientHandlerEvent>,
+) {
+    let weak = Arc::downgrade(shared);
+    tokio::spawn(
+        async move {
+            while let Some(event) = event_rx.recv().await {
+                let Some(shared) = weak.upgrade() else {
+                    break;
+                };
+                if let ClientHandlerEvent::Disconnect = event {
+                    shared.remove_from_pool();
+                }
+                shared.route(event);
+            }
+        }
+        .instrument(Span::current()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::{SSHTargetAuth, SshTargetPasswordAuth};
+
+    use super::*;
+
+    fn options() -> TargetSSHOptions {
+        #[allow(clippy::unwrap_used)]
+        serde_json::from_str(r#"{"host": "10.0.0.1", "username": "root"}"#).unwrap()
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn pool_keys_tell_targets_apart() {
+        let key = |target: &str, options: &TargetSSHOptions| {
+            PoolKey::new("alice".into(), target.into(), options).unwrap()
+        };
+        let base = options();
+        assert_eq!(key("web", &base), key("web", &options()));
+        assert_ne!(key("web", &base), key("web-2", &base));
+
+        let mut via = options();
+        via.via = Some(vec!["bastion".into()]);
+        assert_ne!(key("web", &base), key("web", &via));
+
+        let mut password = options();
+        password.auth = SSHTargetAuth::Password(SshTargetPasswordAuth {
+            password: "hunter2".to_owned().into(),
+        });
+        assert_ne!(key("web", &base), key("web", &password));
+    }
+
+    #[test]
+    fn only_one_session_receives_x11_and_agent_channels() {
+        let (a, b) = (SessionId::new_v4(), SessionId::new_v4());
+        let mut routes = Routes::default();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        routes.subscribers.insert(a, tx.clone());
+        routes.subscribers.insert(b, tx);
+        routes.remote_forwards.insert(("0.0.0.0".into(), 8080), a);
+
+        assert!(claim(&mut routes.x11, a));
+        assert!(claim(&mut routes.x11, a));
+        assert!(!claim(&mut routes.x11, b));
+        assert!(claim(&mut routes.agent, b));
+        assert!(!claim(&mut routes.agent, a));
+
+        assert_eq!(
+            routes.remove(a),
+            (vec![("0.0.0.0".to_owned(), 8080)], false)
+        );
+        assert!(routes.remote_forwards.is_empty());
+        assert!(claim(&mut routes.x11, b));
+        assert_eq!(routes.agent, Some(b));
+        assert_eq!(routes.remove(b), (vec![], true));
+        assert_eq!(routes.agent, None);
+    }
+}
@@ -58,6 +58,7 @@ pub enum ChannelOperation {
     RequestExec(String),
     RequestX11(X11Request),
     RequestSubsystem(String),
+    RequestAgentForward,
     Data(Bytes),
     ExtendedData { data: Bytes, ext: u32 },
     Close,
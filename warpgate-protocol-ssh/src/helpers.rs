@@ -1,4 +1,4 @@
-use russh::keys::key::KeyPair;
+use russh::keys::key::{KeyPair, PublicKey};
 use russh::keys::PublicKeyBase64;
 
 pub trait PublicKeyAsOpenSSH {
@@ -14,3 +14,13 @@ impl PublicKeyAsOpenSSH for KeyPair {
         buf
     }
 }
+
+impl PublicKeyAsOpenSSH for PublicKey {
+    fn as_openssh(&self) -> String {
+        let mut buf = String::new();
+        buf.push_str(self.name());
+        buf.push(' ');
+        buf.push_str(&self.public_key_base64());
+        buf
+    }
+}
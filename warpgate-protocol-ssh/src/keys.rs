@@ -1,13 +1,30 @@
-use std::fs::{create_dir_all, File};
+use std::fs::{create_dir, create_dir_all, File};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use russh::keys::key::{KeyPair, SignatureHash};
 use russh::keys::{encode_pkcs8_pem, load_secret_key};
+use ssh_key::rand_core::OsRng;
+use ssh_key::{Algorithm, EcdsaCurve, LineEnding, PrivateKey};
 use tracing::*;
 use warpgate_common::helpers::fs::{secure_directory, secure_file};
 use warpgate_common::WarpgateConfig;
 
+use crate::certificates::{from_ssh_key_public, unix_time_now, CertificateError};
+
+const HOST_KEY_NAMES: &[&str] = &["host-ed25519", "host-ecdsa", "host-rsa"];
+/// Keys that replace the current host keys once the rotation is over
+const NEXT_HOST_KEYS_DIR: &str = "next";
+const RETIRED_HOST_KEYS_DIR: &str = "retired";
+const RETIRE_AT_FILE: &str = "retire-at";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyState {
+    Active,
+    Next,
+}
+
 fn get_keys_path(config: &WarpgateConfig) -> PathBuf {
     let mut path = config.paths_relative_to.clone();
     path.push(&config.store.ssh.keys);
@@ -19,35 +36,75 @@ pub fn generate_host_keys(config: &WarpgateConfig) -> Result<()> {
     create_dir_all(&path)?;
     secure_directory(&path)?;
 
-    let key_path = path.join("host-ed25519");
-    if !key_path.exists() {
-        info!("Generating Ed25519 host key");
-        let key = KeyPair::generate_ed25519().context("Failed to generate Ed25519 host key")?;
-        let f = File::create(&key_path)?;
-        encode_pkcs8_pem(&key, f)?;
+    for name in HOST_KEY_NAMES {
+        generate_host_key(&path, name)?;
     }
-    secure_file(&key_path)?;
 
-    let key_path = path.join("host-rsa");
+    Ok(())
+}
+
+fn generate_host_key(path: &Path, name: &str) -> Result<()> {
+    let key_path = path.join(name);
     if !key_path.exists() {
-        info!("Generating RSA host key");
-        let key = KeyPair::generate_rsa(4096, SignatureHash::SHA2_512)
-            .context("Failed to generate RSA key")?;
-        let f = File::create(&key_path)?;
-        encode_pkcs8_pem(&key, f)?;
+        match name {
+            "host-ed25519" => {
+                info!("Generating Ed25519 host key");
+                let key =
+                    KeyPair::generate_ed25519().context("Failed to generate Ed25519 host key")?;
+                let f = File::create(&key_path)?;
+                encode_pkcs8_pem(&key, f)?;
+            }
+            "host-ecdsa" => {
+                info!("Generating ECDSA host key");
+                let key = PrivateKey::random(
+                    &mut OsRng,
+                    Algorithm::Ecdsa {
+                        curve: EcdsaCurve::NistP256,
+                    },
+                )
+                .context("Failed to generate ECDSA host key")?;
+                key.write_openssh_file(&key_path, LineEnding::LF)?;
+            }
+            "host-rsa" => {
+                info!("Generating RSA host key");
+                let key = KeyPair::generate_rsa(4096, SignatureHash::SHA2_512)
+                    .context("Failed to generate RSA key")?;
+                let f = File::create(&key_path)?;
+                encode_pkcs8_pem(&key, f)?;
+            }
+            name => anyhow::bail!("Unknown host key type: {name}"),
+        }
     }
     secure_file(&key_path)?;
-
     Ok(())
 }
 
+/// Loads the current host keys followed by the next ones, if a rotation is in progress.
+/// russh presents the first key for each algorithm, so the next keys stay unused until
+/// they replace the current ones.
 pub fn load_host_keys(config: &WarpgateConfig) -> Result<Vec<KeyPair>, russh::keys::Error> {
     let path = get_keys_path(config);
+    let mut keys = load_host_key_set(&path)?;
+
+    let next_path = path.join(NEXT_HOST_KEYS_DIR);
+    if next_path.exists() {
+        keys.extend(load_host_key_set(&next_path)?);
+    }
+
+    Ok(keys)
+}
+
+fn load_host_key_set(path: &Path) -> Result<Vec<KeyPair>, russh::keys::Error> {
     let mut keys = Vec::new();
 
     let key_path = path.join("host-ed25519");
     keys.push(load_and_maybe_resave_ed25519_key(key_path)?);
 
+    let key_path = path.join("host-ecdsa");
+    if key_path.exists() {
+        keys.push(load_secret_key(key_path, None)?);
+    }
+
     let key_path = path.join("host-rsa");
     let key = load_secret_key(key_path, None)?;
     if let Some(key) = key.with_signature_hash(SignatureHash::SHA2_512) {
@@ -63,6 +120,103 @@ pub fn load_host_keys(config: &WarpgateConfig) -> Result<Vec<KeyPair>, russh::ke
     Ok(keys)
 }
 
+/// Public host keys along with whether they're in use or will replace the current ones
+pub fn list_host_keys(
+    config: &WarpgateConfig,
+) -> Result<Vec<(HostKeyState, russh::keys::key::PublicKey)>> {
+    let path = get_keys_path(config);
+    let mut keys = vec![];
+    for (state, path) in [
+        (HostKeyState::Active, path.clone()),
+        (HostKeyState::Next, path.join(NEXT_HOST_KEYS_DIR)),
+    ] {
+        for name in HOST_KEY_NAMES {
+            let key_path = path.join(name);
+            if key_path.exists() {
+                keys.push((state, load_secret_key(key_path, None)?.clone_public_key()?));
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Generates the next set of host keys. They replace the current ones
+/// once the configured rotation overlap has passed.
+pub fn rotate_host_keys(config: &WarpgateConfig) -> Result<SystemTime> {
+    rotate_host_keys_with(config, generate_host_key)
+}
+
+fn rotate_host_keys_with(
+    config: &WarpgateConfig,
+    generate: fn(&Path, &str) -> Result<()>,
+) -> Result<SystemTime> {
+    let path = get_keys_path(config).join(NEXT_HOST_KEYS_DIR);
+    if let Err(error) = create_dir(&path) {
+        if error.kind() == std::io::ErrorKind::AlreadyExists {
+            anyhow::bail!("A host key rotation is already in progress");
+        }
+        return Err(error.into());
+    }
+    secure_directory(&path)?;
+
+    for name in HOST_KEY_NAMES {
+        generate(&path, name)?;
+    }
+
+    let overlap = config.store.ssh.host_key_rotation_overlap;
+    let retire_at = unix_time_now()? + overlap.as_secs();
+    std::fs::write(path.join(RETIRE_AT_FILE), retire_at.to_string())?;
+    info!(
+        "Generated the next host keys, the current ones will be retired in {} days",
+        overlap.as_secs() / 86400
+    );
+
+    Ok(UNIX_EPOCH + Duration::from_secs(retire_at))
+}
+
+/// When the current host keys will be replaced, if a rotation is in progress
+pub fn host_key_retirement_time(config: &WarpgateConfig) -> Result<Option<SystemTime>> {
+    let path = get_keys_path(config)
+        .join(NEXT_HOST_KEYS_DIR)
+        .join(RETIRE_AT_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let seconds: u64 = std::fs::read_to_string(&path)?
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid timestamp in {path:?}"))?;
+    Ok(Some(UNIX_EPOCH + Duration::from_secs(seconds)))
+}
+
+/// Replaces the current host keys with the next ones once the rotation is over.
+/// The old keys are moved into the `retired` directory.
+pub fn retire_host_keys_if_due(config: &WarpgateConfig) -> Result<bool> {
+    match host_key_retirement_time(config)? {
+        Some(time) if time <= SystemTime::now() => (),
+        _ => return Ok(false),
+    }
+
+    let path = get_keys_path(config);
+    let next_path = path.join(NEXT_HOST_KEYS_DIR);
+    let retired_path = path
+        .join(RETIRED_HOST_KEYS_DIR)
+        .join(unix_time_now()?.to_string());
+    create_dir_all(&retired_path)?;
+    secure_directory(path.join(RETIRED_HOST_KEYS_DIR))?;
+    secure_directory(&retired_path)?;
+
+    for name in HOST_KEY_NAMES {
+        if path.join(name).exists() {
+            std::fs::rename(path.join(name), retired_path.join(name))?;
+        }
+        std::fs::rename(next_path.join(name), path.join(name))?;
+    }
+    std::fs::remove_dir_all(&next_path)?;
+    info!(retired_path = ?retired_path, "Replaced the host keys with the next ones");
+    Ok(true)
+}
+
 pub fn generate_client_keys(config: &WarpgateConfig) -> Result<()> {
     let path = get_keys_path(config);
     create_dir_all(&path)?;
@@ -103,6 +257,34 @@ pub fn load_client_keys(config: &WarpgateConfig) -> Result<Vec<KeyPair>, russh::
     Ok(keys)
 }
 
+pub fn generate_ca_key(config: &WarpgateConfig) -> Result<()> {
+    let path = get_keys_path(config);
+    create_dir_all(&path)?;
+    secure_directory(&path)?;
+
+    let key_path = path.join("ca-ed25519");
+    if !key_path.exists() {
+        info!("Generating Ed25519 certificate authority key");
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .context("Failed to generate Ed25519 CA key")?;
+        key.write_openssh_file(&key_path, LineEnding::LF)?;
+    }
+    secure_file(&key_path)?;
+
+    Ok(())
+}
+
+/// The CA key that Warpgate uses to sign short-lived certificates for its client keys
+pub fn load_ca_key(config: &WarpgateConfig) -> Result<PrivateKey, ssh_key::Error> {
+    PrivateKey::read_openssh_file(&get_keys_path(config).join("ca-ed25519"))
+}
+
+pub fn load_ca_public_key(
+    config: &WarpgateConfig,
+) -> Result<russh::keys::key::PublicKey, CertificateError> {
+    from_ssh_key_public(load_ca_key(config)?.public_key())
+}
+
 /// russh 0.43 has a bug that generates incorrect PKCS#8 encoding for Ed25519 keys
 /// This will preemptively try to correctly re-encode and save the key
 fn load_and_maybe_resave_ed25519_key<P: AsRef<Path>>(p: P) -> Result<KeyPair, russh::keys::Error> {
@@ -116,3 +298,73 @@ fn load_and_maybe_resave_ed25519_key<P: AsRef<Path>>(p: P) -> Result<KeyPair, ru
     };
     Ok(key)
 }
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::WarpgateConfigStore;
+
+    use super::*;
+    use crate::helpers::PublicKeyAsOpenSSH;
+
+    /// Generating RSA keys takes minutes in debug builds
+    fn generate_test_key(path: &Path, name: &str) -> Result<()> {
+        let key = KeyPair::generate_ed25519().context("Failed to generate a key")?;
+        encode_pkcs8_pem(&key, File::create(path.join(name))?)?;
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn rotates_and_retires_host_keys() {
+        let dir = std::env::temp_dir().join(format!("warpgate-keys-{}", uuid::Uuid::new_v4()));
+        let mut config = WarpgateConfig {
+            store: WarpgateConfigStore::default(),
+            paths_relative_to: dir.clone(),
+        };
+        config.store.ssh.host_key_rotation_overlap = Duration::from_secs(3600);
+        let keys_path = get_keys_path(&config);
+        create_dir_all(&keys_path).unwrap();
+        for name in HOST_KEY_NAMES {
+            generate_test_key(&keys_path, name).unwrap();
+        }
+        let active = |config: &WarpgateConfig| {
+            list_host_keys(config)
+                .unwrap()
+                .into_iter()
+                .filter(|(state, _)| *state == HostKeyState::Active)
+                .map(|(_, key)| key.as_openssh())
+                .collect::<Vec<_>>()
+        };
+        let original = active(&config);
+
+        let retire_at = rotate_host_keys_with(&config, generate_test_key).unwrap();
+        assert!(retire_at > SystemTime::now());
+        assert_eq!(host_key_retirement_time(&config).unwrap(), Some(retire_at));
+        assert!(rotate_host_keys_with(&config, generate_test_key).is_err());
+        let next = list_host_keys(&config)
+            .unwrap()
+            .into_iter()
+            .filter(|(state, _)| *state == HostKeyState::Next)
+            .map(|(_, key)| key.as_openssh())
+            .collect::<Vec<_>>();
+        assert_eq!(next.len(), HOST_KEY_NAMES.len());
+
+        // Not due yet
+        assert!(!retire_host_keys_if_due(&config).unwrap());
+        assert_eq!(active(&config), original);
+
+        std::fs::write(keys_path.join(NEXT_HOST_KEYS_DIR).join(RETIRE_AT_FILE), "0").unwrap();
+        assert!(retire_host_keys_if_due(&config).unwrap());
+        assert_eq!(active(&config), next);
+        assert_eq!(host_key_retirement_time(&config).unwrap(), None);
+        assert!(!keys_path.join(NEXT_HOST_KEYS_DIR).exists());
+        assert_eq!(
+            std::fs::read_dir(keys_path.join(RETIRED_HOST_KEYS_DIR))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -20,6 +20,21 @@ pub enum KnownHostValidationResult {
     Unknown,
 }
 
+/// Checks a key against fingerprints pinned in the target config.
+/// Fingerprints are accepted in the `ssh-keygen -l` format, with or without the `SHA256:` prefix.
+pub fn matches_pinned_fingerprint(key: &PublicKey, fingerprints: &[String]) -> bool {
+    let fingerprint = key.fingerprint();
+    let fingerprint = fingerprint.trim_end_matches('=');
+    fingerprints.iter().any(|pinned| {
+        let pinned = pinned.trim();
+        pinned
+            .strip_prefix("SHA256:")
+            .unwrap_or(pinned)
+            .trim_end_matches('=')
+            == fingerprint
+    })
+}
+
 impl KnownHosts {
     pub fn new(db: &Arc<Mutex<DatabaseConnection>>) -> Self {
         Self { db: db.clone() }
@@ -74,3 +89,37 @@ impl KnownHosts {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use russh::keys::key::KeyPair;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn matches_pinned_fingerprints() {
+        let key = KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap();
+        let other_key = KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap();
+
+        assert!(matches_pinned_fingerprint(
+            &key,
+            &[format!("SHA256:{}", key.fingerprint())]
+        ));
+        assert!(matches_pinned_fingerprint(
+            &key,
+            &[other_key.fingerprint(), key.fingerprint()]
+        ));
+        assert!(!matches_pinned_fingerprint(
+            &key,
+            &[format!("SHA256:{}", other_key.fingerprint())]
+        ));
+        assert!(!matches_pinned_fingerprint(&key, &[]));
+    }
+}
@@ -1,16 +1,21 @@
 #![feature(type_alias_impl_trait, try_blocks)]
+mod algorithms;
+mod certificates;
 mod client;
 mod common;
 mod compat;
 pub mod helpers;
 mod keys;
 mod known_hosts;
+mod scp;
 mod server;
+mod sftp;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 
 use anyhow::Result;
 use async_trait::async_trait;
+pub use certificates::*;
 pub use client::*;
 pub use common::*;
 pub use keys::*;
@@ -31,7 +36,9 @@ impl SSHProtocolServer {
     pub async fn new(services: &Services) -> Result<Self> {
         let config = services.config.lock().await;
         generate_host_keys(&config)?;
+        retire_host_keys_if_due(&config)?;
         generate_client_keys(&config)?;
+        generate_ca_key(&config)?;
         Ok(SSHProtocolServer {
             services: services.clone(),
         })
@@ -53,9 +60,10 @@ impl ProtocolServer for SSHProtocolServer {
 
         let mut handles = RemoteClient::create(Uuid::new_v4(), self.services.clone())?;
 
-        let _ = handles
-            .command_tx
-            .send((RCCommand::Connect(ssh_options), None));
+        let _ = handles.command_tx.send((
+            RCCommand::Connect(target.name.clone(), Box::new(ssh_options)),
+            None,
+        ));
 
         while let Some(event) = handles.event_rx.recv().await {
             match event {
@@ -91,6 +99,28 @@ impl ProtocolServer for SSHProtocolServer {
                     }
                 }
                 RCEvent::HostKeyReceived(_) => (),
+                RCEvent::KeyboardInteractive(prompt, reply) => {
+                    for text in [prompt.name, prompt.instructions] {
+                        if !text.trim().is_empty() {
+                            println!("{}", text.trim());
+                        }
+                    }
+                    let mut answers = vec![];
+                    for prompt in prompt.prompts {
+                        answers.push(if prompt.echo {
+                            dialoguer::Input::<String>::new()
+                                .with_prompt(prompt.prompt.trim())
+                                .allow_empty(true)
+                                .interact_text()?
+                        } else {
+                            dialoguer::Password::new()
+                                .with_prompt(prompt.prompt.trim())
+                                .allow_empty_password(true)
+                                .interact()?
+                        });
+                    }
+                    let _ = reply.send(Some(answers));
+                }
                 RCEvent::ConnectionError(err) => {
                     if let ConnectionError::HostKeyMismatch {
                         ref received_key_type,
@@ -106,6 +136,15 @@ impl ProtocolServer for SSHProtocolServer {
                         println!("If you know that the key is correct (e.g. it has been changed),");
                         println!("you can remove the old key in the Warpgate management UI and try again");
                     }
+                    if let ConnectionError::HostKeyNotPinned {
+                        ref received_key_type,
+                        ref received_fingerprint,
+                    } = err
+                    {
+                        println!("\n");
+                        println!("Received key ({received_key_type}): {received_fingerprint}");
+                        println!("Host key doesn't match any of the pinned fingerprints.");
+                    }
                     return Err(TargetTestError::ConnectionError(format!("{err:?}")));
                 }
                 RCEvent::State(state) => match state {
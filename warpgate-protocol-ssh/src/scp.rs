@@ -0,0 +1,436 @@
+//! Passive SCP (`scp -t` / `scp -f`) stream decoder used for auditing file
+//! transfers. Like the SFTP tracker, it only observes the exec channel.
+//!
+//! The side sending files (the source) emits `C`/`D`/`E`/`T` control lines
+//! followed by file contents, and the receiving side (the sink) acknowledges
+//! every step with a zero byte or an error line. Transfers are reported once
+//! the sink has acknowledged the file contents.
+
+use std::collections::VecDeque;
+
+use tracing::*;
+use warpgate_core::recordings::{FileTransferEvent, FileTransferOperation};
+
+/// Anything longer is not a sane SCP control line and means we lost sync
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScpDirection {
+    /// `scp -t` - the client sends files to the target
+    Upload,
+    /// `scp -f` - the target sends files to the client
+    Download,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScpParseError {
+    #[error("control line too long")]
+    LineTooLong,
+    #[error("invalid control line: {0:?}")]
+    InvalidLine(String),
+}
+
+enum SourceState {
+    Line(Vec<u8>),
+    Data(u64),
+    EndMarker,
+    /// Skipping the rest of an error message after a failed file
+    ErrorLine,
+}
+
+enum SinkState {
+    Ack,
+    ErrorLine,
+}
+
+enum PendingAck {
+    /// Acknowledgement of a `C` line - the sink is ready to receive the file
+    FileHeader(FileTransferEvent),
+    /// Acknowledgement of the file contents
+    FileData(FileTransferEvent),
+    Directory(Option<FileTransferEvent>),
+    Other,
+}
+
+/// Tracks a single SCP exec channel and produces [FileTransferEvent]s
+/// for completed transfers.
+pub struct ScpTracker {
+    direction: ScpDirection,
+    path: String,
+    directory_target: bool,
+    source_state: SourceState,
+    sink_state: SinkState,
+    pending: VecDeque<PendingAck>,
+    current_file: Option<FileTransferEvent>,
+    directories: Vec<String>,
+    desynced: bool,
+}
+
+impl ScpTracker {
+    /// Recognizes the remote side of an SCP transfer in an exec command
+    pub fn from_command(command: &str) -> Option<Self> {
+        let mut words = command.split_whitespace();
+        let program = words.next()?;
+        if program.rsplit('/').next() != Some("scp") {
+            return None;
+        }
+
+        let mut direction = None;
+        let mut directory_target = false;
+        let mut paths = vec![];
+        let mut options_done = false;
+        for word in words {
+            if !options_done && word == "--" {
+                options_done = true;
+            } else if !options_done && word.starts_with('-') {
+                for flag in word.chars().skip(1) {
+                    match flag {
+                        't' => direction = Some(ScpDirection::Upload),
+                        'f' => direction = Some(ScpDirection::Download),
+                        'r' | 'd' => directory_target = true,
+                        _ => (),
+                    }
+                }
+            } else {
+                options_done = true;
+                paths.push(unquote(word));
+            }
+        }
+
+        Some(Self {
+            direction: direction?,
+            path: paths.into_iter().next().unwrap_or_default(),
+            directory_target,
+            source_state: SourceState::Line(vec![]),
+            sink_state: SinkState::Ack,
+            pending: VecDeque::new(),
+            current_file: None,
+            directories: vec![],
+            desynced: false,
+        })
+    }
+
+    /// Feeds data sent by the client
+    pub fn client_data(&mut self, data: &[u8]) -> Vec<FileTransferEvent> {
+        match self.direction {
+            ScpDirection::Upload => self.source_data(data),
+            ScpDirection::Download => self.sink_data(data),
+        }
+    }
+
+    /// Feeds data sent by the target
+    pub fn server_data(&mut self, data: &[u8]) -> Vec<FileTransferEvent> {
+        match self.direction {
+            ScpDirection::Upload => self.sink_data(data),
+            ScpDirection::Download => self.source_data(data),
+        }
+    }
+
+    /// Reports transfers that were never acknowledged
+    pub fn finish(&mut self) -> Vec<FileTransferEvent> {
+        self.pending
+            .drain(..)
+            .filter_map(|ack| match ack {
+                PendingAck::FileHeader(event) | PendingAck::FileData(event) => Some(event),
+                _ => None,
+            })
+            .chain(self.current_file.take())
+            .map(|event| FileTransferEvent {
+                success: false,
+                ..event
+            })
+            .collect()
+    }
+
+    fn desync(&mut self, error: ScpParseError) {
+        warn!(%error, "Lost track of the SCP stream, file transfers will not be audited");
+        self.desynced = true;
+    }
+
+    fn source_data(&mut self, mut data: &[u8]) -> Vec<FileTransferEvent> {
+        let mut events = vec![];
+        while !data.is_empty() && !self.desynced {
+            match self.source_state {
+                SourceState::Line(ref mut line) => {
+                    let end = data.iter().position(|b| *b == b'\n');
+                    let (chunk, rest) = data.split_at(end.map(|x| x + 1).unwrap_or(data.len()));
+                    line.extend_from_slice(chunk);
+                    data = rest;
+                    if line.len() > MAX_LINE_LENGTH {
+                        self.desync(ScpParseError::LineTooLong);
+                    } else if end.is_some() {
+                        let line = std::mem::take(line);
+                        if let Err(error) = self.handle_control_line(&line) {
+                            self.desync(error);
+                        }
+                    }
+                }
+                SourceState::Data(ref mut remaining) => {
+                    let length = (*remaining).min(data.len() as u64);
+                    *remaining -= length;
+                    data = data.get(length as usize..).unwrap_or_default();
+                    if *remaining == 0 {
+                        self.source_state = SourceState::EndMarker;
+                    }
+                }
+                SourceState::EndMarker => {
+                    let Some((&marker, rest)) = data.split_first() else {
+                        break;
+                    };
+                    data = rest;
+                    let Some(event) = self.current_file.take() else {
+                        self.source_state = SourceState::Line(vec![]);
+                        continue;
+                    };
+                    if marker == 0 {
+                        self.pending.push_back(PendingAck::FileData(event));
+                        self.source_state = SourceState::Line(vec![]);
+                    } else {
+                        // The source failed to read the file and sends an error line instead
+                        events.push(FileTransferEvent {
+                            success: false,
+                            ..event
+                        });
+                        self.source_state = SourceState::ErrorLine;
+                    }
+                }
+                SourceState::ErrorLine => {
+                    let end = data.iter().position(|b| *b == b'\n');
+                    data = data
+                        .get(end.map(|x| x + 1).unwrap_or(data.len())..)
+                        .unwrap_or_default();
+                    if end.is_some() {
+                        self.source_state = SourceState::Line(vec![]);
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    fn handle_control_line(&mut self, line: &[u8]) -> Result<(), ScpParseError> {
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches('\n');
+        let invalid = || ScpParseError::InvalidLine(text.to_owned());
+        let mut chars = text.chars();
+        let Some(kind) = chars.next() else {
+            return Err(invalid());
+        };
+        let rest = chars.as_str();
+        match kind {
+            'C' | 'D' => {
+                let mut parts = rest.splitn(3, ' ');
+                let mode = parts
+                    .next()
+                    .and_then(|x| u32::from_str_radix(x, 8).ok())
+                    .ok_or_else(invalid)?;
+                let size = parts
+                    .next()
+                    .and_then(|x| x.parse::<u64>().ok())
+                    .ok_or_else(invalid)?;
+                let name = parts.next().ok_or_else(invalid)?;
+                if name.contains('/') || name == ".." {
+                    return Err(invalid());
+                }
+
+                let path = self.full_path(name);
+                if kind == 'D' {
+                    self.directories.push(name.to_owned());
+                    self.pending.push_back(PendingAck::Directory(
+                        (self.direction == ScpDirection::Upload).then_some(FileTransferEvent {
+                            operation: FileTransferOperation::Mkdir,
+                            path,
+                            target_path: None,
+                            bytes: None,
+                            mode: Some(mode),
+                            success: true,
+                        }),
+                    ));
+                    return Ok(());
+                }
+
+                let event = FileTransferEvent {
+                    operation: match self.direction {
+                        ScpDirection::Upload => FileTransferOperation::Write,
+                        ScpDirection::Download => FileTransferOperation::Read,
+                    },
+                    path,
+                    target_path: None,
+                    bytes: Some(size),
+                    mode: Some(mode),
+                    success: true,
+                };
+                self.current_file = Some(event.clone());
+                self.pending.push_back(PendingAck::FileHeader(event));
+                self.source_state = match size {
+                    0 => SourceState::EndMarker,
+                    size => SourceState::Data(size),
+                };
+            }
+            'E' => {
+                self.directories.pop();
+                self.pending.push_back(PendingAck::Other);
+            }
+            'T' => self.pending.push_back(PendingAck::Other),
+            '\x01' | '\x02' => {
+                info!(message=%rest.trim(), "SCP source reported an error");
+            }
+            _ => return Err(invalid()),
+        }
+        Ok(())
+    }
+
+    fn sink_data(&mut self, data: &[u8]) -> Vec<FileTransferEvent> {
+        let mut events = vec![];
+        if self.desynced {
+            return events;
+        }
+        for byte in data {
+            match self.sink_state {
+                SinkState::Ack => {
+                    if *byte != 0 {
+                        self.sink_state = SinkState::ErrorLine;
+                    }
+                    events.extend(self.acknowledged(*byte == 0));
+                }
+                SinkState::ErrorLine => {
+                    if *byte == b'\n' {
+                        self.sink_state = SinkState::Ack;
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    fn acknowledged(&mut self, ok: bool) -> Option<FileTransferEvent> {
+        // The sink also sends an ack before the source starts, which has nothing pending
+        match self.pending.pop_front()? {
+            PendingAck::FileHeader(event) => {
+                if ok {
+                    return None;
+                }
+                // The sink refused the file, so the source won't send its contents
+                self.current_file = None;
+                self.source_state = SourceState::Line(vec![]);
+                Some(FileTransferEvent {
+                    success: false,
+                    ..event
+                })
+            }
+            PendingAck::FileData(event) | PendingAck::Directory(Some(event)) => {
+                Some(FileTransferEvent {
+                    success: ok,
+                    ..event
+                })
+            }
+            PendingAck::Directory(None) | PendingAck::Other => None,
+        }
+    }
+
+    fn full_path(&self, name: &str) -> String {
+        let relative = self
+            .directories
+            .iter()
+            .map(String::as_str)
+            .chain([name])
+            .collect::<Vec<_>>()
+            .join("/");
+        match self.direction {
+            ScpDirection::Upload => {
+                // Without -d or -r, the target path may name the file itself
+                if self.directory_target
+                    || !self.directories.is_empty()
+                    || self.path.is_empty()
+                    || self.path == "."
+                    || self.path.ends_with('/')
+                {
+                    join_path(&self.path, &relative)
+                } else {
+                    self.path.clone()
+                }
+            }
+            ScpDirection::Download => {
+                let parent = match self.path.trim_end_matches('/').rsplit_once('/') {
+                    Some(("", _)) => "/",
+                    Some((parent, _)) => parent,
+                    None => "",
+                };
+                join_path(parent, &relative)
+            }
+        }
+    }
+}
+
+fn join_path(base: &str, relative: &str) -> String {
+    if base.is_empty() {
+        relative.to_owned()
+    } else if base.ends_with('/') {
+        format!("{base}{relative}")
+    } else {
+        format!("{base}/{relative}")
+    }
+}
+
+fn unquote(word: &str) -> String {
+    for quote in ['\'', '"'] {
+        if let Some(word) = word.strip_prefix(quote).and_then(|w| w.strip_suffix(quote)) {
+            return word.to_owned();
+        }
+    }
+    word.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_upload() {
+        let mut tracker = ScpTracker::from_command("scp -v -r -t -- /tmp/upload").unwrap();
+
+        assert!(tracker.server_data(b"\0").is_empty());
+        assert!(tracker.client_data(b"D0755 0 dir\n").is_empty());
+        let events = tracker.server_data(b"\0");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, FileTransferOperation::Mkdir);
+        assert_eq!(events[0].path, "/tmp/upload/dir");
+
+        // deliver in fragments
+        assert!(tracker.client_data(b"C0644 5 ").is_empty());
+        assert!(tracker.client_data(b"file.txt\n").is_empty());
+        assert!(tracker.server_data(b"\0").is_empty());
+        assert!(tracker.client_data(b"hel").is_empty());
+        assert!(tracker.client_data(b"lo\0").is_empty());
+        let events = tracker.server_data(b"\0");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, FileTransferOperation::Write);
+        assert_eq!(events[0].path, "/tmp/upload/dir/file.txt");
+        assert_eq!(events[0].bytes, Some(5));
+        assert_eq!(events[0].mode, Some(0o644));
+        assert!(events[0].success);
+
+        assert!(tracker.client_data(b"E\n").is_empty());
+        assert!(tracker.server_data(b"\0").is_empty());
+        assert!(tracker.finish().is_empty());
+    }
+
+    #[test]
+    fn tracks_failed_download() {
+        let mut tracker = ScpTracker::from_command("scp -f /etc/shadow").unwrap();
+
+        assert!(tracker.client_data(b"\0").is_empty());
+        assert!(tracker.server_data(b"C0640 1024 shadow\n").is_empty());
+        let events = tracker.client_data(b"\x01scp: shadow: Permission denied\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, FileTransferOperation::Read);
+        assert_eq!(events[0].path, "/etc/shadow");
+        assert!(!events[0].success);
+    }
+
+    #[test]
+    fn ignores_other_commands() {
+        assert!(ScpTracker::from_command("ls -t /tmp").is_none());
+        assert!(ScpTracker::from_command("scp file host:").is_none());
+    }
+}
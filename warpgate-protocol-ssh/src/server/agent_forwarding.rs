@@ -0,0 +1,270 @@
+//! Relays SSH agent connections opened by the target back to the client.
+//!
+//! The client only ever gets a single agent channel from Warpgate, while the
+//! target may open any number of them. Since the agent protocol is strictly
+//! request/response, requests from all target-side channels are queued and
+//! passed to the client one at a time, and each reply is routed back to the
+//! channel that sent the request.
+
+use std::collections::{HashMap, VecDeque};
+
+use bytes::{Buf, Bytes, BytesMut};
+use russh::keys::key::parse_public_key;
+use tracing::*;
+use uuid::Uuid;
+
+/// Same limit as OpenSSH's `AGENT_MAX_LEN`
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH2_AGENTC_SIGN_REQUEST: u8 = 13;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AgentForwardingError {
+    #[error("invalid agent message length: {0}")]
+    InvalidLength(usize),
+}
+
+/// Splits an agent byte stream into complete messages, length prefix included
+#[derive(Default)]
+struct MessageStream {
+    buffer: BytesMut,
+}
+
+impl MessageStream {
+    fn feed(&mut self, data: &[u8]) -> Result<Vec<Bytes>, AgentForwardingError> {
+        self.buffer.extend_from_slice(data);
+        let mut messages = vec![];
+        while self.buffer.len() >= 4 {
+            #[allow(clippy::indexing_slicing)] // length checked
+            let length = u32::from_be_bytes([
+                self.buffer[0],
+                self.buffer[1],
+                self.buffer[2],
+                self.buffer[3],
+            ]) as usize;
+            if length == 0 || length > MAX_MESSAGE_SIZE {
+                return Err(AgentForwardingError::InvalidLength(length));
+            }
+            if self.buffer.len() < 4 + length {
+                break;
+            }
+            messages.push(self.buffer.split_to(4 + length).freeze());
+        }
+        Ok(messages)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AgentAction {
+    SendToClient(Bytes),
+    SendToTarget(Uuid, Bytes),
+}
+
+#[derive(Default)]
+pub struct AgentForwarder {
+    client_connected: bool,
+    client_stream: MessageStream,
+    target_streams: HashMap<Uuid, MessageStream>,
+    queue: VecDeque<(Uuid, Bytes)>,
+    in_flight: Option<Uuid>,
+    actions: Vec<AgentAction>,
+}
+
+impl AgentForwarder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_target_channel(&self, channel: &Uuid) -> bool {
+        self.target_streams.contains_key(channel)
+    }
+
+    pub fn client_channel_opened(&mut self) {
+        self.client_connected = true;
+        self.client_stream = MessageStream::default();
+    }
+
+    pub fn add_target_channel(&mut self, channel: Uuid) {
+        self.target_streams
+            .insert(channel, MessageStream::default());
+    }
+
+    /// Feeds data sent by the target and returns the complete requests in it
+    pub fn target_data(
+        &mut self,
+        channel: Uuid,
+        data: &[u8],
+    ) -> Result<Vec<Bytes>, AgentForwardingError> {
+        let Some(stream) = self.target_streams.get_mut(&channel) else {
+            return Ok(vec![]);
+        };
+        let requests = match stream.feed(data) {
+            Ok(requests) => requests,
+            Err(error) => {
+                self.remove_target_channel(&channel);
+                return Err(error);
+            }
+        };
+        for request in requests.iter() {
+            self.queue.push_back((channel, request.clone()));
+        }
+        self.pump();
+        Ok(requests)
+    }
+
+    /// Feeds data sent by the client's agent
+    pub fn client_data(&mut self, data: &[u8]) -> Result<(), AgentForwardingError> {
+        let responses = match self.client_stream.feed(data) {
+            Ok(responses) => responses,
+            Err(error) => {
+                self.client_channel_closed();
+                return Err(error);
+            }
+        };
+        for response in responses {
+            match self.in_flight.take() {
+                Some(channel) if self.target_streams.contains_key(&channel) => {
+                    self.actions
+                        .push(AgentAction::SendToTarget(channel, response));
+                }
+                // The requesting channel is gone already
+                Some(_) => (),
+                None => warn!("Unsolicited agent response from the client"),
+            }
+        }
+        self.pump();
+        Ok(())
+    }
+
+    pub fn remove_target_channel(&mut self, channel: &Uuid) {
+        self.target_streams.remove(channel);
+        self.queue.retain(|(c, _)| c != channel);
+    }
+
+    pub fn client_channel_closed(&mut self) {
+        self.client_connected = false;
+        if let Some(channel) = self.in_flight.take() {
+            self.fail_request(channel);
+        }
+        self.pump();
+    }
+
+    /// Returns the data that has to be sent out as a result of previous calls
+    pub fn take_actions(&mut self) -> Vec<AgentAction> {
+        std::mem::take(&mut self.actions)
+    }
+
+    fn pump(&mut self) {
+        while self.in_flight.is_none() {
+            let Some((channel, request)) = self.queue.pop_front() else {
+                break;
+            };
+            if self.client_connected {
+                self.in_flight = Some(channel);
+                self.actions.push(AgentAction::SendToClient(request));
+            } else {
+                self.fail_request(channel);
+            }
+        }
+    }
+
+    fn fail_request(&mut self, channel: Uuid) {
+        if self.target_streams.contains_key(&channel) {
+            self.actions.push(AgentAction::SendToTarget(
+                channel,
+                Bytes::from_static(&[0, 0, 0, 1, SSH_AGENT_FAILURE]),
+            ));
+        }
+    }
+}
+
+/// Human-readable description of an agent request for the session log
+pub fn describe_agent_request(request: &[u8]) -> String {
+    let mut request = Bytes::copy_from_slice(request);
+    if request.remaining() < 5 {
+        return "invalid request".into();
+    }
+    request.advance(4);
+    let message_type = request.get_u8();
+    let name = match message_type {
+        11 => "list identities",
+        SSH2_AGENTC_SIGN_REQUEST => "sign",
+        17 | 25 => "add identity",
+        18 => "remove identity",
+        19 => "remove all identities",
+        20 | 26 => "add smartcard key",
+        21 => "remove smartcard key",
+        22 => "lock",
+        23 => "unlock",
+        27 => "extension",
+        _ => return format!("unknown request type {message_type}"),
+    };
+
+    if message_type == SSH2_AGENTC_SIGN_REQUEST && request.remaining() >= 4 {
+        let length = request.get_u32() as usize;
+        if let Some(key) = request
+            .get(..length)
+            .and_then(|blob| parse_public_key(blob, None).ok())
+        {
+            return format!(
+                "{name} with {} key SHA256:{}",
+                key.name(),
+                key.fingerprint()
+            );
+        }
+    }
+
+    name.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(payload: &[u8]) -> Bytes {
+        let mut message = (payload.len() as u32).to_be_bytes().to_vec();
+        message.extend_from_slice(payload);
+        message.into()
+    }
+
+    #[test]
+    fn routes_responses_in_order() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut forwarder = AgentForwarder::new();
+        forwarder.client_channel_opened();
+        forwarder.add_target_channel(a);
+        forwarder.add_target_channel(b);
+
+        let request_a = message(&[11]);
+        let request_b = message(&[11, 0]);
+        #[allow(clippy::unwrap_used)]
+        {
+            forwarder.target_data(a, &request_a[..3]).unwrap();
+            forwarder.target_data(b, &request_b).unwrap();
+            forwarder.target_data(a, &request_a[3..]).unwrap();
+        }
+        // Only one request may be in flight at a time
+        assert_eq!(
+            forwarder.take_actions(),
+            vec![AgentAction::SendToClient(request_b.clone())]
+        );
+
+        let response_b = message(&[12, 1]);
+        #[allow(clippy::unwrap_used)]
+        forwarder.client_data(&response_b).unwrap();
+        assert_eq!(
+            forwarder.take_actions(),
+            vec![
+                AgentAction::SendToTarget(b, response_b),
+                AgentAction::SendToClient(request_a),
+            ]
+        );
+
+        forwarder.client_channel_closed();
+        assert_eq!(
+            forwarder.take_actions(),
+            vec![AgentAction::SendToTarget(a, message(&[SSH_AGENT_FAILURE]))]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Relays the target's keyboard-interactive prompts to the user over the PTY.
+//! Answers are only echoed back if the target asks for it.
+
+use russh::client::Prompt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthPromptAction {
+    Output(String),
+    Done(String, Vec<String>),
+    Cancel,
+}
+
+pub struct AuthPrompt {
+    prompts: Vec<Prompt>,
+    answers: Vec<String>,
+    input: String,
+    after_cr: bool,
+}
+
+impl AuthPrompt {
+    pub fn new(prompts: Vec<Prompt>) -> Self {
+        Self {
+            prompts,
+            answers: vec![],
+            input: String::new(),
+            after_cr: false,
+        }
+    }
+
+    /// Renders the target's instructions followed by the first prompt
+    pub fn render_start(&self, name: &str, instructions: &str) -> String {
+        let mut output = String::new();
+        for text in [name, instructions] {
+            let text = text.trim();
+            if !text.is_empty() {
+                output.push_str(&text.replace('\n', "\r\n"));
+                output.push_str("\r\n");
+            }
+        }
+        output.push_str(&self.current_prompt());
+        output
+    }
+
+    fn current_prompt(&self) -> String {
+        self.prompts
+            .get(self.answers.len())
+            .map(|p| p.prompt.clone())
+            .unwrap_or_default()
+    }
+
+    fn echo(&self) -> bool {
+        self.prompts
+            .get(self.answers.len())
+            .map(|p| p.echo)
+            .unwrap_or(false)
+    }
+
+    pub fn handle_input(&mut self, data: &[u8]) -> AuthPromptAction {
+        let mut output = String::new();
+        for c in String::from_utf8_lossy(data).chars() {
+            let after_cr = std::mem::replace(&mut self.after_cr, c == '\r');
+            match c {
+                // Ctrl-C, Ctrl-D
+                '\x03' | '\x04' => return AuthPromptAction::Cancel,
+                '\n' if after_cr => (),
+                '\r' | '\n' => {
+                    self.answers.push(std::mem::take(&mut self.input));
+                    output.push_str("\r\n");
+                    if self.answers.len() >= self.prompts.len() {
+                        return AuthPromptAction::Done(output, std::mem::take(&mut self.answers));
+                    }
+                    output.push_str(&self.current_prompt());
+                }
+                // Backspace
+                '\x7f' | '\x08' => {
+                    if self.input.pop().is_some() && self.echo() {
+                        output.push_str("\x08 \x08");
+                    }
+                }
+                c if c.is_control() => (),
+                c => {
+                    self.input.push(c);
+                    if self.echo() {
+                        output.push(c);
+                    }
+                }
+            }
+        }
+        AuthPromptAction::Output(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_answers() {
+        let mut prompt = AuthPrompt::new(vec![
+            Prompt {
+                prompt: "Password: ".to_owned(),
+                echo: false,
+            },
+            Prompt {
+                prompt: "Code: ".to_owned(),
+                echo: true,
+            },
+        ]);
+        assert_eq!(
+            prompt.render_start("", "Enter your code\n"),
+            "Enter your code\r\nPassword: "
+        );
+        assert_eq!(
+            prompt.handle_input(b"hunter3\x7f2"),
+            AuthPromptAction::Output(String::new())
+        );
+        assert_eq!(
+            prompt.handle_input(b"\r\n12"),
+            AuthPromptAction::Output("\r\nCode: 12".to_owned())
+        );
+        assert_eq!(
+            prompt.handle_input(b"3\r"),
+            AuthPromptAction::Done(
+                "3\r\n".to_owned(),
+                vec!["hunter2".to_owned(), "123".to_owned()]
+            )
+        );
+        assert_eq!(prompt.handle_input(&[0x03]), AuthPromptAction::Cancel);
+    }
+}
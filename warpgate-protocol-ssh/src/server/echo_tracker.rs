@@ -0,0 +1,103 @@
+//! Guesses whether the terminal is currently echoing the user's input so that
+//! passwords typed at prompts can be left out of recordings. The remote
+//! termios state isn't visible over SSH, so this relies on the PTY modes
+//! requested by the client and on recognizing password prompts in the output.
+
+use bytes::Bytes;
+use russh::Pty;
+
+/// Only the tail of the current output line is needed to spot a prompt
+const MAX_LINE_LENGTH: usize = 256;
+
+const PROMPT_KEYWORDS: &[&str] = &[
+    "password",
+    "passphrase",
+    "passcode",
+    "pin",
+    "verification code",
+    "one-time",
+    "otp",
+];
+
+pub struct EchoTracker {
+    pty_echo: bool,
+    at_prompt: bool,
+    line: String,
+}
+
+impl EchoTracker {
+    pub fn new() -> Self {
+        Self {
+            pty_echo: true,
+            at_prompt: false,
+            line: String::new(),
+        }
+    }
+
+    pub fn set_pty_modes(&mut self, modes: &[(Pty, u32)]) {
+        if let Some((_, value)) = modes.iter().find(|(mode, _)| *mode == Pty::ECHO) {
+            self.pty_echo = *value != 0;
+        }
+    }
+
+    pub fn output(&mut self, data: &[u8]) {
+        for c in String::from_utf8_lossy(data).chars() {
+            match c {
+                '\r' | '\n' => self.line.clear(),
+                c if c.is_control() => (),
+                c => {
+                    if self.line.len() >= MAX_LINE_LENGTH {
+                        self.line.remove(0);
+                    }
+                    self.line.push(c);
+                }
+            }
+        }
+        if is_password_prompt(&self.line) {
+            self.at_prompt = true;
+        }
+    }
+
+    /// Returns the input with everything typed while echo is off removed.
+    /// Line endings are kept so the recording still shows when a prompt was answered.
+    pub fn redact_input(&mut self, data: &[u8]) -> Bytes {
+        let mut redacted = Vec::with_capacity(data.len());
+        for &byte in data {
+            let echo = self.pty_echo && !self.at_prompt;
+            match byte {
+                b'\r' | b'\n' | 0x03 | 0x04 => {
+                    self.at_prompt = false;
+                    self.line.clear();
+                    redacted.push(byte);
+                }
+                _ if echo => redacted.push(byte),
+                _ => (),
+            }
+        }
+        Bytes::from(redacted)
+    }
+}
+
+fn is_password_prompt(line: &str) -> bool {
+    let line = line.trim_end().to_lowercase();
+    line.ends_with(':') && PROMPT_KEYWORDS.iter().any(|k| line.contains(k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_input_at_password_prompts() {
+        let mut tracker = EchoTracker::new();
+        tracker.output(b"$ ");
+        assert_eq!(tracker.redact_input(b"sudo ls\r"), &b"sudo ls\r"[..]);
+
+        tracker.output(b"\r\n[sudo] password for user: ");
+        assert_eq!(tracker.redact_input(b"hunter"), &b""[..]);
+        assert_eq!(tracker.redact_input(b"2\rls"), &b"\rls"[..]);
+
+        tracker.set_pty_modes(&[(Pty::ECHO, 0)]);
+        assert_eq!(tracker.redact_input(b"secret\n"), &b"\n"[..]);
+    }
+}
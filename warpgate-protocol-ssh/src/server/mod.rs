@@ -1,8 +1,15 @@
+mod agent_forwarding;
+mod auth_prompt;
 mod channel_writer;
+mod echo_tracker;
+mod observers;
+mod policy;
 mod russh_handler;
 mod service_output;
 mod session;
 mod session_handle;
+mod target_picker;
+mod tunnel;
 use std::borrow::Cow;
 use std::fmt::Debug;
 use std::net::SocketAddr;
@@ -17,39 +24,96 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::sync::mpsc::unbounded_channel;
 use tracing::*;
+use warpgate_common::WarpgateConfig;
 use warpgate_core::{Services, SessionStateInit};
 
-use crate::keys::load_host_keys;
+use crate::algorithms::{apply_algorithms, KexInit, KexInitSniffer};
+use crate::keys::{load_host_keys, retire_host_keys_if_due};
 use crate::server::session_handle::SSHSessionHandle;
 
-pub async fn run_server(services: Services, address: SocketAddr) -> Result<()> {
-    let russh_config = {
-        let config = services.config.lock().await;
-        russh::server::Config {
-            auth_rejection_time: Duration::from_secs(1),
-            auth_rejection_time_initial: Some(Duration::from_secs(0)),
-            inactivity_timeout: Some(config.store.ssh.inactivity_timeout),
-            methods: MethodSet::PUBLICKEY | MethodSet::PASSWORD | MethodSet::KEYBOARD_INTERACTIVE,
-            keys: load_host_keys(&config)?,
-            event_buffer_size: 100,
-            preferred: Preferred {
+/// How often to check whether the next host keys should take over
+const HOST_KEY_RETIREMENT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// russh wants a &'static str, so the banner is leaked once on startup
+/// and reused whenever the config is rebuilt
+fn leak_auth_banner(config: &WarpgateConfig) -> Option<&'static str> {
+    config.store.ssh.auth_banner.as_ref().map(|banner| {
+        let mut banner = banner
+            .trim_end()
+            .replace("\r\n", "\n")
+            .replace('\n', "\r\n");
+        banner.push_str("\r\n");
+        &*Box::leak(banner.into_boxed_str())
+    })
+}
+
+fn build_russh_config(
+    config: &WarpgateConfig,
+    auth_banner: Option<&'static str>,
+) -> Result<russh::server::Config> {
+    // russh 0.44 can't send hostkeys-00@openssh.com, so clients only learn about
+    // the next host keys from `warpgate host-keys` or the admin UI
+    Ok(russh::server::Config {
+        auth_banner,
+        auth_rejection_time: Duration::from_secs(1),
+        auth_rejection_time_initial: Some(Duration::from_secs(0)),
+        inactivity_timeout: Some(config.store.ssh.inactivity_timeout),
+        methods: MethodSet::PUBLICKEY | MethodSet::PASSWORD | MethodSet::KEYBOARD_INTERACTIVE,
+        keys: load_host_keys(config)?,
+        event_buffer_size: 100,
+        preferred: apply_algorithms(
+            Preferred {
                 key: Cow::Borrowed(&[
                     russh::keys::key::ED25519,
+                    russh::keys::key::ECDSA_SHA2_NISTP256,
                     russh::keys::key::RSA_SHA2_256,
                     russh::keys::key::RSA_SHA2_512,
                     russh::keys::key::SSH_RSA,
                 ]),
                 ..<_>::default()
             },
-            ..<_>::default()
-        }
-    };
+            &config.store.ssh.algorithms,
+        )?,
+        ..<_>::default()
+    })
+}
 
-    let russh_config = Arc::new(russh_config);
+pub async fn run_server(services: Services, address: SocketAddr) -> Result<()> {
+    let (auth_banner, mut russh_config) = {
+        let config = services.config.lock().await;
+        let auth_banner = leak_auth_banner(&config);
+        (
+            auth_banner,
+            Arc::new(build_russh_config(&config, auth_banner)?),
+        )
+    };
+    let mut server_kex_init = KexInit::for_server(&russh_config.preferred, &russh_config.keys);
 
     let socket = TcpListener::bind(&address).await?;
     info!(?address, "Listening");
-    while let Ok((socket, remote_address)) = socket.accept().await {
+    let mut host_key_retirement_check = tokio::time::interval(HOST_KEY_RETIREMENT_CHECK_INTERVAL);
+    loop {
+        let (socket, remote_address) = tokio::select! {
+            accepted = socket.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break,
+            },
+            _ = host_key_retirement_check.tick() => {
+                let config = services.config.lock().await;
+                let reloaded = retire_host_keys_if_due(&config)
+                    .and_then(|retired| retired.then(|| build_russh_config(&config, auth_banner)).transpose());
+                match reloaded {
+                    Ok(Some(new_config)) => {
+                        russh_config = Arc::new(new_config);
+                        server_kex_init =
+                            KexInit::for_server(&russh_config.preferred, &russh_config.keys);
+                    }
+                    Ok(None) => (),
+                    Err(error) => error!(?error, "Failed to replace the host keys"),
+                }
+                continue;
+            }
+        };
         let russh_config = russh_config.clone();
 
         let (session_handle, session_handle_rx) = SSHSessionHandle::new();
@@ -69,9 +133,23 @@ pub async fn run_server(services: Services, address: SocketAddr) -> Result<()> {
 
         let id = server_handle.lock().await.id();
 
+        let (socket, client_kex_init) = KexInitSniffer::new(socket);
+        tokio::spawn({
+            let server_kex_init = server_kex_init.clone();
+            async move {
+                if let Ok(client_kex_init) = client_kex_init.await {
+                    KexInit::negotiate(&client_kex_init, &server_kex_init).log("client");
+                }
+            }
+            .instrument(info_span!("SSH", session=%id, client_ip=%remote_address.ip()))
+        });
+
         let (event_tx, event_rx) = unbounded_channel();
 
-        let handler = ServerHandler { event_tx };
+        let handler = ServerHandler {
+            event_tx,
+            agent_channel: None,
+        };
 
         let session = match ServerSession::start(
             remote_address,
@@ -0,0 +1,141 @@
+//! Admins attached to a live terminal session. The most recent terminal
+//! output is kept so that an observer joining mid-session starts out with
+//! the current screen instead of a blank one.
+
+use std::collections::{HashMap, VecDeque};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use uuid::Uuid;
+use warpgate_core::SessionObserverMode;
+
+/// Enough to redraw a full screen, including a full-screen TUI app
+const HISTORY_SIZE: usize = 64 * 1024;
+
+pub struct AttachedObserver {
+    pub username: String,
+    pub mode: SessionObserverMode,
+    output: Sender<Bytes>,
+}
+
+pub struct Observers {
+    observers: HashMap<Uuid, AttachedObserver>,
+    history: VecDeque<u8>,
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self {
+            observers: HashMap::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Records terminal output and sends it to every attached observer.
+    /// Returns the observers that have fallen too far behind to take it.
+    pub fn output(&mut self, data: &[u8]) -> Vec<Uuid> {
+        self.history.extend(data);
+        let excess = self.history.len().saturating_sub(HISTORY_SIZE);
+        self.history.drain(..excess);
+
+        let data = Bytes::copy_from_slice(data);
+        self.observers
+            .iter()
+            .filter(|(_, observer)| {
+                matches!(
+                    observer.output.try_send(data.clone()),
+                    Err(TrySendError::Full(_))
+                )
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Replays the recent output to the new observer before any live output
+    pub fn attach(
+        &mut self,
+        username: String,
+        mode: SessionObserverMode,
+        output: Sender<Bytes>,
+    ) -> Uuid {
+        if !self.history.is_empty() {
+            let _ = output.try_send(self.history.iter().copied().collect());
+        }
+        let id = Uuid::new_v4();
+        self.observers.insert(
+            id,
+            AttachedObserver {
+                username,
+                mode,
+                output,
+            },
+        );
+        id
+    }
+
+    pub fn detach(&mut self, id: Uuid) -> Option<AttachedObserver> {
+        self.observers.remove(&id)
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<&AttachedObserver> {
+        self.observers.get(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+
+    #[test]
+    fn replays_recent_output_on_attach() {
+        let mut observers = Observers::new();
+        observers.output(b"$ top\r\n");
+        observers.output(&[b'x'; HISTORY_SIZE]);
+
+        let (tx, mut rx) = channel(16);
+        let id = observers.attach("admin".into(), SessionObserverMode::Shadow, tx);
+        let replay = rx.try_recv().unwrap_or_default();
+        assert_eq!(replay.len(), HISTORY_SIZE);
+        assert!(replay.iter().all(|b| *b == b'x'));
+
+        assert!(observers.output(b"live").is_empty());
+        assert_eq!(rx.try_recv().ok(), Some(Bytes::from_static(b"live")));
+        assert_eq!(
+            observers.get(id).map(|o| o.mode),
+            Some(SessionObserverMode::Shadow)
+        );
+
+        let detached = observers.detach(id);
+        assert_eq!(detached.map(|o| o.username), Some("admin".to_owned()));
+        assert!(observers.get(id).is_none());
+        observers.output(b"after");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn attaching_to_a_fresh_session_sends_nothing() {
+        let mut observers = Observers::new();
+        let (tx, mut rx) = channel(16);
+        observers.attach("admin".into(), SessionObserverMode::Assist, tx);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn reports_observers_that_fall_behind() {
+        let mut observers = Observers::new();
+        let (tx, mut rx) = channel(2);
+        let id = observers.attach("admin".into(), SessionObserverMode::Shadow, tx);
+
+        assert!(observers.output(b"1").is_empty());
+        assert!(observers.output(b"2").is_empty());
+        assert_eq!(observers.output(b"3"), vec![id]);
+
+        let _ = rx.try_recv();
+        assert!(observers.output(b"4").is_empty());
+        drop(rx);
+        assert!(observers.output(b"5").is_empty());
+    }
+}
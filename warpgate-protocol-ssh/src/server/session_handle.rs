@@ -1,9 +1,10 @@
 use tokio::sync::mpsc;
-use warpgate_core::SessionHandle;
+use warpgate_core::{SessionHandle, SessionObserver};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub enum SessionHandleCommand {
     Close,
+    Attach(SessionObserver),
 }
 
 pub struct SSHSessionHandle {
@@ -21,4 +22,10 @@ impl SessionHandle for SSHSessionHandle {
     fn close(&mut self) {
         let _ = self.sender.send(SessionHandleCommand::Close);
     }
+
+    fn attach_observer(&mut self, observer: SessionObserver) -> bool {
+        self.sender
+            .send(SessionHandleCommand::Attach(observer))
+            .is_ok()
+    }
 }
@@ -0,0 +1,171 @@
+//! Interactive target menu shown over the PTY when the SSH username
+//! doesn't include a target name.
+
+use ansi_term::Colour;
+
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+/// Lines taken up by everything but the target list
+const HEADER_LINES: usize = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TargetPickerAction {
+    None,
+    Redraw,
+    Select(String),
+    Cancel,
+}
+
+pub struct TargetPicker {
+    targets: Vec<String>,
+    filter: String,
+    selected: usize,
+}
+
+impl TargetPicker {
+    pub fn new(mut targets: Vec<String>) -> Self {
+        targets.sort();
+        Self {
+            targets,
+            filter: String::new(),
+            selected: 0,
+        }
+    }
+
+    fn filtered(&self) -> Vec<&String> {
+        let filter = self.filter.to_lowercase();
+        self.targets
+            .iter()
+            .filter(|t| t.to_lowercase().contains(&filter))
+            .collect()
+    }
+
+    pub fn handle_input(&mut self, data: &[u8]) -> TargetPickerAction {
+        let mut action = TargetPickerAction::None;
+        let mut input = data;
+        while !input.is_empty() {
+            let consumed = match input {
+                [0x1b, b'[' | b'O', b'A', ..] => {
+                    self.selected = self.selected.saturating_sub(1);
+                    3
+                }
+                [0x1b, b'[' | b'O', b'B', ..] => {
+                    self.selected += 1;
+                    3
+                }
+                // Ctrl-C, Ctrl-D
+                [0x03 | 0x04, ..] => return TargetPickerAction::Cancel,
+                [b'\r' | b'\n', ..] => {
+                    return match self.filtered().get(self.selected) {
+                        Some(target) => TargetPickerAction::Select((*target).clone()),
+                        None => TargetPickerAction::Redraw,
+                    };
+                }
+                // Backspace
+                [0x7f | 0x08, ..] => {
+                    self.filter.pop();
+                    1
+                }
+                // Ctrl-U
+                [0x15, ..] => {
+                    self.filter.clear();
+                    1
+                }
+                // Unknown escape sequences
+                [0x1b, ..] => input.len(),
+                [c, ..] if c.is_ascii() => {
+                    if !c.is_ascii_control() {
+                        self.filter.push(*c as char);
+                        self.selected = 0;
+                    }
+                    1
+                }
+                _ => {
+                    let end = input
+                        .iter()
+                        .position(|b| b.is_ascii())
+                        .unwrap_or(input.len());
+                    let (text, _) = input.split_at(end);
+                    self.filter.push_str(&String::from_utf8_lossy(text));
+                    self.selected = 0;
+                    end
+                }
+            };
+            input = input.get(consumed..).unwrap_or_default();
+            action = TargetPickerAction::Redraw;
+        }
+
+        let count = self.filtered().len();
+        self.selected = self.selected.min(count.saturating_sub(1));
+        action
+    }
+
+    /// Renders the whole menu, fitting the target list into `height` terminal rows
+    pub fn render(&self, height: usize) -> String {
+        let mut output = format!(
+            "{CLEAR_SCREEN}{} Select a target: type to filter, up/down to move, Enter to connect, Ctrl-C to quit\r\n\r\n> {}\r\n",
+            Colour::Black.on(Colour::White).paint(" Warpgate "),
+            self.filter,
+        );
+
+        let filtered = self.filtered();
+        if filtered.is_empty() {
+            output.push_str("  (no matching targets)");
+            return output;
+        }
+
+        let rows = height.saturating_sub(HEADER_LINES).max(1);
+        let offset = (self.selected + 1).saturating_sub(rows);
+        let lines = filtered
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(rows)
+            .map(|(index, target)| {
+                if index == self.selected {
+                    format!(
+                        "{}",
+                        Colour::Black.on(Colour::Cyan).paint(format!("> {target}"))
+                    )
+                } else {
+                    format!("  {target}")
+                }
+            })
+            .collect::<Vec<_>>();
+        output.push_str(&lines.join("\r\n"));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_and_selects_targets() {
+        let mut picker = TargetPicker::new(vec![
+            "web-2".to_owned(),
+            "db".to_owned(),
+            "web-1".to_owned(),
+        ]);
+
+        assert_eq!(picker.handle_input(b"\x1b[B"), TargetPickerAction::Redraw);
+        assert_eq!(
+            picker.handle_input(b"\r"),
+            TargetPickerAction::Select("web-1".to_owned())
+        );
+
+        assert_eq!(picker.handle_input(b"WEB"), TargetPickerAction::Redraw);
+        picker.handle_input(b"\x1b[B\x1b[B\x1b[B");
+        assert_eq!(
+            picker.handle_input(b"\r"),
+            TargetPickerAction::Select("web-2".to_owned())
+        );
+
+        picker.handle_input(b"x");
+        assert!(picker.render(24).contains("no matching targets"));
+        assert_eq!(picker.handle_input(b"\r"), TargetPickerAction::Redraw);
+        picker.handle_input(&[0x15]);
+        assert!(picker.render(24).contains("db"));
+        assert_eq!(picker.handle_input(&[0x03]), TargetPickerAction::Cancel);
+    }
+}
@@ -0,0 +1,231 @@
+//! Connections that Warpgate opens itself on behalf of the client, e.g. for
+//! `ssh -J` where no target is selected and there is no target SSH
+//! connection to tunnel through, or for forwards that another protocol
+//! server handles in-process. Data coming back is reported as regular
+//! [RCEvent]s so that it takes the same path as target channel output.
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinHandle;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{Target, TargetOptions};
+
+use crate::RCEvent;
+
+enum TunnelInput {
+    Data(Bytes),
+    Eof,
+}
+
+pub struct Tunnel {
+    input: UnboundedSender<TunnelInput>,
+    reader: JoinHandle<()>,
+}
+
+impl Tunnel {
+    pub async fn connect(
+        channel: Uuid,
+        host: &str,
+        port: u16,
+        events: UnboundedSender<RCEvent>,
+    ) -> std::io::Result<Self> {
+        let stream = TcpStream::connect((host, port)).await?;
+        Ok(Self::new(channel, stream, events))
+    }
+
+    pub fn new<S>(channel: Uuid, stream: S, events: UnboundedSender<RCEvent>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (input, mut input_rx) = unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Some(input) = input_rx.recv().await {
+                match input {
+                    TunnelInput::Data(data) => {
+                        if let Err(error) = writer.write_all(&data).await {
+                            debug!(%channel, %error, "Tunnel write failed");
+                            break;
+                        }
+                    }
+                    TunnelInput::Eof => {
+                        let _ = writer.shutdown().await;
+                    }
+                }
+            }
+        });
+
+        let reader = tokio::spawn(async move {
+            let mut buf = vec![0; 65536];
+            loop {
+                match reader.read(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let Some(data) = buf.get(..n) else {
+                            break;
+                        };
+                        let data = Bytes::copy_from_slice(data);
+                        if events.send(RCEvent::Output(channel, data)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(error) => {
+                        debug!(%channel, %error, "Tunnel read failed");
+                        break;
+                    }
+                }
+            }
+            let _ = events.send(RCEvent::Eof(channel));
+            let _ = events.send(RCEvent::Close(channel));
+        });
+
+        Self { input, reader }
+    }
+
+    pub fn data(&self, data: Bytes) {
+        let _ = self.input.send(TunnelInput::Data(data));
+    }
+
+    pub fn eof(&self) {
+        let _ = self.input.send(TunnelInput::Eof);
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Whether a `direct-tcpip` destination refers to an SSH target, either by
+/// its Warpgate name or by its configured address
+pub fn is_jump_destination(target: &Target, host: &str, port: u32) -> bool {
+    let TargetOptions::Ssh(ref options) = target.options else {
+        return false;
+    };
+    address_matches(&target.name, &options.host, options.port, host, port)
+}
+
+/// How the session authenticated. A ticket only grants access to the target
+/// it was issued for, which also applies to targets reached through
+/// forwarded channels and jump connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionAuthorization {
+    User,
+    Ticket { target_name: String },
+}
+
+impl SessionAuthorization {
+    pub fn allows_target(&self, target_name: &str) -> bool {
+        match self {
+            SessionAuthorization::User => true,
+            SessionAuthorization::Ticket {
+                target_name: ticket_target,
+            } => ticket_target == target_name,
+        }
+    }
+}
+
+/// The HTTP or MySQL target that a forwarding destination names, if the
+/// session may reach it
+pub fn forwarded_target<'a>(
+    targets: &'a [Target],
+    host: &str,
+    authorization: &SessionAuthorization,
+) -> Option<&'a Target> {
+    targets
+        .iter()
+        .filter(|t| matches!(t.options, TargetOptions::Http(_) | TargetOptions::MySql(_)))
+        .find(|t| t.name == host && authorization.allows_target(&t.name))
+}
+
+/// There is no terminal to prompt on for forwarded and jump connections, so
+/// the justification must have been given before the channel is opened
+pub fn justification_missing(target: &Target, justification: Option<&String>) -> bool {
+    target.require_justification && justification.is_none()
+}
+
+fn address_matches(name: &str, target_host: &str, target_port: u16, host: &str, port: u32) -> bool {
+    name == host || (target_host.eq_ignore_ascii_case(host) && u32::from(target_port) == port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    fn target(name: &str, kind: &str, options: serde_json::Value) -> Target {
+        serde_json::from_value(serde_json::json!({ "name": name, kind: options })).unwrap()
+    }
+
+    fn targets() -> Vec<Target> {
+        vec![
+            target(
+                "ssh",
+                "ssh",
+                serde_json::json!({ "host": "10.0.0.5", "username": "root" }),
+            ),
+            target("grafana", "http", serde_json::json!({})),
+            target("kibana", "http", serde_json::json!({})),
+        ]
+    }
+
+    #[test]
+    fn matches_jump_destinations() {
+        assert!(address_matches("db-host", "10.0.0.5", 2222, "db-host", 22));
+        assert!(address_matches(
+            "db-host", "10.0.0.5", 2222, "10.0.0.5", 2222
+        ));
+        assert!(address_matches(
+            "db-host",
+            "Db.Example.com",
+            22,
+            "db.example.com",
+            22
+        ));
+        assert!(!address_matches(
+            "db-host", "10.0.0.5", 2222, "10.0.0.5", 22
+        ));
+        assert!(!address_matches(
+            "db-host",
+            "10.0.0.5",
+            2222,
+            "example.com",
+            2222
+        ));
+    }
+
+    #[test]
+    fn ticket_sessions_only_forward_to_their_target() {
+        let targets = targets();
+        let ticket = SessionAuthorization::Ticket {
+            target_name: "grafana".into(),
+        };
+        let name = |host| forwarded_target(&targets, host, &ticket).map(|t| t.name.as_str());
+        assert_eq!(name("grafana"), Some("grafana"));
+        assert_eq!(name("kibana"), None);
+
+        let user = SessionAuthorization::User;
+        let name = |host| forwarded_target(&targets, host, &user).map(|t| t.name.as_str());
+        assert_eq!(name("kibana"), Some("kibana"));
+        // SSH targets are reached through jump connections instead
+        assert_eq!(name("ssh"), None);
+        assert_eq!(name("example.com"), None);
+    }
+
+    #[test]
+    fn forwarding_requires_an_upfront_justification() {
+        let mut target = target("grafana", "http", serde_json::json!({}));
+        assert!(!justification_missing(&target, None));
+        target.require_justification = true;
+        assert!(justification_missing(&target, None));
+        assert!(!justification_missing(
+            &target,
+            Some(&"incident 42".to_string())
+        ));
+    }
+}
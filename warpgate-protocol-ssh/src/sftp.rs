@@ -0,0 +1,436 @@
+//! Passive SFTP (v3) stream decoder used for auditing file operations.
+//! It never modifies the traffic - it only observes both directions of
+//! the subsystem channel and correlates requests with their responses.
+
+use std::collections::HashMap;
+
+use bytes::{Buf, Bytes, BytesMut};
+use tracing::*;
+use warpgate_core::recordings::{FileTransferEvent, FileTransferOperation};
+
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_REMOVE: u8 = 13;
+const SSH_FXP_MKDIR: u8 = 14;
+const SSH_FXP_RMDIR: u8 = 15;
+const SSH_FXP_RENAME: u8 = 18;
+const SSH_FXP_SYMLINK: u8 = 20;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+
+const SSH_FX_OK: u32 = 0;
+
+/// Anything larger is not a sane SFTP packet and means we lost sync
+const MAX_PACKET_SIZE: usize = 4 * 1024 * 1024;
+
+/// Splits a byte stream into SFTP packets (type byte + payload)
+#[derive(Default)]
+struct PacketStream {
+    buffer: BytesMut,
+}
+
+impl PacketStream {
+    fn feed(&mut self, data: &[u8]) -> Result<Vec<Bytes>, SftpParseError> {
+        self.buffer.extend_from_slice(data);
+        let mut packets = vec![];
+        loop {
+            if self.buffer.len() < 4 {
+                break;
+            }
+            #[allow(clippy::indexing_slicing)] // length checked
+            let length = u32::from_be_bytes([
+                self.buffer[0],
+                self.buffer[1],
+                self.buffer[2],
+                self.buffer[3],
+            ]) as usize;
+            if length == 0 || length > MAX_PACKET_SIZE {
+                return Err(SftpParseError::InvalidLength(length));
+            }
+            if self.buffer.len() < 4 + length {
+                break;
+            }
+            self.buffer.advance(4);
+            packets.push(self.buffer.split_to(length).freeze());
+        }
+        Ok(packets)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SftpParseError {
+    #[error("invalid packet length: {0}")]
+    InvalidLength(usize),
+    #[error("unexpected end of packet")]
+    Truncated,
+}
+
+trait SftpBufExt {
+    fn read_u32(&mut self) -> Result<u32, SftpParseError>;
+    fn read_u64(&mut self) -> Result<u64, SftpParseError>;
+    fn read_string(&mut self) -> Result<Bytes, SftpParseError>;
+    fn read_utf8(&mut self) -> Result<String, SftpParseError> {
+        Ok(String::from_utf8_lossy(&self.read_string()?).into_owned())
+    }
+}
+
+impl SftpBufExt for Bytes {
+    fn read_u32(&mut self) -> Result<u32, SftpParseError> {
+        if self.remaining() < 4 {
+            return Err(SftpParseError::Truncated);
+        }
+        Ok(self.get_u32())
+    }
+
+    fn read_u64(&mut self) -> Result<u64, SftpParseError> {
+        if self.remaining() < 8 {
+            return Err(SftpParseError::Truncated);
+        }
+        Ok(self.get_u64())
+    }
+
+    fn read_string(&mut self) -> Result<Bytes, SftpParseError> {
+        let length = self.read_u32()? as usize;
+        if self.remaining() < length {
+            return Err(SftpParseError::Truncated);
+        }
+        Ok(self.split_to(length))
+    }
+}
+
+enum PendingRequest {
+    Open(String),
+    Read(Bytes),
+    Write(Bytes, u64),
+    Close(Bytes),
+    Simple(FileTransferOperation, String, Option<String>),
+}
+
+struct OpenFile {
+    path: String,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+/// Tracks a single SFTP session and produces [FileTransferEvent]s
+/// for completed operations.
+#[derive(Default)]
+pub struct SftpTracker {
+    client_stream: PacketStream,
+    server_stream: PacketStream,
+    pending: HashMap<u32, PendingRequest>,
+    handles: HashMap<Bytes, OpenFile>,
+    desynced: bool,
+}
+
+impl SftpTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds data sent by the client (requests)
+    pub fn client_data(&mut self, data: &[u8]) {
+        if self.desynced {
+            return;
+        }
+        match self.client_stream.feed(data) {
+            Ok(packets) => {
+                for packet in packets {
+                    if let Err(error) = self.handle_request(packet) {
+                        debug!(?error, "Failed to parse an SFTP request");
+                    }
+                }
+            }
+            Err(error) => self.desync(error),
+        }
+    }
+
+    /// Feeds data sent by the server (responses)
+    pub fn server_data(&mut self, data: &[u8]) -> Vec<FileTransferEvent> {
+        if self.desynced {
+            return vec![];
+        }
+        let mut events = vec![];
+        match self.server_stream.feed(data) {
+            Ok(packets) => {
+                for packet in packets {
+                    match self.handle_response(packet) {
+                        Ok(event) => events.extend(event),
+                        Err(error) => debug!(?error, "Failed to parse an SFTP response"),
+                    }
+                }
+            }
+            Err(error) => self.desync(error),
+        }
+        events
+    }
+
+    /// Reports transfers on handles that were never closed
+    pub fn finish(&mut self) -> Vec<FileTransferEvent> {
+        self.pending.clear();
+        self.handles
+            .drain()
+            .flat_map(|(_, file)| Self::transfer_events(file, false))
+            .collect()
+    }
+
+    fn desync(&mut self, error: SftpParseError) {
+        warn!(%error, "Lost track of the SFTP stream, file operations will not be audited");
+        self.desynced = true;
+    }
+
+    fn handle_request(&mut self, mut packet: Bytes) -> Result<(), SftpParseError> {
+        if packet.is_empty() {
+            return Err(SftpParseError::Truncated);
+        }
+        let kind = packet.get_u8();
+        let request = match kind {
+            SSH_FXP_OPEN | SSH_FXP_OPENDIR => {
+                let id = packet.read_u32()?;
+                (id, PendingRequest::Open(packet.read_utf8()?))
+            }
+            SSH_FXP_CLOSE => {
+                let id = packet.read_u32()?;
+                (id, PendingRequest::Close(packet.read_string()?))
+            }
+            SSH_FXP_READ => {
+                let id = packet.read_u32()?;
+                (id, PendingRequest::Read(packet.read_string()?))
+            }
+            SSH_FXP_WRITE => {
+                let id = packet.read_u32()?;
+                let handle = packet.read_string()?;
+                let _offset = packet.read_u64()?;
+                let data = packet.read_string()?;
+                (id, PendingRequest::Write(handle, data.len() as u64))
+            }
+            SSH_FXP_REMOVE | SSH_FXP_MKDIR | SSH_FXP_RMDIR => {
+                let id = packet.read_u32()?;
+                let operation = match kind {
+                    SSH_FXP_REMOVE => FileTransferOperation::Remove,
+                    SSH_FXP_MKDIR => FileTransferOperation::Mkdir,
+                    _ => FileTransferOperation::Rmdir,
+                };
+                (
+                    id,
+                    PendingRequest::Simple(operation, packet.read_utf8()?, None),
+                )
+            }
+            SSH_FXP_RENAME | SSH_FXP_SYMLINK => {
+                let id = packet.read_u32()?;
+                let operation = match kind {
+                    SSH_FXP_RENAME => FileTransferOperation::Rename,
+                    _ => FileTransferOperation::Symlink,
+                };
+                let path = packet.read_utf8()?;
+                let target_path = packet.read_utf8()?;
+                (
+                    id,
+                    PendingRequest::Simple(operation, path, Some(target_path)),
+                )
+            }
+            _ => return Ok(()),
+        };
+        self.pending.insert(request.0, request.1);
+        Ok(())
+    }
+
+    fn handle_response(
+        &mut self,
+        mut packet: Bytes,
+    ) -> Result<Vec<FileTransferEvent>, SftpParseError> {
+        if packet.is_empty() {
+            return Err(SftpParseError::Truncated);
+        }
+        let kind = packet.get_u8();
+        if !matches!(kind, SSH_FXP_STATUS | SSH_FXP_HANDLE | SSH_FXP_DATA) {
+            return Ok(vec![]);
+        }
+        let id = packet.read_u32()?;
+        let Some(request) = self.pending.remove(&id) else {
+            return Ok(vec![]);
+        };
+
+        let status_ok = if kind == SSH_FXP_STATUS {
+            Some(packet.read_u32()? == SSH_FX_OK)
+        } else {
+            None
+        };
+
+        Ok(match (request, kind) {
+            (PendingRequest::Open(path), SSH_FXP_HANDLE) => {
+                let handle = packet.read_string()?;
+                self.handles.insert(
+                    handle,
+                    OpenFile {
+                        path: path.clone(),
+                        bytes_read: 0,
+                        bytes_written: 0,
+                    },
+                );
+                vec![FileTransferEvent {
+                    operation: FileTransferOperation::Open,
+                    path,
+                    target_path: None,
+                    bytes: None,
+                    mode: None,
+                    success: true,
+                }]
+            }
+            (PendingRequest::Open(path), _) => vec![FileTransferEvent {
+                operation: FileTransferOperation::Open,
+                path,
+                target_path: None,
+                bytes: None,
+                mode: None,
+                success: false,
+            }],
+            (PendingRequest::Read(handle), SSH_FXP_DATA) => {
+                let data = packet.read_string()?;
+                if let Some(file) = self.handles.get_mut(&handle) {
+                    file.bytes_read += data.len() as u64;
+                }
+                vec![]
+            }
+            (PendingRequest::Write(handle, length), _) => {
+                if status_ok == Some(true) {
+                    if let Some(file) = self.handles.get_mut(&handle) {
+                        file.bytes_written += length;
+                    }
+                }
+                vec![]
+            }
+            (PendingRequest::Close(handle), _) => match self.handles.remove(&handle) {
+                Some(file) => Self::transfer_events(file, true),
+                None => vec![],
+            },
+            (PendingRequest::Simple(operation, path, target_path), _) => {
+                vec![FileTransferEvent {
+                    operation,
+                    path,
+                    target_path,
+                    bytes: None,
+                    mode: None,
+                    success: status_ok == Some(true),
+                }]
+            }
+            (PendingRequest::Read(_), _) => vec![],
+        })
+    }
+
+    fn transfer_events(file: OpenFile, closed: bool) -> Vec<FileTransferEvent> {
+        let mut events = vec![];
+        if file.bytes_read > 0 {
+            events.push(FileTransferEvent {
+                operation: FileTransferOperation::Read,
+                path: file.path.clone(),
+                target_path: None,
+                bytes: Some(file.bytes_read),
+                mode: None,
+                success: true,
+            });
+        }
+        if file.bytes_written > 0 {
+            events.push(FileTransferEvent {
+                operation: FileTransferOperation::Write,
+                path: file.path.clone(),
+                target_path: None,
+                bytes: Some(file.bytes_written),
+                mode: None,
+                success: true,
+            });
+        }
+        events.push(FileTransferEvent {
+            operation: FileTransferOperation::Close,
+            path: file.path,
+            target_path: None,
+            bytes: None,
+            mode: None,
+            success: closed,
+        });
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+
+    use super::*;
+
+    fn packet(kind: u8, id: u32, f: impl FnOnce(&mut BytesMut)) -> Vec<u8> {
+        let mut body = BytesMut::new();
+        body.put_u8(kind);
+        body.put_u32(id);
+        f(&mut body);
+        let mut out = BytesMut::new();
+        out.put_u32(body.len() as u32);
+        out.extend_from_slice(&body);
+        out.to_vec()
+    }
+
+    fn put_string(buf: &mut BytesMut, s: &[u8]) {
+        buf.put_u32(s.len() as u32);
+        buf.extend_from_slice(s);
+    }
+
+    fn status(id: u32, code: u32) -> Vec<u8> {
+        packet(SSH_FXP_STATUS, id, |b| {
+            b.put_u32(code);
+            put_string(b, b"");
+            put_string(b, b"");
+        })
+    }
+
+    #[test]
+    fn tracks_upload() {
+        let mut tracker = SftpTracker::new();
+
+        let open = packet(SSH_FXP_OPEN, 1, |b| {
+            put_string(b, b"/tmp/file");
+            b.put_u32(0x1a);
+            b.put_u32(0);
+        });
+        // deliver in fragments
+        let (a, b) = open.split_at(5);
+        tracker.client_data(a);
+        tracker.client_data(b);
+
+        let events = tracker.server_data(&packet(SSH_FXP_HANDLE, 1, |b| put_string(b, b"h1")));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, FileTransferOperation::Open);
+        assert_eq!(events[0].path, "/tmp/file");
+
+        tracker.client_data(&packet(SSH_FXP_WRITE, 2, |b| {
+            put_string(b, b"h1");
+            b.put_u64(0);
+            put_string(b, &[0; 1000]);
+        }));
+        assert!(tracker.server_data(&status(2, SSH_FX_OK)).is_empty());
+
+        tracker.client_data(&packet(SSH_FXP_CLOSE, 3, |b| put_string(b, b"h1")));
+        let events = tracker.server_data(&status(3, SSH_FX_OK));
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].operation, FileTransferOperation::Write);
+        assert_eq!(events[0].bytes, Some(1000));
+        assert_eq!(events[1].operation, FileTransferOperation::Close);
+    }
+
+    #[test]
+    fn tracks_failed_rename() {
+        let mut tracker = SftpTracker::new();
+        tracker.client_data(&packet(SSH_FXP_RENAME, 7, |b| {
+            put_string(b, b"/a");
+            put_string(b, b"/b");
+        }));
+        let events = tracker.server_data(&status(7, 3));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, FileTransferOperation::Rename);
+        assert_eq!(events[0].target_path.as_deref(), Some("/b"));
+        assert!(!events[0].success);
+    }
+}
@@ -12,5 +12,13 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     for key in keys {
         println!("{}", key.as_openssh());
     }
+
+    if let Ok(ca_key) = warpgate_protocol_ssh::load_ca_key(&config) {
+        println!();
+        println!("Warpgate SSH certificate authority key:");
+        println!("(add this to your target's TrustedUserCAKeys file instead to only allow per-session certificates)");
+        println!();
+        println!("{}", ca_key.public_key().to_openssh()?);
+    }
     Ok(())
 }
@@ -0,0 +1,35 @@
+use anyhow::Result;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use warpgate_protocol_ssh::helpers::PublicKeyAsOpenSSH;
+use warpgate_protocol_ssh::HostKeyState;
+
+use crate::config::load_config;
+
+pub(crate) async fn command(cli: &crate::Cli, rotate: bool) -> Result<()> {
+    let config = load_config(&cli.config, true)?;
+    if rotate {
+        warpgate_protocol_ssh::rotate_host_keys(&config)?;
+    }
+
+    let keys = warpgate_protocol_ssh::list_host_keys(&config)?;
+    println!("Warpgate SSH host keys:");
+    println!();
+    for (_, key) in keys.iter().filter(|(s, _)| *s == HostKeyState::Active) {
+        println!("{}", key.as_openssh());
+    }
+
+    if let Some(retire_at) = warpgate_protocol_ssh::host_key_retirement_time(&config)? {
+        println!();
+        println!(
+            "Next host keys, replacing the ones above at {}:",
+            OffsetDateTime::from(retire_at).format(&Rfc3339)?
+        );
+        println!("(add these to your clients' known_hosts files before then)");
+        println!();
+        for (_, key) in keys.iter().filter(|(s, _)| *s == HostKeyState::Next) {
+            println!("{}", key.as_openssh());
+        }
+    }
+    Ok(())
+}
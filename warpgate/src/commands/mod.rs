@@ -1,6 +1,7 @@
 pub mod check;
 pub mod client_keys;
 mod common;
+pub mod host_keys;
 pub mod recover_access;
 pub mod run;
 pub mod setup;
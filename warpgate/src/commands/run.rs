@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::Result;
 use futures::StreamExt;
@@ -9,8 +10,9 @@ use tracing::*;
 use warpgate_core::db::cleanup_db;
 use warpgate_core::logging::install_database_logger;
 use warpgate_core::{ProtocolServer, Services};
-use warpgate_protocol_http::HTTPProtocolServer;
-use warpgate_protocol_mysql::MySQLProtocolServer;
+use warpgate_db_entities::Target::TargetKind;
+use warpgate_protocol_http::{HTTPForwardedConnectionHandler, HTTPProtocolServer};
+use warpgate_protocol_mysql::{MySQLForwardedConnectionHandler, MySQLProtocolServer};
 use warpgate_protocol_ssh::SSHProtocolServer;
 
 use crate::config::{load_config, watch_config};
@@ -35,6 +37,12 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     }
 
     if config.store.http.enable {
+        services
+            .register_forwarded_connection_handler(
+                TargetKind::Http,
+                Arc::new(HTTPForwardedConnectionHandler::new(&services)),
+            )
+            .await;
         protocol_futures.push(
             HTTPProtocolServer::new(&services)
                 .await?
@@ -43,6 +51,12 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     }
 
     if config.store.mysql.enable {
+        services
+            .register_forwarded_connection_handler(
+                TargetKind::MySql,
+                Arc::new(MySQLForwardedConnectionHandler::new(&services)),
+            )
+            .await;
         protocol_futures.push(
             MySQLProtocolServer::new(&services)
                 .await?
@@ -270,6 +270,7 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     let services = Services::new(config.clone()).await?;
     warpgate_protocol_ssh::generate_host_keys(&config)?;
     warpgate_protocol_ssh::generate_client_keys(&config)?;
+    warpgate_protocol_ssh::generate_ca_key(&config)?;
 
     {
         let db = services.db.lock().await;
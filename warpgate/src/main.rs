@@ -65,6 +65,12 @@ pub(crate) enum Commands {
     },
     /// Show Warpgate's SSH client keys
     ClientKeys,
+    /// Show Warpgate's SSH host keys
+    HostKeys {
+        /// Generate the next host keys and schedule the current ones for retirement
+        #[clap(long)]
+        rotate: bool,
+    },
     /// Run Warpgate
     Run,
     /// Create a password hash for use in the config file
@@ -96,6 +102,7 @@ async fn _main() -> Result<()> {
             crate::commands::setup::command(&cli).await
         }
         Commands::ClientKeys => crate::commands::client_keys::command(&cli).await,
+        Commands::HostKeys { rotate } => crate::commands::host_keys::command(&cli, *rotate).await,
         Commands::RecoverAccess { username } => {
             crate::commands::recover_access::command(&cli, username).await
         }